@@ -0,0 +1,148 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rmonitor::protocol::Record;
+use rmonitor::{RMonitorDecoder, RMonitorReader};
+use std::hint::black_box;
+use tokio_util::bytes::{BufMut, BytesMut};
+use tokio_util::codec::Decoder;
+
+const SAMPLE: &[u8] = include_bytes!("../sample/2009_Sebring_ALMS_Session_5.txt");
+
+/// One representative line for each record type
+const RECORDS: &[(&str, &str)] = &[
+    (
+        "heartbeat",
+        r#"$F,14,"00:12:45","13:34:23","00:09:47","Green ""#,
+    ),
+    (
+        "competitor",
+        r#"$A,"1234BE","12X",52474,"John","Johnson","USA",5"#,
+    ),
+    (
+        "competitor_ext",
+        r#"$COMP,"1234BE","12X",5,"John","Johnson","USA","CAMEL""#,
+    ),
+    ("run", r#"$B,5,"Friday free practice""#),
+    ("class", r#"$C,5,"Formula 3000""#),
+    ("setting", r#"$E,"TRACKNAME","Indianapolis Motor Speedway""#),
+    ("race", r#"$G,3,"1234BE",14,"01:12:47.872""#),
+    ("practice_qual", r#"$H,2,"1234BE",3,"00:02:17.872""#),
+    ("init", r#"$I,"16:36:08.000","12 jan 01""#),
+    ("passing", r#"$J,"1234BE","00:02:03.826","01:42:17.672""#),
+    (
+        "correction",
+        r#"$COR,"123BE","658",2,"00:00:35.272","+00:00:00.012""#,
+    ),
+    (
+        "line_crossing",
+        r#"$L,"13","P2","POP","01/27/2009","10:10:20.589",1,"PC""#,
+    ),
+    (
+        "track_description",
+        concat!(
+            r#"$T,"Circuit of the Americas","COTA","3.40",15,"#,
+            r#""S01","T1","T2",3375,"S02","T2","T3",36559,"S03","T3","T4",40933,"S04","T4","T5",13256,"S05","T5",""#,
+            r#"T6",20923,"S06","T6","T7",1181,"S07","T7","T8",12711,"S08","T8","T9",1181,"S09","T9","TA",29313,"S1"#,
+            r#"0","TA","TB",41744,"S11","TB","T1",16113,"LAP","T1","P1",217379,"PIT","PB","P2",19688,"SP4","T6","T"#,
+            r#"7",1181,"SP5","T8","T9",1181"#
+        ),
+    ),
+];
+
+fn record_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("record_decode");
+
+    for (name, line) in RECORDS {
+        group.throughput(Throughput::Bytes(line.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), line, |b, line| {
+            b.iter(|| Record::decode(black_box(line)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// Drain every record from `buffer`, returning the number decoded.
+fn drain(decoder: &mut RMonitorDecoder, buffer: &mut BytesMut) -> usize {
+    let mut count = 0;
+    while !buffer.is_empty() {
+        match decoder.decode(buffer).unwrap() {
+            Some(record) => {
+                black_box(record);
+                count += 1;
+            }
+            // Either a skipped line or an incomplete one, stop if nothing was consumed
+            None if !buffer.contains(&b'\n') => break,
+            None => {}
+        }
+    }
+    count
+}
+
+fn decoder_full_sample(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decoder");
+    group.throughput(Throughput::Bytes(SAMPLE.len() as u64));
+
+    group.bench_function("sebring_session_5", |b| {
+        b.iter(|| {
+            let mut decoder = RMonitorDecoder::new_with_max_length(2048);
+            let mut buffer = BytesMut::from(SAMPLE);
+            drain(&mut decoder, &mut buffer)
+        })
+    });
+
+    group.finish();
+}
+
+fn decoder_chunked(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decoder_chunked");
+    group.throughput(Throughput::Bytes(SAMPLE.len() as u64));
+
+    // Mimic the small `read()` calls made in `examples/sync.rs`
+    for chunk_size in [64usize, 256, 4096] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk_size,
+            |b, &chunk_size| {
+                b.iter(|| {
+                    let mut decoder = RMonitorDecoder::new_with_max_length(2048);
+                    let mut buffer = BytesMut::with_capacity(4096);
+                    let mut count = 0;
+                    for chunk in SAMPLE.chunks(chunk_size) {
+                        buffer.put(chunk);
+                        count += drain(&mut decoder, &mut buffer);
+                    }
+                    count
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn reader_full_sample(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reader");
+    group.throughput(Throughput::Bytes(SAMPLE.len() as u64));
+
+    group.bench_function("sebring_session_5", |b| {
+        b.iter(|| {
+            let mut count = 0;
+            for record in RMonitorReader::new_with_max_length(SAMPLE, 2048) {
+                black_box(record.unwrap());
+                count += 1;
+            }
+            count
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    record_decode,
+    decoder_full_sample,
+    decoder_chunked,
+    reader_full_sample
+);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+use rmonitor::http::{self, SharedSession};
+use rmonitor::{client, RMonitorDecoder};
+use std::error::Error;
+
+/// Serves the session state from the simulator (or another RMonitor source given as the first
+/// argument) on http://127.0.0.1:8080
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let source = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:50000".to_owned());
+    let session = SharedSession::default();
+
+    let records = client::connect(source, RMonitorDecoder::new_with_max_length(2048)).await?;
+    tokio::spawn(http::track(records, session.clone()));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+    println!("Serving session state on http://127.0.0.1:8080/session");
+    axum::serve(listener, http::router(session)).await?;
+
+    Ok(())
+}
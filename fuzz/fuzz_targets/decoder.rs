@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rmonitor::RMonitorDecoder;
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = RMonitorDecoder::new_with_max_length(2048);
+
+    // Feed the input in two halves so partial lines are exercised as well
+    let (head, tail) = data.split_at(data.len() / 2);
+    let mut bytes = BytesMut::from(head);
+    while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+
+    bytes.extend_from_slice(tail);
+    while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+    let _ = decoder.decode_eof(&mut bytes);
+});
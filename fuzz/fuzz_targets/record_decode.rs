@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rmonitor::protocol::Record;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = std::str::from_utf8(data) {
+        // Any input must be rejected with an error rather than a panic, and anything accepted
+        // must survive re-encoding.
+        if let Ok(record) = Record::decode(line) {
+            let encoded = record.encode();
+            let decoded = Record::decode(&encoded).expect("re-encoded record failed to decode");
+            assert_eq!(decoded, record, "record changed by re-encoding as {:?}", encoded);
+        }
+    }
+});
@@ -0,0 +1,724 @@
+//! Conversion of record streams to Apache Arrow record batches and Parquet files, for analysis in
+//! tools such as DuckDB and Polars.
+//!
+//! Records are split into one table per record type, named after [`Record::kind`], e.g.
+//! `passing` or `line_crossing`. Every table starts with a `sequence` column numbering the records
+//! in the order they were received, so tables can be joined back into a single stream. Fields are
+//! converted to typed columns:
+//!
+//! - lap, race and correction times become `Int64` milliseconds in columns with an `_ms` suffix,
+//!   e.g. `laptime_ms`, and are null for the `00:59:59.999` "no time" sentinel
+//! - the heartbeat time of day becomes a `Time32(Millisecond)`
+//! - `LineCrossing` dates and times are combined into a `Timestamp(Millisecond)` column named
+//!   `timestamp`, alongside the original fields
+//! - track sections become a list of structs
+//!
+//! A [`BatchBuilder`] buffers rows until a table's batch is full, so sessions of any length can be
+//! converted without holding them in memory. With the `parquet` feature, a [`ParquetWriter`]
+//! writes each table to its own file as batches fill.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[cfg(feature = "parquet")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use rmonitor::arrow::ParquetWriter;
+//! use rmonitor::RMonitorReader;
+//! use std::fs::File;
+//!
+//! let mut writer = ParquetWriter::create("sebring")?;
+//! for record in RMonitorReader::new(File::open("sample/2009_Sebring_ALMS_Session_5.txt")?) {
+//!     writer.write(&record?)?;
+//! }
+//! // Writes `sebring/passing.parquet`, `sebring/line_crossing.parquet`, ...
+//! writer.close()?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "parquet"))]
+//! # fn main() {}
+//! ```
+use arrow_array::builder::{
+    ArrayBuilder, Int64Builder, ListBuilder, StringBuilder, StructBuilder,
+    Time32MillisecondBuilder, TimestampMillisecondBuilder, UInt16Builder, UInt32Builder,
+    UInt64Builder, UInt8Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::protocol::*;
+use crate::time::{parse_millis, parse_signed_millis};
+
+#[cfg(feature = "parquet")]
+pub use self::parquet::ParquetWriter;
+
+/// Number of rows in each batch, by default
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// The name of every table, in the order of the [`Record`] variants
+pub const TABLES: &[&str] = &[
+    "heartbeat",
+    "competitor",
+    "competitor_ext",
+    "run",
+    "class",
+    "setting",
+    "race",
+    "practice_qual",
+    "init",
+    "passing",
+    "correction",
+    "line_crossing",
+    "track_description",
+];
+
+fn section_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("start", DataType::Utf8, false),
+        Field::new("end", DataType::Utf8, false),
+        Field::new("distance", DataType::UInt32, false),
+    ])
+}
+
+/// Returns the schema of a table, or `None` if there is no table with that name
+pub fn schema(table: &str) -> Option<SchemaRef> {
+    use DataType::*;
+
+    let utf8 = |name| Field::new(name, Utf8, false);
+    let millis = |name| Field::new(name, Int64, true);
+    let mut fields = vec![Field::new("sequence", UInt64, false)];
+    fields.extend(match table {
+        "heartbeat" => vec![
+            Field::new("laps_to_go", UInt32, false),
+            millis("time_to_go_ms"),
+            Field::new("time_of_day", Time32(TimeUnit::Millisecond), true),
+            millis("race_time_ms"),
+            utf8("flag_status"),
+        ],
+        "competitor" => vec![
+            utf8("registration_number"),
+            utf8("number"),
+            Field::new("transponder_number", UInt32, false),
+            utf8("first_name"),
+            utf8("last_name"),
+            utf8("nationality"),
+            Field::new("class_number", UInt8, false),
+        ],
+        "competitor_ext" => vec![
+            utf8("registration_number"),
+            utf8("number"),
+            Field::new("class_number", UInt8, false),
+            utf8("first_name"),
+            utf8("last_name"),
+            utf8("nationality"),
+            utf8("additional_data"),
+        ],
+        "run" | "class" => vec![Field::new("number", UInt8, false), utf8("description")],
+        "setting" => vec![utf8("description"), utf8("value")],
+        "race" => vec![
+            Field::new("position", UInt16, false),
+            utf8("registration_number"),
+            Field::new("laps", UInt32, true),
+            millis("total_time_ms"),
+        ],
+        "practice_qual" => vec![
+            Field::new("position", UInt16, false),
+            utf8("registration_number"),
+            Field::new("best_lap", UInt32, false),
+            millis("best_laptime_ms"),
+        ],
+        "init" => vec![utf8("time"), utf8("date")],
+        "passing" => vec![
+            utf8("registration_number"),
+            millis("laptime_ms"),
+            millis("total_time_ms"),
+        ],
+        "correction" => vec![
+            utf8("registration_number"),
+            utf8("number"),
+            Field::new("laps", UInt32, false),
+            millis("total_time_ms"),
+            millis("correction_ms"),
+        ],
+        "line_crossing" => vec![
+            utf8("number"),
+            utf8("timeline_number"),
+            utf8("timeline_name"),
+            utf8("date"),
+            utf8("time"),
+            Field::new("timestamp", Timestamp(TimeUnit::Millisecond, None), true),
+            Field::new("driver_id", UInt8, true),
+            Field::new("class_name", Utf8, true),
+        ],
+        "track_description" => vec![
+            utf8("name"),
+            utf8("short_name"),
+            utf8("distance"),
+            Field::new(
+                "sections",
+                List(Arc::new(Field::new_list_field(
+                    Struct(section_fields()),
+                    true,
+                ))),
+                false,
+            ),
+        ],
+        _ => return None,
+    });
+    Some(Arc::new(Schema::new(fields)))
+}
+
+/// Parses a time of day as milliseconds since midnight
+fn time_of_day(s: &str) -> Option<i32> {
+    parse_millis(s)
+        .filter(|&ms| ms < 86_400_000)
+        .map(|ms| ms as i32)
+}
+
+/// Returns the number of days between 1970-01-01 and the given date, in the proleptic Gregorian
+/// calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parses a `LineCrossing` date and time as milliseconds since the Unix epoch.
+///
+/// Dates are sent as `MM/DD/YYYY`, `YYYY-MM-DD` is also accepted.
+fn timestamp(date: &str, time: &str) -> Option<i64> {
+    let date = date.trim();
+    let parts: Vec<&str> = date.split(['/', '-']).collect();
+    let (year, month, day) = match parts[..] {
+        [month, day, year] if date.contains('/') => (year, month, day),
+        [year, month, day] if date.contains('-') => (year, month, day),
+        _ => return None,
+    };
+    let (year, month, day): (i64, i64, i64) =
+        (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let time = time_of_day(time)?;
+    Some(days_from_civil(year, month, day) * 86_400_000 + i64::from(time))
+}
+
+/// A single field value, converted to the type of its column when appended
+enum Value<'a> {
+    Str(Option<&'a str>),
+    Int(Option<i64>),
+    Sections(&'a [TrackSection]),
+}
+
+use Value::{Int, Sections, Str};
+
+/// Returns the table name and field values of a record, in schema order after `sequence`
+fn row(record: &Record) -> (&'static str, Vec<Value<'_>>) {
+    fn int<'a>(v: u32) -> Value<'a> {
+        Int(Some(i64::from(v)))
+    }
+    fn str(s: &str) -> Value<'_> {
+        Str(Some(s))
+    }
+
+    let values = match record {
+        Record::Heartbeat(r) => vec![
+            int(r.laps_to_go),
+            Int(parse_millis(&r.time_to_go)),
+            Int(time_of_day(&r.time_of_day).map(i64::from)),
+            Int(parse_millis(&r.race_time)),
+            str(r.flag_status.name()),
+        ],
+        Record::Competitor(r) => vec![
+            str(&r.registration_number),
+            str(&r.number),
+            int(r.transponder_number),
+            str(&r.first_name),
+            str(&r.last_name),
+            str(&r.nationality),
+            int(r.class_number.into()),
+        ],
+        Record::CompetitorExt(r) => vec![
+            str(&r.registration_number),
+            str(&r.number),
+            int(r.class_number.into()),
+            str(&r.first_name),
+            str(&r.last_name),
+            str(&r.nationality),
+            str(&r.additional_data),
+        ],
+        Record::Run(r) => vec![int(r.number.into()), str(&r.description)],
+        Record::Class(r) => vec![int(r.number.into()), str(&r.description)],
+        Record::Setting(r) => vec![str(&r.description), str(&r.value)],
+        Record::Race(r) => vec![
+            int(r.position.into()),
+            str(&r.registration_number),
+            Int(r.laps.map(i64::from)),
+            Int(parse_millis(&r.total_time)),
+        ],
+        Record::PracticeQual(r) => vec![
+            int(r.position.into()),
+            str(&r.registration_number),
+            int(r.best_lap),
+            Int(parse_millis(&r.best_laptime)),
+        ],
+        Record::Init(r) => vec![str(&r.time), str(&r.date)],
+        Record::Passing(r) => vec![
+            str(&r.registration_number),
+            Int(parse_millis(&r.laptime)),
+            Int(parse_millis(&r.total_time)),
+        ],
+        Record::Correction(r) => vec![
+            str(&r.registration_number),
+            str(&r.number),
+            int(r.laps),
+            Int(parse_millis(&r.total_time)),
+            Int(parse_signed_millis(&r.correction)),
+        ],
+        Record::LineCrossing(r) => vec![
+            str(&r.number),
+            str(&r.timeline_number),
+            str(&r.timeline_name),
+            str(&r.date),
+            str(&r.time),
+            Int(timestamp(&r.date, &r.time)),
+            Int(r.driver_id.map(i64::from)),
+            Str(r.class_name.as_deref()),
+        ],
+        Record::TrackDescription(r) => vec![
+            str(&r.name),
+            str(&r.short_name),
+            str(&r.distance),
+            Sections(&r.sections),
+        ],
+    };
+    (record.kind(), values)
+}
+
+/// A builder for a single column
+enum Column {
+    Utf8(StringBuilder),
+    UInt8(UInt8Builder),
+    UInt16(UInt16Builder),
+    UInt32(UInt32Builder),
+    UInt64(UInt64Builder),
+    Int64(Int64Builder),
+    Time(Time32MillisecondBuilder),
+    Timestamp(TimestampMillisecondBuilder),
+    Sections(ListBuilder<StructBuilder>),
+}
+
+impl Column {
+    fn new(field: &Field, capacity: usize) -> Self {
+        match field.data_type() {
+            DataType::Utf8 => Column::Utf8(StringBuilder::with_capacity(capacity, capacity * 8)),
+            DataType::UInt8 => Column::UInt8(UInt8Builder::with_capacity(capacity)),
+            DataType::UInt16 => Column::UInt16(UInt16Builder::with_capacity(capacity)),
+            DataType::UInt32 => Column::UInt32(UInt32Builder::with_capacity(capacity)),
+            DataType::UInt64 => Column::UInt64(UInt64Builder::with_capacity(capacity)),
+            DataType::Int64 => Column::Int64(Int64Builder::with_capacity(capacity)),
+            DataType::Time32(_) => Column::Time(Time32MillisecondBuilder::with_capacity(capacity)),
+            DataType::Timestamp(..) => {
+                Column::Timestamp(TimestampMillisecondBuilder::with_capacity(capacity))
+            }
+            DataType::List(item) => Column::Sections(
+                ListBuilder::new(StructBuilder::from_fields(section_fields(), 0))
+                    .with_field(item.clone()),
+            ),
+            data_type => unreachable!("no builder for {}", data_type),
+        }
+    }
+
+    fn append(&mut self, value: &Value<'_>) {
+        match (self, value) {
+            (Column::Utf8(b), Str(v)) => b.append_option(*v),
+            (Column::UInt8(b), Int(v)) => b.append_option(v.map(|v| v as u8)),
+            (Column::UInt16(b), Int(v)) => b.append_option(v.map(|v| v as u16)),
+            (Column::UInt32(b), Int(v)) => b.append_option(v.map(|v| v as u32)),
+            (Column::UInt64(b), Int(v)) => b.append_option(v.map(|v| v as u64)),
+            (Column::Int64(b), Int(v)) => b.append_option(*v),
+            (Column::Time(b), Int(v)) => b.append_option(v.map(|v| v as i32)),
+            (Column::Timestamp(b), Int(v)) => b.append_option(*v),
+            (Column::Sections(b), Sections(sections)) => {
+                let s = b.values();
+                for section in sections.iter() {
+                    field::<StringBuilder>(s, 0).append_value(&section.name);
+                    field::<StringBuilder>(s, 1).append_value(&section.start);
+                    field::<StringBuilder>(s, 2).append_value(&section.end);
+                    field::<UInt32Builder>(s, 3).append_value(section.distance);
+                    s.append(true);
+                }
+                b.append(true);
+            }
+            _ => unreachable!("value does not match the column type"),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Column::Utf8(b) => Arc::new(b.finish()),
+            Column::UInt8(b) => Arc::new(b.finish()),
+            Column::UInt16(b) => Arc::new(b.finish()),
+            Column::UInt32(b) => Arc::new(b.finish()),
+            Column::UInt64(b) => Arc::new(b.finish()),
+            Column::Int64(b) => Arc::new(b.finish()),
+            Column::Time(b) => Arc::new(b.finish()),
+            Column::Timestamp(b) => Arc::new(b.finish()),
+            Column::Sections(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+fn field<T: ArrayBuilder>(builder: &mut StructBuilder, i: usize) -> &mut T {
+    builder
+        .field_builder(i)
+        .expect("section fields match the schema")
+}
+
+/// The buffered rows of a single table
+struct Table {
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    rows: usize,
+}
+
+impl Table {
+    fn new(schema: SchemaRef, capacity: usize) -> Self {
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|f| Column::new(f, capacity))
+            .collect();
+        Self {
+            schema,
+            columns,
+            rows: 0,
+        }
+    }
+
+    fn finish(&mut self) -> RecordBatch {
+        let columns = self.columns.iter_mut().map(Column::finish).collect();
+        self.rows = 0;
+        RecordBatch::try_new(self.schema.clone(), columns).expect("columns match the schema")
+    }
+}
+
+/// Converts records to Arrow record batches, one table per record type.
+///
+/// Rows are buffered for each table until it holds a full batch, which is returned by
+/// [`push`](BatchBuilder::push). Any remaining rows are returned by
+/// [`finish`](BatchBuilder::finish) once the stream ends.
+pub struct BatchBuilder {
+    batch_size: usize,
+    sequence: u64,
+    tables: BTreeMap<&'static str, Table>,
+}
+
+impl Default for BatchBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for BatchBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchBuilder")
+            .field("batch_size", &self.batch_size)
+            .field("sequence", &self.sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BatchBuilder {
+    /// Returns a `BatchBuilder` producing batches of 8192 rows
+    pub fn new() -> Self {
+        Self::new_with_batch_size(DEFAULT_BATCH_SIZE)
+    }
+
+    /// Returns a `BatchBuilder` producing batches of `batch_size` rows
+    pub fn new_with_batch_size(batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            sequence: 0,
+            tables: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a record to its table, returning the table name and a batch if the table is full
+    pub fn push(&mut self, record: &Record) -> Option<(&'static str, RecordBatch)> {
+        let (name, values) = row(record);
+        let batch_size = self.batch_size;
+        let table = self.tables.entry(name).or_insert_with(|| {
+            Table::new(
+                schema(name).expect("every record kind has a schema"),
+                batch_size,
+            )
+        });
+
+        table.columns[0].append(&Int(Some(self.sequence as i64)));
+        for (column, value) in table.columns[1..].iter_mut().zip(&values) {
+            column.append(value);
+        }
+        self.sequence += 1;
+        table.rows += 1;
+
+        if table.rows >= self.batch_size {
+            Some((name, table.finish()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a batch for every table holding rows which have not yet been returned
+    pub fn finish(&mut self) -> Vec<(&'static str, RecordBatch)> {
+        self.tables
+            .iter_mut()
+            .filter(|(_, table)| table.rows > 0)
+            .map(|(name, table)| (*name, table.finish()))
+            .collect()
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet {
+    use ::parquet::arrow::ArrowWriter;
+    use ::parquet::basic::Compression;
+    use ::parquet::errors::Result;
+    use ::parquet::file::properties::WriterProperties;
+    use arrow_array::RecordBatch;
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+
+    use super::{schema, BatchBuilder};
+    use crate::protocol::Record;
+
+    /// Writes records to a directory of Parquet files, one for each record type.
+    ///
+    /// A file named after the table, e.g. `passing.parquet`, is created when the first record of
+    /// each type is written. Files are only valid once the writer is closed with
+    /// [`close`](ParquetWriter::close).
+    pub struct ParquetWriter {
+        dir: PathBuf,
+        properties: WriterProperties,
+        batches: BatchBuilder,
+        writers: BTreeMap<&'static str, ArrowWriter<File>>,
+    }
+
+    impl std::fmt::Debug for ParquetWriter {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ParquetWriter")
+                .field("dir", &self.dir)
+                .field("batches", &self.batches)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl ParquetWriter {
+        /// Returns a `ParquetWriter` writing Snappy compressed files to `dir`, creating the
+        /// directory if it doesn't exist.
+        pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+            std::fs::create_dir_all(dir.as_ref())?;
+            Ok(Self {
+                dir: dir.as_ref().to_owned(),
+                properties: WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build(),
+                batches: BatchBuilder::new(),
+                writers: BTreeMap::new(),
+            })
+        }
+
+        /// Sets the properties used for each file, e.g. the compression or row group size
+        pub fn with_properties(mut self, properties: WriterProperties) -> Self {
+            self.properties = properties;
+            self
+        }
+
+        /// Sets the number of rows buffered for each table before they are written
+        pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+            self.batches = BatchBuilder::new_with_batch_size(batch_size);
+            self
+        }
+
+        /// Writes a record
+        pub fn write(&mut self, record: &Record) -> Result<()> {
+            match self.batches.push(record) {
+                Some((name, batch)) => self.write_batch(name, &batch),
+                None => Ok(()),
+            }
+        }
+
+        fn write_batch(&mut self, name: &'static str, batch: &RecordBatch) -> Result<()> {
+            let writer = match self.writers.get_mut(name) {
+                Some(writer) => writer,
+                None => {
+                    let file = File::create(self.dir.join(format!("{}.parquet", name)))?;
+                    let schema = schema(name).expect("every table has a schema");
+                    let writer = ArrowWriter::try_new(file, schema, Some(self.properties.clone()))?;
+                    self.writers.entry(name).or_insert(writer)
+                }
+            };
+            writer.write(batch)
+        }
+
+        /// Writes any buffered rows and closes every file, returning the paths written
+        pub fn close(mut self) -> Result<Vec<PathBuf>> {
+            for (name, batch) in self.batches.finish() {
+                self.write_batch(name, &batch)?;
+            }
+
+            let mut paths = Vec::new();
+            for (name, writer) in self.writers {
+                writer.close()?;
+                paths.push(self.dir.join(format!("{}.parquet", name)));
+            }
+            Ok(paths)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{
+        Int64Type, Time32MillisecondType, TimestampMillisecondType, UInt64Type,
+    };
+    use arrow_array::Array;
+
+    fn record(line: &str) -> Record {
+        Record::decode(line).unwrap()
+    }
+
+    #[test]
+    fn test_converts_typed_columns() {
+        let mut batches = BatchBuilder::new();
+        for line in &[
+            r#"$F,14,"00:12:45","13:34:23","00:09:47","Green ""#,
+            r#"$J,"1234BE","00:02:03.826","01:42:17.672""#,
+            r#"$J,"1234BE","00:59:59.999","01:44:21.498""#,
+            r#"$L,"40","P2","POP","01/27/2009","08:00:05.894""#,
+            r#"$T,"Sebring","SEB","3.7",2,"S1","P1","P2",1000,"S2","P2","SF",2000"#,
+        ] {
+            assert!(batches.push(&record(line)).is_none());
+        }
+        let tables: BTreeMap<_, _> = batches.finish().into_iter().collect();
+        assert_eq!(
+            tables.keys().copied().collect::<Vec<_>>(),
+            ["heartbeat", "line_crossing", "passing", "track_description"]
+        );
+        for (name, batch) in &tables {
+            assert_eq!(batch.schema(), schema(name).unwrap());
+        }
+
+        let heartbeat = &tables["heartbeat"];
+        assert_eq!(
+            heartbeat["time_of_day"]
+                .as_primitive::<Time32MillisecondType>()
+                .value(0),
+            48_863_000
+        );
+        assert_eq!(
+            heartbeat["flag_status"].as_string::<i32>().value(0),
+            "Green"
+        );
+
+        let passing = &tables["passing"];
+        assert_eq!(
+            passing["sequence"].as_primitive::<UInt64Type>().values(),
+            &[1, 2]
+        );
+        let laptimes = passing["laptime_ms"].as_primitive::<Int64Type>();
+        assert_eq!(laptimes.value(0), 123_826);
+        assert!(laptimes.is_null(1));
+        assert_eq!(
+            passing["total_time_ms"]
+                .as_primitive::<Int64Type>()
+                .value(1),
+            6_261_498
+        );
+
+        // 2009-01-27T08:00:05.894Z
+        let crossing = &tables["line_crossing"];
+        assert_eq!(
+            crossing["timestamp"]
+                .as_primitive::<TimestampMillisecondType>()
+                .value(0),
+            1_233_043_205_894
+        );
+        assert!(crossing["driver_id"].is_null(0));
+
+        let sections = tables["track_description"]["sections"].as_list::<i32>();
+        assert_eq!(sections.value_length(0), 2);
+    }
+
+    #[test]
+    fn test_returns_full_batches() {
+        let mut batches = BatchBuilder::new_with_batch_size(2);
+        let passing = record(r#"$J,"1","00:01:10.000","00:01:10.000""#);
+        let run = record(r#"$B,5,"Race""#);
+
+        assert!(batches.push(&passing).is_none());
+        assert!(batches.push(&run).is_none());
+        let (name, batch) = batches.push(&passing).unwrap();
+        assert_eq!(name, "passing");
+        assert_eq!(batch.num_rows(), 2);
+        assert!(batches.push(&passing).is_none());
+
+        let remaining = batches.finish();
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(remaining[0].0, "passing");
+        assert_eq!(remaining[0].1.num_rows(), 1);
+        assert_eq!(remaining[1].0, "run");
+        assert!(batches.finish().is_empty());
+    }
+
+    #[test]
+    fn test_parses_line_crossing_timestamps() {
+        assert_eq!(timestamp("01/01/1970", "00:00:00.001"), Some(1));
+        assert_eq!(
+            timestamp("2024-02-29", "23:59:59.999"),
+            Some(1_709_251_199_999)
+        );
+        assert_eq!(timestamp("13/01/2024", "00:00:00"), None);
+        assert_eq!(timestamp("01/01/2024", "25:00:00"), None);
+        assert_eq!(timestamp("", "00:00:00"), None);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_writes_parquet_files() {
+        use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use std::fs::File;
+
+        let dir = std::env::temp_dir().join(format!("rmonitor-parquet-{}", std::process::id()));
+        let mut writer = ParquetWriter::create(&dir).unwrap().with_batch_size(2);
+        for _ in 0..3 {
+            writer
+                .write(&record(r#"$J,"1","00:01:10.000","00:01:10.000""#))
+                .unwrap();
+        }
+        writer.write(&record(r#"$B,5,"Race""#)).unwrap();
+        let paths = writer.close().unwrap();
+        assert_eq!(
+            paths,
+            [dir.join("passing.parquet"), dir.join("run.parquet")]
+        );
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&paths[0]).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
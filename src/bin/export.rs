@@ -0,0 +1,57 @@
+use rmonitor::csv;
+use rmonitor::session::Session;
+use rmonitor::RMonitorReader;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+/// Exports a session to CSV files for spreadsheets
+///
+/// Usage: export <FILE|ADDRESS> [OUTPUT_DIR], reading a recorded session from FILE, or from an
+/// RMonitor source at ADDRESS until it disconnects. `classification.csv`, `lap_chart.csv` and
+/// `lap_times.csv` are written to OUTPUT_DIR, the current directory by default, along with a
+/// printable `results.html` if built with the `report` feature.
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let source = match args.next() {
+        Some(source) => source,
+        None => {
+            eprintln!("Usage: export <FILE|ADDRESS> [OUTPUT_DIR]");
+            std::process::exit(2);
+        }
+    };
+    let output_dir = args
+        .next()
+        .map_or_else(|| PathBuf::from("."), PathBuf::from);
+
+    let session = if Path::new(&source).exists() {
+        read_session(File::open(&source)?)?
+    } else {
+        read_session(TcpStream::connect(&source)?)?
+    };
+
+    let order = session.session_type().session_type.standings_order();
+    std::fs::create_dir_all(&output_dir)?;
+    csv::write_classification(
+        &session,
+        order,
+        File::create(output_dir.join("classification.csv"))?,
+    )?;
+    csv::write_lap_chart(&session, File::create(output_dir.join("lap_chart.csv"))?)?;
+    csv::write_lap_times(&session, File::create(output_dir.join("lap_times.csv"))?)?;
+    #[cfg(feature = "report")]
+    rmonitor::report::Report::new()
+        .with_order(order)
+        .write(&session, File::create(output_dir.join("results.html"))?)?;
+    Ok(())
+}
+
+fn read_session<R: Read>(source: R) -> Result<Session, Box<dyn Error>> {
+    let mut session = Session::new();
+    for record in RMonitorReader::new_with_max_length(source, 2048) {
+        session.apply(&record?);
+    }
+    Ok(session)
+}
@@ -0,0 +1,361 @@
+use futures::StreamExt;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use rmonitor::client;
+use rmonitor::protocol::{Flag, Record};
+use rmonitor::session::{BestScope, Session, StandingsOrder};
+use rmonitor::time::format_duration;
+use rmonitor::RMonitorDecoder;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How long a position change stays highlighted
+const HIGHLIGHT: Duration = Duration::from_secs(5);
+/// Delay between attempts to reconnect to the RMonitor source
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// `laps_to_go` value sent when a session is not limited by laps
+const NO_LAP_LIMIT: u32 = 9999;
+
+enum AppEvent {
+    Record(Record),
+    Connected(bool),
+    Key(KeyEvent),
+}
+
+/// Live leaderboard for an RMonitor source
+///
+/// Usage: leaderboard [ADDRESS], connecting to 127.0.0.1:50000 (the simulator) by default.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> std::io::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:50000".to_owned());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(read_records(addr.clone(), tx.clone()));
+    std::thread::spawn(move || read_keys(tx));
+
+    let mut terminal = ratatui::init();
+    let mut app = App::new(addr);
+    let result = run(&mut terminal, &mut app, &mut rx).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    rx: &mut mpsc::UnboundedReceiver<AppEvent>,
+) -> std::io::Result<()> {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(AppEvent::Record(record)) => app.session.apply(&record),
+                Some(AppEvent::Connected(connected)) => app.connected = connected,
+                Some(AppEvent::Key(key)) => {
+                    if !app.handle_key(key) {
+                        return Ok(());
+                    }
+                }
+                None => return Ok(()),
+            },
+            _ = tick.tick() => {}
+        }
+    }
+}
+
+/// Reads records from the source, reconnecting whenever the connection is lost
+async fn read_records(addr: String, tx: mpsc::UnboundedSender<AppEvent>) {
+    loop {
+        let decoder = RMonitorDecoder::new_with_max_length(2048);
+        if let Ok(mut records) = client::connect(addr.as_str(), decoder).await {
+            let _ = tx.send(AppEvent::Connected(true));
+            while let Some(Ok(record)) = records.next().await {
+                if tx.send(AppEvent::Record(record)).is_err() {
+                    return;
+                }
+            }
+        }
+
+        if tx.send(AppEvent::Connected(false)).is_err() {
+            return;
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Forwards key presses from the terminal, this blocks so runs on its own thread
+fn read_keys(tx: mpsc::UnboundedSender<AppEvent>) {
+    loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if tx.send(AppEvent::Key(key)).is_err() {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Change {
+    Gained,
+    Lost,
+}
+
+struct App {
+    addr: String,
+    connected: bool,
+    session: Session,
+    order: StandingsOrder,
+    class: Option<u8>,
+    /// Positions at the last redraw, to detect changes
+    positions: HashMap<String, usize>,
+    changes: HashMap<String, (Change, Instant)>,
+}
+
+impl App {
+    fn new(addr: String) -> Self {
+        Self {
+            addr,
+            connected: false,
+            session: Session::new(),
+            order: StandingsOrder::Race,
+            class: None,
+            positions: HashMap::new(),
+            changes: HashMap::new(),
+        }
+    }
+
+    /// Handles a key press, returning `false` if the application should exit
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('o') => {
+                self.order = match self.order {
+                    StandingsOrder::Race => StandingsOrder::PracticeQual,
+                    StandingsOrder::PracticeQual => StandingsOrder::Race,
+                };
+                self.reset_changes();
+            }
+            KeyCode::Char('c') => {
+                // Cycle through the classes which have competitors entered, then back to all
+                let mut classes: Vec<u8> = self
+                    .session
+                    .competitors
+                    .values()
+                    .filter_map(|c| c.class_number)
+                    .collect();
+                classes.sort_unstable();
+                classes.dedup();
+
+                self.class = match self.class {
+                    None => classes.first().copied(),
+                    Some(current) => classes.into_iter().find(|&c| c > current),
+                };
+                self.reset_changes();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn reset_changes(&mut self) {
+        self.positions.clear();
+        self.changes.clear();
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, table, footer] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_header(frame, header);
+        self.draw_standings(frame, table);
+        self.draw_footer(frame, footer);
+    }
+
+    fn draw_header(&self, frame: &mut Frame, area: Rect) {
+        let run = self
+            .session
+            .run
+            .as_ref()
+            .map_or("Waiting for session", |r| r.description.as_str());
+        let mut track = self.session.track_name().unwrap_or_default().to_owned();
+        if let Some(length) = self.session.track_length() {
+            track.push_str(&format!(" ({})", length));
+        }
+
+        let mut clocks = vec![flag_span(self.session.flag())];
+        if let Some(hb) = &self.session.heartbeat {
+            let laps_to_go = match hb.laps_to_go {
+                NO_LAP_LIMIT => "-".to_owned(),
+                laps => laps.to_string(),
+            };
+            clocks.push(Span::raw(format!(
+                "  Time {}   Elapsed {}   To go {}   Laps to go {}",
+                hb.time_of_day, hb.race_time, hb.time_to_go, laps_to_go
+            )));
+        }
+
+        let lines = vec![
+            Line::from(vec![
+                Span::raw(run).bold(),
+                Span::raw("  "),
+                Span::raw(track),
+            ]),
+            Line::from(clocks),
+        ];
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered()), area);
+    }
+
+    fn draw_standings(&mut self, frame: &mut Frame, area: Rect) {
+        let standings = self.session.standings(self.order, self.class);
+        let now = Instant::now();
+        self.changes
+            .retain(|_, (_, at)| now.duration_since(*at) < HIGHLIGHT);
+
+        let mut rows = Vec::with_capacity(standings.len());
+        for s in &standings {
+            let c = s.competitor;
+            let has_position = match self.order {
+                StandingsOrder::Race => c.race_position.is_some(),
+                StandingsOrder::PracticeQual => c.practice_position.is_some(),
+            };
+
+            if has_position {
+                let previous = self
+                    .positions
+                    .insert(c.registration_number.clone(), s.position);
+                match previous {
+                    Some(p) if p > s.position => {
+                        self.changes
+                            .insert(c.registration_number.clone(), (Change::Gained, now));
+                    }
+                    Some(p) if p < s.position => {
+                        self.changes
+                            .insert(c.registration_number.clone(), (Change::Lost, now));
+                    }
+                    _ => {}
+                }
+            }
+
+            let style = match self.changes.get(&c.registration_number) {
+                Some((Change::Gained, _)) => Style::default().fg(Color::Green),
+                Some((Change::Lost, _)) => Style::default().fg(Color::Red),
+                None => Style::default(),
+            };
+
+            let class = c
+                .class_number
+                .and_then(|n| self.session.class_name(n))
+                .unwrap_or_default();
+            // Lap times are highlighted as on timing screens
+            let laptime = |laptime: Option<Duration>| {
+                let highlight =
+                    laptime.and_then(|t| self.session.highlight(&c.registration_number, None, t));
+                let cell = Cell::from(laptime.map(format_duration).unwrap_or_default());
+                match highlight {
+                    Some(BestScope::Session) => cell.fg(Color::Magenta),
+                    Some(BestScope::Class) => cell.fg(Color::Yellow),
+                    Some(BestScope::Personal) => cell.fg(Color::Green),
+                    None => cell,
+                }
+            };
+
+            rows.push(
+                Row::new(vec![
+                    Cell::from(if has_position {
+                        s.position.to_string()
+                    } else {
+                        "-".to_owned()
+                    }),
+                    Cell::from(c.number.clone()),
+                    Cell::from(c.name()),
+                    Cell::from(class.to_owned()),
+                    Cell::from(c.laps.map(|l| l.to_string()).unwrap_or_default()),
+                    laptime(c.last_laptime),
+                    laptime(c.best_laptime),
+                    Cell::from(s.gap.map(|g| g.to_string()).unwrap_or_default()),
+                    Cell::from(s.interval.map(|g| g.to_string()).unwrap_or_default()),
+                ])
+                .style(style),
+            );
+        }
+
+        let header = Row::new(vec![
+            "Pos", "No", "Driver", "Class", "Laps", "Last", "Best", "Gap", "Int",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED));
+
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Length(5),
+            Constraint::Min(20),
+            Constraint::Length(10),
+            Constraint::Length(5),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ];
+
+        let title = match self.order {
+            StandingsOrder::Race => " Race order ",
+            StandingsOrder::PracticeQual => " Fastest lap order ",
+        };
+        let class = self
+            .class
+            .map(|n| format!(" {} ", self.session.class_name(n).unwrap_or("?")))
+            .unwrap_or_else(|| " All classes ".to_owned());
+
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title).title(class));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_footer(&self, frame: &mut Frame, area: Rect) {
+        let status = if self.connected {
+            Span::raw(format!(" Connected to {} ", self.addr)).fg(Color::Green)
+        } else {
+            Span::raw(format!(" Connecting to {}... ", self.addr)).fg(Color::Red)
+        };
+        let help = Span::raw("  q: quit  c: class filter  o: race / fastest lap order");
+        frame.render_widget(Paragraph::new(Line::from(vec![status, help])), area);
+    }
+}
+
+fn flag_span(flag: Option<&Flag>) -> Span<'static> {
+    let (text, fg, bg) = match flag {
+        Some(Flag::Green) => (" GREEN ".to_owned(), Color::Black, Color::Green),
+        Some(Flag::Yellow) => (" YELLOW ".to_owned(), Color::Black, Color::Yellow),
+        Some(Flag::Caution) => (" CAUTION ".to_owned(), Color::Black, Color::Yellow),
+        Some(Flag::Red) => (" RED ".to_owned(), Color::White, Color::Red),
+        Some(Flag::White) => (" WHITE ".to_owned(), Color::Black, Color::White),
+        Some(Flag::Finish) => (" FINISH ".to_owned(), Color::Black, Color::White),
+        Some(Flag::Purple) => (" PURPLE ".to_owned(), Color::White, Color::Magenta),
+        Some(Flag::Warmup) => (" WARMUP ".to_owned(), Color::Black, Color::Cyan),
+        Some(Flag::Other(name)) => (
+            format!(" {} ", name.to_uppercase()),
+            Color::White,
+            Color::DarkGray,
+        ),
+        Some(Flag::None) | None => (" NO FLAG ".to_owned(), Color::White, Color::DarkGray),
+    };
+    Span::styled(text, Style::default().fg(fg).bg(bg).bold())
+}
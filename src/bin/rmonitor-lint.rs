@@ -0,0 +1,58 @@
+use rmonitor::lint::{Linter, Severity};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::ExitCode;
+
+/// Reports protocol-level problems in an RMonitor feed
+///
+/// Usage: rmonitor-lint <FILE|ADDRESS>, checking a capture from FILE, or a live stream from
+/// ADDRESS until it disconnects. Each problem is printed with its line number and severity, and
+/// the exit status is 1 if any errors were found.
+fn main() -> ExitCode {
+    let source = match std::env::args().nth(1) {
+        Some(source) => source,
+        None => {
+            eprintln!("Usage: rmonitor-lint <FILE|ADDRESS>");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = if Path::new(&source).exists() {
+        File::open(&source).and_then(run)
+    } else {
+        TcpStream::connect(&source).and_then(run)
+    };
+
+    match result {
+        Ok((_, 0)) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Prints problems as they are found, returning the number of warnings and errors
+fn run<R: Read>(source: R) -> io::Result<(usize, usize)> {
+    let mut linter = Linter::new();
+    let (mut warnings, mut errors) = (0, 0);
+
+    linter.check_reader(BufReader::new(source), |finding| {
+        match finding.severity {
+            Severity::Warning => warnings += 1,
+            Severity::Error => errors += 1,
+        }
+        println!("{}", finding);
+    })?;
+
+    eprintln!(
+        "{} lines checked: {} errors, {} warnings",
+        linter.lines(),
+        errors,
+        warnings
+    );
+    Ok((warnings, errors))
+}
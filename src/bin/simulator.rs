@@ -24,7 +24,7 @@ async fn handle_socket(mut socket: TcpStream, addr: SocketAddr) -> std::io::Resu
 
     for line in lines {
         socket.write_all(line).await?;
-        socket.write_all(&[b'\n']).await?;
+        socket.write_all(b"\n").await?;
 
         // If this was a heartbeat message, delay sending the next for 1s
         if &line[..2] == b"$F" {
@@ -0,0 +1,40 @@
+//! An async TCP client for RMonitor servers.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use futures::stream::StreamExt;
+//! use rmonitor::client;
+//! use rmonitor::RMonitorDecoder;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let mut records =
+//!         client::connect("127.0.0.1:4000", RMonitorDecoder::new_with_max_length(2048)).await?;
+//!
+//!     while let Some(Ok(record)) = records.next().await {
+//!         println!("{:?}", record);
+//!     }
+//!     # Ok(())
+//! }
+//! ```
+use std::io;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::FramedRead;
+
+use crate::codec::RMonitorDecoder;
+
+/// A stream of records decoded from an RMonitor server connection.
+pub type RMonitorStream = FramedRead<TcpStream, RMonitorDecoder>;
+
+/// Connects to an RMonitor server, returning a [`Stream`] of the records it sends.
+///
+/// [`Stream`]: futures::stream::Stream
+pub async fn connect<A: ToSocketAddrs>(
+    addr: A,
+    decoder: RMonitorDecoder,
+) -> io::Result<RMonitorStream> {
+    let stream = TcpStream::connect(addr).await?;
+    Ok(FramedRead::new(stream, decoder))
+}
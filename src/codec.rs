@@ -1,10 +1,17 @@
-//! A tokio-util [`Decoder`] implementation for RMonitor.
+//! tokio-util [`Decoder`] and [`Encoder`] implementations for RMonitor.
 //!
 //! [`Decoder`]: tokio_util::codec::Decoder
+//! [`Encoder`]: tokio_util::codec::Encoder
+use std::io;
 use thiserror::Error;
-use tokio_util::bytes::BytesMut;
-use tokio_util::codec::{Decoder, LinesCodec, LinesCodecError};
+use tokio_util::bytes::{BufMut, BytesMut};
+use tokio_util::codec::{
+    AnyDelimiterCodec, AnyDelimiterCodecError, Decoder, Encoder, LinesCodecError,
+};
 
+use crate::encoding::Encoding;
+#[cfg(feature = "metrics")]
+use crate::metrics::FeedMetrics;
 use crate::protocol::*;
 
 /// An error was encountered when trying to decode an RMonitor record from
@@ -25,13 +32,35 @@ pub enum RMonitorCodecError {
     Io(#[from] std::io::Error),
 }
 
-/// A decoder for RMonitor records, which wraps an underlying [`LinesCodec`]
-/// to provide framing logic.
+impl From<AnyDelimiterCodecError> for RMonitorCodecError {
+    fn from(e: AnyDelimiterCodecError) -> Self {
+        // Framing errors are reported as they were when lines were split by a `LinesCodec`
+        match e {
+            AnyDelimiterCodecError::MaxChunkLengthExceeded => {
+                LinesCodecError::MaxLineLengthExceeded.into()
+            }
+            AnyDelimiterCodecError::Io(e) => LinesCodecError::Io(e).into(),
+        }
+    }
+}
+
+/// A decoder for RMonitor records, which splits the input on newlines and decodes each line
+/// using the configured [`Encoding`].
 ///
-/// [`LinesCodec`]: tokio_util::codec::LinesCodec
-#[derive(Default, Debug)]
+/// [`Encoding`]: crate::encoding::Encoding
+#[derive(Debug)]
 pub struct RMonitorDecoder {
-    lines_codec: LinesCodec,
+    line_codec: AnyDelimiterCodec,
+    encoding: Encoding,
+    lenient: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<FeedMetrics>,
+}
+
+impl Default for RMonitorDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RMonitorDecoder {
@@ -39,27 +68,79 @@ impl RMonitorDecoder {
     ///
     /// # Note
     ///
-    /// The returned `RMonitorDecoder` will have an underlying `LinesCodec` with no upper
-    /// bound on the length of a buffered line. Consider using [`new_with_max_length`] instead.
+    /// The returned `RMonitorDecoder` will have no upper bound on the length of a buffered line.
+    /// Consider using [`new_with_max_length`] instead.
     ///
     /// [`new_with_max_length`]: crate::codec::RMonitorDecoder::new_with_max_length()
     pub fn new() -> Self {
-        Self {
-            lines_codec: LinesCodec::new(),
-        }
+        Self::new_with_max_length(usize::MAX)
     }
 
-    /// Returns an `RMonitorDecoder` where the underlying `LinesCodec` has a maximum line length
-    /// limit.
+    /// Returns an `RMonitorDecoder` with a maximum line length limit.
     ///
     /// It is recommended to set such a limit where the input to be supplied to the decoder is
     /// untrusted, as an attacker could send an unbounded amount of input with no newline
     /// characters.
     pub fn new_with_max_length(max_length: usize) -> Self {
         Self {
-            lines_codec: LinesCodec::new_with_max_length(max_length),
+            line_codec: AnyDelimiterCodec::new_with_max_length(
+                b"\n".to_vec(),
+                b"\n".to_vec(),
+                max_length,
+            ),
+            encoding: Encoding::default(),
+            lenient: false,
+            #[cfg(feature = "metrics")]
+            metrics: None,
         }
     }
+
+    /// Sets the character encoding of the input stream, which defaults to strict UTF-8.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rmonitor::encoding::Encoding;
+    /// use rmonitor::RMonitorDecoder;
+    ///
+    /// let decoder = RMonitorDecoder::new_with_max_length(2048).with_encoding(Encoding::Windows1252);
+    /// ```
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Accepts records with extra or missing trailing fields, as some vendors send, rather than
+    /// skipping them. Each such record is logged as a warning, see [`Record::decode_lenient`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rmonitor::RMonitorDecoder;
+    ///
+    /// let decoder = RMonitorDecoder::new_with_max_length(2048).with_lenient(true);
+    /// ```
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Counts every decoded record and decode error in the given feed's metrics.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rmonitor::metrics::Metrics;
+    /// use rmonitor::RMonitorDecoder;
+    ///
+    /// let metrics = Metrics::new();
+    /// let decoder = RMonitorDecoder::new_with_max_length(2048).with_metrics(metrics.feed("main"));
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: FeedMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
 }
 
 impl Decoder for RMonitorDecoder {
@@ -67,31 +148,80 @@ impl Decoder for RMonitorDecoder {
     type Error = RMonitorCodecError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let line = self.lines_codec.decode(src)?;
-        if let Some(line) = line {
-            // If we've somehow started decoding in the middle of a record, or this line is
-            // completely empty, discard it and continue from the next one.
-            if line.is_empty() || line.as_bytes()[0] != b'$' {
-                return Ok(None);
-            }
+        // Lines which are skipped or fail to decode don't end decoding, there may be more
+        // complete lines in the buffer
+        while let Some(line) = self.line_codec.decode(src)? {
+            let line = match line.last() {
+                Some(b'\r') => &line[..line.len() - 1],
+                _ => &line[..],
+            };
+            let line = self.encoding.decode(line).ok_or_else(|| {
+                LinesCodecError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Unable to decode input as UTF8",
+                ))
+            })?;
 
-            // Try to decode the record, but if it fails, log the error and skip this line
-            match Record::decode(&line) {
-                Ok(record) => Ok(Some(record)),
-                Err(source) => {
-                    // Log the error with full details but continue processing
-                    log::warn!(
-                        "Skipping invalid RMonitor record from line '{}': {}",
-                        line,
-                        source
-                    );
-                    // Return Ok(None) to continue processing the next line
-                    Ok(None)
+            match decode_line(&line, self.lenient) {
+                Some(Ok(record)) => {
+                    #[cfg(feature = "metrics")]
+                    if let Some(metrics) = &self.metrics {
+                        metrics.observe(&record);
+                    }
+                    return Ok(Some(record));
+                }
+                #[cfg(feature = "metrics")]
+                Some(Err(e)) => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.observe_error(&e);
+                    }
                 }
+                _ => {}
             }
-        } else {
-            Ok(None)
         }
+        Ok(None)
+    }
+}
+
+/// An encoder for RMonitor records, which writes each record as a single CR/LF terminated line
+/// using the configured [`Encoding`].
+///
+/// [`Encoding`]: crate::encoding::Encoding
+#[derive(Default, Debug, Clone)]
+pub struct RMonitorEncoder {
+    encoding: Encoding,
+}
+
+impl RMonitorEncoder {
+    /// Returns an `RMonitorEncoder` which writes UTF-8 output.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the character encoding of the output stream, which defaults to UTF-8.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+}
+
+impl Encoder<&Record> for RMonitorEncoder {
+    type Error = RMonitorCodecError;
+
+    fn encode(&mut self, item: &Record, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let line = item.encode();
+        dst.reserve(line.len() + 2);
+        self.encoding.encode(&line, dst);
+        dst.put_slice(b"\r\n");
+        Ok(())
+    }
+}
+
+impl Encoder<Record> for RMonitorEncoder {
+    type Error = RMonitorCodecError;
+
+    fn encode(&mut self, item: Record, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&item, dst)
     }
 }
 
@@ -188,4 +318,130 @@ mod tests {
         assert!(matches!(valid_records[0], Record::Heartbeat(_)));
         assert!(matches!(valid_records[1], Record::Run(_)));
     }
+
+    #[test]
+    fn test_lenient_accepts_extra_fields() {
+        let line = "$B,5,\"Friday free practice\",\"FP1\"\r\n";
+
+        let mut decoder = RMonitorDecoder::new_with_max_length(2048);
+        let result = consume(&mut decoder, &mut BytesMut::from(line));
+        assert!(result.is_empty());
+
+        let mut decoder = RMonitorDecoder::new_with_max_length(2048).with_lenient(true);
+        let result = consume(&mut decoder, &mut BytesMut::from(line));
+        assert_eq!(1, result.len());
+        assert!(matches!(
+            &result[0],
+            Ok(Some(Record::Run(Run { extra, .. }))) if extra == &["\"FP1\""]
+        ));
+    }
+
+    #[test]
+    fn test_decodes_windows1252_line() {
+        let mut decoder =
+            RMonitorDecoder::new_with_max_length(2048).with_encoding(Encoding::Windows1252);
+        let mut bytes =
+            BytesMut::from(&b"$A,\"1\",\"1\",1,\"J\xfcrgen\",\"M\xfcller\",\"GER\",1\r\n"[..]);
+
+        let result = decoder.decode(&mut bytes).unwrap();
+        match result {
+            Some(Record::Competitor(c)) => {
+                assert_eq!(c.first_name, "Jürgen");
+                assert_eq!(c.last_name, "Müller");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_utf8_rejects_invalid_line() {
+        let line = &b"$B,1,\"Gro\xdfer Preis\"\r\n"[..];
+
+        let mut decoder = RMonitorDecoder::new_with_max_length(2048);
+        let result = decoder.decode(&mut BytesMut::from(line));
+        assert!(matches!(
+            result,
+            Err(RMonitorCodecError::LinesCodec(LinesCodecError::Io(_)))
+        ));
+
+        let mut decoder =
+            RMonitorDecoder::new_with_max_length(2048).with_encoding(Encoding::Utf8Lossy);
+        let result = decoder.decode(&mut BytesMut::from(line)).unwrap();
+        assert!(
+            matches!(result, Some(Record::Run(Run { description, .. })) if description == "Gro\u{FFFD}er Preis")
+        );
+    }
+
+    #[test]
+    fn test_errors_on_max_line_length() {
+        let mut decoder = RMonitorDecoder::new_with_max_length(16);
+        let mut bytes = BytesMut::from("$B,5,\"Friday free practice\"\r\n");
+
+        let result = decoder.decode(&mut bytes);
+        assert!(matches!(
+            result,
+            Err(RMonitorCodecError::LinesCodec(
+                LinesCodecError::MaxLineLengthExceeded
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_encodes_latin1_line() {
+        let record = Record::Run(Run {
+            number: 1,
+            description: "Großer Preis".to_owned(),
+            extra: Vec::new(),
+        });
+
+        let mut encoder = RMonitorEncoder::new().with_encoding(Encoding::Latin1);
+        let mut bytes = BytesMut::new();
+        encoder.encode(&record, &mut bytes).unwrap();
+        assert_eq!(&bytes[..], &b"$B,1,\"Gro\xdfer Preis\"\r\n"[..]);
+
+        let mut decoder = RMonitorDecoder::new().with_encoding(Encoding::Latin1);
+        let decoded = decoder.decode(&mut bytes).unwrap();
+        assert_eq!(decoded, Some(record));
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use crate::protocol::strategies::record;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_round_trips_stream(records in vec(record(), 0..16)) {
+            let mut decoder = RMonitorDecoder::new_with_max_length(2048);
+            let mut bytes = BytesMut::new();
+            for record in &records {
+                bytes.extend_from_slice(record.encode().as_bytes());
+                bytes.extend_from_slice(b"\r\n");
+            }
+
+            let mut decoded = vec![];
+            while let Some(record) = decoder.decode(&mut bytes).unwrap() {
+                decoded.push(record);
+            }
+
+            prop_assert_eq!(decoded, records);
+        }
+
+        #[test]
+        fn prop_arbitrary_bytes_do_not_panic(
+            chunks in vec(vec(any::<u8>(), 0..64), 0..16)
+        ) {
+            let mut decoder = RMonitorDecoder::new_with_max_length(128);
+            let mut bytes = BytesMut::new();
+            for chunk in chunks {
+                bytes.extend_from_slice(&chunk);
+                // Errors are fine, only panics are a failure
+                while let Ok(Some(_)) = decoder.decode(&mut bytes) {}
+            }
+            let _ = decoder.decode_eof(&mut bytes);
+        }
+    }
 }
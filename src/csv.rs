@@ -0,0 +1,317 @@
+//! CSV export of session results, for stewards and organisers working in spreadsheets.
+//!
+//! Three tables can be written from a [`Session`]:
+//!
+//! - [`write_classification`]: the final classification, with position, number, drivers, class,
+//!   laps, total time, gap to the leader and best lap
+//! - [`write_lap_chart`]: each car's position at the end of every lap
+//! - [`write_lap_times`]: each car's time for every lap
+//!
+//! Cars are listed in classification order in every table. Times are formatted as on a timing
+//! screen, e.g. `1:02.345`, and files are written as described by RFC 4180 with CRLF line
+//! endings.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::csv;
+//! use rmonitor::session::{Session, StandingsOrder};
+//! use rmonitor::RMonitorReader;
+//! use std::fs::File;
+//! # use std::error::Error;
+//!
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! let mut session = Session::new();
+//! for record in RMonitorReader::new(File::open("sample/2009_Sebring_ALMS_Session_5.txt")?) {
+//!     session.apply(&record?);
+//! }
+//!
+//! csv::write_classification(&session, StandingsOrder::Race, File::create("results.csv")?)?;
+//! csv::write_lap_chart(&session, File::create("lap_chart.csv")?)?;
+//! csv::write_lap_times(&session, File::create("lap_times.csv")?)?;
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter, Write};
+
+use crate::session::{CompetitorState, Session, StandingsOrder};
+use crate::time::format_duration;
+
+/// Writes rows of fields, quoting any field which requires it
+struct CsvWriter<W: Write> {
+    writer: BufWriter<W>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    fn row<I, S>(&mut self, fields: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b",")?;
+            }
+            let field = field.as_ref();
+            if field.contains([',', '"', '\r', '\n']) {
+                write!(self.writer, "\"{}\"", field.replace('"', "\"\""))?;
+            } else {
+                self.writer.write_all(field.as_bytes())?;
+            }
+        }
+        self.writer.write_all(b"\r\n")
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Returns the competitors in race classification order
+fn classified(session: &Session) -> Vec<&CompetitorState> {
+    session
+        .classification(StandingsOrder::Race, None)
+        .into_iter()
+        .map(|s| s.competitor)
+        .collect()
+}
+
+/// Returns the highest lap number completed by any competitor
+fn max_lap(session: &Session) -> u32 {
+    session
+        .competitors
+        .values()
+        .flat_map(|c| c.lap_history.iter().map(|l| l.lap))
+        .max()
+        .unwrap_or(0)
+}
+
+fn lap_header(first: &[&str], laps: u32) -> Vec<String> {
+    first
+        .iter()
+        .map(|&s| s.to_owned())
+        .chain((1..=laps).map(|lap| format!("Lap {}", lap)))
+        .collect()
+}
+
+/// Writes the final classification, in the given standings order.
+///
+/// Positions are calculated from laps and times, see [`Session::classification`], and cars
+/// without a time are listed last.
+pub fn write_classification<W: Write>(
+    session: &Session,
+    order: StandingsOrder,
+    writer: W,
+) -> io::Result<()> {
+    let mut csv = CsvWriter::new(writer);
+    csv.row([
+        "Position",
+        "Number",
+        "Drivers",
+        "Class",
+        "Laps",
+        "Total Time",
+        "Gap",
+        "Best Lap",
+        "Best Lap Time",
+    ])?;
+
+    for s in session.classification(order, None) {
+        let c = s.competitor;
+        let class = c
+            .class_number
+            .map(|n| {
+                session
+                    .class_name(n)
+                    .map_or_else(|| n.to_string(), str::to_owned)
+            })
+            .unwrap_or_default();
+
+        csv.row([
+            s.position.to_string(),
+            c.display_number().to_owned(),
+            c.name(),
+            class,
+            c.laps.map(|l| l.to_string()).unwrap_or_default(),
+            c.total_time.map(format_duration).unwrap_or_default(),
+            s.gap.map(|g| g.to_string()).unwrap_or_default(),
+            c.best_lap.map(|l| l.to_string()).unwrap_or_default(),
+            c.best_laptime.map(format_duration).unwrap_or_default(),
+        ])?;
+    }
+    csv.finish()
+}
+
+/// Writes a lap chart, with a row for each car and a column for each lap giving the car's
+/// position at the end of that lap.
+///
+/// Positions are calculated from the total time at the end of each lap, so a car is only placed
+/// on laps for which a passing was received.
+pub fn write_lap_chart<W: Write>(session: &Session, writer: W) -> io::Result<()> {
+    // Position of each car at the end of each lap, ordered by the time they completed it
+    let mut laps: BTreeMap<u32, Vec<(std::time::Duration, &str)>> = BTreeMap::new();
+    for c in session.competitors.values() {
+        for lap in &c.lap_history {
+            if let Some(total_time) = lap.total_time {
+                laps.entry(lap.lap)
+                    .or_default()
+                    .push((total_time, c.registration_number.as_str()));
+            }
+        }
+    }
+    let mut positions: BTreeMap<(&str, u32), usize> = BTreeMap::new();
+    for (lap, mut cars) in laps {
+        cars.sort();
+        for (i, (_, registration_number)) in cars.into_iter().enumerate() {
+            positions.insert((registration_number, lap), i + 1);
+        }
+    }
+
+    let max_lap = max_lap(session);
+    let mut csv = CsvWriter::new(writer);
+    csv.row(lap_header(&["Number", "Drivers"], max_lap))?;
+    for c in classified(session) {
+        let chart = (1..=max_lap).map(|lap| {
+            positions
+                .get(&(c.registration_number.as_str(), lap))
+                .map(|p| p.to_string())
+                .unwrap_or_default()
+        });
+        csv.row(
+            vec![c.display_number().to_owned(), c.name()]
+                .into_iter()
+                .chain(chart),
+        )?;
+    }
+    csv.finish()
+}
+
+/// Writes a table of lap times, with a row for each car and a column for each lap.
+pub fn write_lap_times<W: Write>(session: &Session, writer: W) -> io::Result<()> {
+    let max_lap = max_lap(session);
+    let mut csv = CsvWriter::new(writer);
+    csv.row(lap_header(&["Number", "Drivers"], max_lap))?;
+    for c in classified(session) {
+        let mut times = vec![String::new(); max_lap as usize];
+        for lap in &c.lap_history {
+            times[lap.lap as usize - 1] = format_duration(lap.laptime);
+        }
+        csv.row(
+            vec![c.display_number().to_owned(), c.name()]
+                .into_iter()
+                .chain(times),
+        )?;
+    }
+    csv.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::fixtures;
+
+    fn session(lines: &[&str]) -> Session {
+        let mut session = fixtures::session(lines);
+        // Fields are split on every comma, so one can't be sent within a record
+        session.competitors.get_mut("1").unwrap().last_name = "Smith, Jr.".to_owned();
+        session
+    }
+
+    fn output<F>(write: F) -> String
+    where
+        F: FnOnce(&mut Vec<u8>) -> io::Result<()>,
+    {
+        let mut out = Vec::new();
+        write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const LINES: &[&str] = &[
+        r#"$C,1,"GT""#,
+        r#"$A,"1","1",1,"John","Smith","",1"#,
+        r#"$A,"2","2",2,"Joe","Bloggs","",1"#,
+        r#"$J,"1","00:01:10.000","00:01:10.000""#,
+        r#"$G,1,"1",1,"00:01:10.000""#,
+        r#"$J,"2","00:01:11.000","00:01:11.000""#,
+        r#"$G,2,"2",1,"00:01:11.000""#,
+        r#"$J,"2","00:01:08.000","00:02:19.000""#,
+        r#"$G,1,"2",2,"00:02:19.000""#,
+        r#"$J,"1","00:01:10.000","00:02:20.000""#,
+        r#"$G,2,"1",2,"00:02:20.000""#,
+        r#"$H,1,"2",2,"00:01:08.000""#,
+        r#"$H,2,"1",1,"00:01:10.000""#,
+    ];
+
+    #[test]
+    fn test_writes_classification() {
+        let session = session(LINES);
+        let out = output(|w| write_classification(&session, StandingsOrder::Race, w));
+        assert_eq!(
+            out,
+            "Position,Number,Drivers,Class,Laps,Total Time,Gap,Best Lap,Best Lap Time\r\n\
+             1,2,Joe Bloggs,GT,2,2:19.000,,2,1:08.000\r\n\
+             2,1,\"John Smith, Jr.\",GT,2,2:20.000,+1.000,1,1:10.000\r\n"
+        );
+    }
+
+    #[test]
+    fn test_writes_sample_classification() {
+        let session = fixtures::sample_session("2009_Sebring_ALMS_Session_5.txt");
+        let order = session.session_type().session_type.standings_order();
+        let out = output(|w| write_classification(&session, order, w));
+        let rows: Vec<Vec<&str>> = out.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(
+            rows[1],
+            [
+                "1",
+                "66",
+                "de Ferran / Pagenaud",
+                "LMP1",
+                "41",
+                "1:56:27.497",
+                "",
+                "33",
+                "1:45.963"
+            ]
+        );
+        assert_eq!(rows[2][6], "+0.862");
+
+        // Cars which were never timed follow, numbered by registration without a competitor
+        let untimed = rows.iter().position(|r| r[8].is_empty()).unwrap();
+        assert!(rows[1..untimed]
+            .iter()
+            .all(|r| !r[6].is_empty() || r[0] == "1"));
+        assert!(rows[untimed..]
+            .iter()
+            .all(|r| r[8].is_empty() && !r[1].is_empty()));
+        assert!(rows[untimed..].iter().any(|r| r[1] == "7"));
+    }
+
+    #[test]
+    fn test_writes_lap_chart_and_times() {
+        let session = session(LINES);
+
+        let out = output(|w| write_lap_chart(&session, w));
+        assert_eq!(
+            out,
+            "Number,Drivers,Lap 1,Lap 2\r\n\
+             2,Joe Bloggs,2,1\r\n\
+             1,\"John Smith, Jr.\",1,2\r\n"
+        );
+
+        let out = output(|w| write_lap_times(&session, w));
+        assert_eq!(
+            out,
+            "Number,Drivers,Lap 1,Lap 2\r\n\
+             2,Joe Bloggs,1:11.000,1:08.000\r\n\
+             1,\"John Smith, Jr.\",1:10.000,1:10.000\r\n"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Character encodings supported for RMonitor byte streams.
+//!
+//! The protocol itself is ASCII, but free-text fields such as driver names and session
+//! descriptions are sent in whatever encoding the timing software happens to use. Many Windows
+//! based systems emit Windows-1252 or Latin-1 rather than UTF-8.
+use alloc::borrow::Cow;
+use alloc::string::String;
+use core::convert::TryFrom;
+
+/// Characters assigned to bytes `0x80..=0x9F` in Windows-1252, which is otherwise identical to
+/// Latin-1. The five unassigned bytes map to the matching C1 control character, as in the WHATWG
+/// encoding standard.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// The byte written in place of characters which can't be represented in the output encoding.
+const REPLACEMENT_BYTE: u8 = b'?';
+
+/// The character encoding of an RMonitor byte stream.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, where lines containing invalid sequences are rejected with an error
+    #[default]
+    Utf8,
+    /// UTF-8, where invalid sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`
+    Utf8Lossy,
+    /// ISO-8859-1, where every byte maps directly to the matching Unicode code point
+    Latin1,
+    /// Windows-1252, a superset of Latin-1 commonly emitted by Windows timing software
+    Windows1252,
+}
+
+impl Encoding {
+    /// Decodes a line of input into a string.
+    ///
+    /// Returns `None` only for [`Encoding::Utf8`] when the input is not valid UTF-8, all other
+    /// encodings are infallible.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, str>> {
+        match self {
+            Encoding::Utf8 => core::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            Encoding::Utf8Lossy => Some(String::from_utf8_lossy(bytes)),
+            Encoding::Latin1 | Encoding::Windows1252 if bytes.is_ascii() => {
+                // Only ASCII will be valid UTF-8 and mean the same thing in both encodings
+                core::str::from_utf8(bytes).ok().map(Cow::Borrowed)
+            }
+            Encoding::Latin1 => Some(Cow::Owned(bytes.iter().map(|&b| char::from(b)).collect())),
+            Encoding::Windows1252 => Some(Cow::Owned(
+                bytes
+                    .iter()
+                    .map(|&b| match b {
+                        0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+                        _ => char::from(b),
+                    })
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Encodes a string for output, appending the bytes to `out`.
+    ///
+    /// Characters which have no representation in a single-byte encoding are replaced with `?`.
+    pub fn encode<E: Extend<u8>>(&self, s: &str, out: &mut E) {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Lossy => out.extend(s.bytes()),
+            Encoding::Latin1 => out.extend(
+                s.chars()
+                    .map(|c| u8::try_from(c).unwrap_or(REPLACEMENT_BYTE)),
+            ),
+            Encoding::Windows1252 => out.extend(s.chars().map(|c| {
+                match WINDOWS_1252_HIGH.iter().position(|&h| h == c) {
+                    Some(i) => 0x80 + i as u8,
+                    None => match u8::try_from(c) {
+                        // These bytes were remapped, so the code points can't be written directly
+                        Ok(0x80..=0x9F) | Err(_) => REPLACEMENT_BYTE,
+                        Ok(b) => b,
+                    },
+                }
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_latin1() {
+        let bytes = b"M\xfcller";
+        assert_eq!(Encoding::Latin1.decode(bytes).unwrap(), "Müller");
+        assert_eq!(Encoding::Windows1252.decode(bytes).unwrap(), "Müller");
+        assert!(Encoding::Utf8.decode(bytes).is_none());
+        assert_eq!(Encoding::Utf8Lossy.decode(bytes).unwrap(), "M\u{FFFD}ller");
+    }
+
+    #[test]
+    fn test_decodes_windows1252_high_range() {
+        let bytes = b"\x80 \x8a \x9f";
+        assert_eq!(Encoding::Windows1252.decode(bytes).unwrap(), "€ Š Ÿ");
+        assert_eq!(
+            Encoding::Latin1.decode(bytes).unwrap(),
+            "\u{0080} \u{008A} \u{009F}"
+        );
+    }
+
+    #[test]
+    fn test_encodes_with_replacement() {
+        let mut out = vec![];
+        Encoding::Latin1.encode("Müller €", &mut out);
+        assert_eq!(out, b"M\xfcller ?");
+
+        let mut out = vec![];
+        Encoding::Windows1252.encode("Müller € 日", &mut out);
+        assert_eq!(out, b"M\xfcller \x80 ?");
+    }
+
+    #[test]
+    fn test_windows1252_round_trips_all_bytes() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let decoded = Encoding::Windows1252.decode(&bytes).unwrap();
+
+        let mut out = vec![];
+        Encoding::Windows1252.encode(&decoded, &mut out);
+        assert_eq!(out, bytes);
+    }
+}
@@ -0,0 +1,237 @@
+//! An HTTP/JSON API serving the live state of a session.
+//!
+//! The following endpoints are provided, all returning JSON:
+//!
+//! - `/session`: run, track and clock information from the latest heartbeat, and the session
+//!   type inferred by [`Session::session_type`](crate::session::Session::session_type)
+//! - `/competitors`: every known competitor
+//! - `/standings?class=&order=`: the current standings, optionally filtered by class number and
+//!   ordered by `race` (the default), `practice` (fastest lap) or `auto`, following the session
+//!   type
+//! - `/laps/{registration_number}`: the lap history of a single competitor
+//! - `/flags`: the current flag state and every flag change in the session
+//!
+//! All durations are given in milliseconds.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::http::{self, SharedSession};
+//! use rmonitor::RMonitorDecoder;
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::FramedRead;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let session = SharedSession::default();
+//!
+//!     let stream = TcpStream::connect("127.0.0.1:50000").await?;
+//!     let records = FramedRead::new(stream, RMonitorDecoder::new_with_max_length(2048));
+//!     tokio::spawn(http::track(records, session.clone()));
+//!
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//!     axum::serve(listener, http::router(session)).await?;
+//!     # Ok(())
+//! }
+//! ```
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+
+use crate::json::{competitor_json, flags_json, lap_json, session_json, standings_json};
+use crate::protocol::Record;
+use crate::session::{Session, StandingsOrder};
+use crate::sync::{read, write};
+
+/// Session state shared between the task applying records and the HTTP handlers.
+pub type SharedSession = Arc<RwLock<Session>>;
+
+/// Applies every record from `records` to the shared session, until the stream ends or returns
+/// an error.
+pub async fn track<S, E>(records: S, session: SharedSession)
+where
+    S: Stream<Item = Result<Record, E>>,
+    E: Display,
+{
+    futures::pin_mut!(records);
+    while let Some(record) = records.next().await {
+        match record {
+            Ok(record) => write(&session).apply(&record),
+            Err(e) => {
+                log::warn!("Stopped tracking session: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Returns a [`Router`] serving the API for the shared session.
+///
+/// [`Router`]: axum::Router
+pub fn router(session: SharedSession) -> Router {
+    Router::new()
+        .route("/session", get(get_session))
+        .route("/competitors", get(get_competitors))
+        .route("/standings", get(get_standings))
+        .route("/laps/{registration_number}", get(get_laps))
+        .route("/flags", get(get_flags))
+        .with_state(session)
+}
+
+async fn get_session(State(session): State<SharedSession>) -> Json<serde_json::Value> {
+    Json(session_json(&read(&session)))
+}
+
+async fn get_competitors(State(session): State<SharedSession>) -> Json<serde_json::Value> {
+    let session = read(&session);
+    let view: Vec<_> = session
+        .competitors
+        .values()
+        .map(|c| competitor_json(&session, c))
+        .collect();
+    Json(serde_json::json!(view))
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum OrderParam {
+    #[default]
+    Race,
+    Practice,
+    Auto,
+}
+
+#[derive(Deserialize)]
+struct StandingsQuery {
+    class: Option<u8>,
+    #[serde(default)]
+    order: OrderParam,
+}
+
+async fn get_standings(
+    State(session): State<SharedSession>,
+    Query(query): Query<StandingsQuery>,
+) -> Json<serde_json::Value> {
+    let session = read(&session);
+    let order = match query.order {
+        OrderParam::Race => StandingsOrder::Race,
+        OrderParam::Practice => StandingsOrder::PracticeQual,
+        OrderParam::Auto => session.session_type().session_type.standings_order(),
+    };
+
+    Json(standings_json(&session, order, query.class, |_| true))
+}
+
+async fn get_laps(
+    State(session): State<SharedSession>,
+    Path(registration_number): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let session = read(&session);
+    let competitor = session
+        .competitors
+        .get(&registration_number)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let view: Vec<_> = competitor.lap_history.iter().map(lap_json).collect();
+    Ok(Json(serde_json::json!({
+        "registration_number": registration_number,
+        "laps": view,
+    })))
+}
+
+async fn get_flags(State(session): State<SharedSession>) -> Json<serde_json::Value> {
+    Json(flags_json(&read(&session)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::session::fixtures::{self, ENTRIES};
+
+    /// Applies the lines to a session of the shared entries
+    fn session(lines: &[&str]) -> SharedSession {
+        Arc::new(RwLock::new(fixtures::session(&[ENTRIES, lines].concat())))
+    }
+
+    /// Serves the API on an ephemeral port and makes a single request, returning the status
+    /// code and body.
+    async fn get(session: SharedSession, path: &str) -> (u16, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(session)).await });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap().to_owned();
+        (status, body)
+    }
+
+    const LINES: &[&str] = &[
+        r#"$F,9999,"00:00:00","14:09:52","00:59:59","Green ""#,
+        r#"$J,"2","00:01:10.000","00:01:10.000""#,
+        r#"$G,1,"2",1,"00:01:10.000""#,
+        r#"$G,2,"1",0,"00:01:11.500""#,
+    ];
+
+    #[tokio::test]
+    async fn test_serves_session() {
+        let (status, body) = get(session(LINES), "/session").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(status, 200);
+        assert_eq!(json["run"]["description"], "Friday free practice");
+        assert_eq!(json["heartbeat"]["flag_status"], "Green");
+        assert_eq!(json["classes"][1]["description"], "Proto");
+        assert_eq!(json["competitors"], 2);
+        assert_eq!(json["session_type"]["type"], "practice");
+    }
+
+    #[tokio::test]
+    async fn test_serves_standings() {
+        let (_, body) = get(session(LINES), "/standings").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json[0]["number"], "2");
+        assert_eq!(json[1]["gap"]["laps"], 1);
+
+        let (_, body) = get(session(LINES), "/standings?class=1&order=practice").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["class_name"], "GT");
+
+        // Free practice is ordered by fastest lap
+        let lines = [LINES, &[r#"$H,1,"1",1,"00:01:09.000""#]].concat();
+        let (_, body) = get(session(&lines), "/standings?order=auto").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json[0]["number"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_serves_laps_and_flags() {
+        let (status, body) = get(session(LINES), "/laps/2").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(json["laps"][0]["laptime_ms"], 70_000);
+
+        let (status, _) = get(session(LINES), "/laps/99").await;
+        assert_eq!(status, 404);
+
+        let (_, body) = get(session(LINES), "/flags").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["current"], "Green");
+        assert_eq!(json["history"][0]["time_of_day"], "14:09:52");
+    }
+}
@@ -0,0 +1,468 @@
+//! JSON representations of the session state, shared by the HTTP, WebSocket and MQTT
+//! integrations.
+//!
+//! All durations are given in milliseconds.
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::protocol::{Flag, Heartbeat, Run};
+use crate::session::{CompetitorState, FlagChange, Gap, Lap, Session, StandingsOrder};
+#[cfg(any(feature = "websocket", feature = "mqtt"))]
+use crate::session::{Inconsistency, NewBest};
+use crate::time::millis;
+
+#[derive(Serialize)]
+struct ClassView<'a> {
+    number: u8,
+    description: &'a str,
+}
+
+#[derive(Serialize)]
+struct SessionView<'a> {
+    run: Option<&'a Run>,
+    track_name: Option<&'a str>,
+    track_length: Option<&'a str>,
+    heartbeat: Option<&'a Heartbeat>,
+    classes: Vec<ClassView<'a>>,
+    settings: &'a BTreeMap<String, String>,
+    competitors: usize,
+    session_type: SessionTypeView,
+}
+
+#[derive(Serialize)]
+struct SessionTypeView {
+    #[serde(rename = "type")]
+    session_type: &'static str,
+    confidence: f32,
+    overridden: bool,
+}
+
+/// Returns the JSON representation of the session information, as served at `/session` by the
+/// HTTP API.
+pub(crate) fn session_json(session: &Session) -> serde_json::Value {
+    let view = SessionView {
+        run: session.run.as_ref(),
+        track_name: session.track_name(),
+        track_length: session.track_length(),
+        heartbeat: session.heartbeat.as_ref(),
+        classes: session
+            .classes
+            .iter()
+            .map(|(&number, description)| ClassView {
+                number,
+                description,
+            })
+            .collect(),
+        settings: &session.settings,
+        competitors: session.competitors.len(),
+        session_type: {
+            let estimate = session.session_type();
+            SessionTypeView {
+                session_type: estimate.session_type.as_str(),
+                confidence: estimate.confidence,
+                overridden: estimate.overridden,
+            }
+        },
+    };
+    serde_json::json!(view)
+}
+
+#[derive(Serialize)]
+struct CompetitorView<'a> {
+    registration_number: &'a str,
+    number: &'a str,
+    transponder_number: Option<u32>,
+    first_name: &'a str,
+    last_name: &'a str,
+    nationality: &'a str,
+    class_number: Option<u8>,
+    class_name: Option<&'a str>,
+    additional_data: Option<&'a str>,
+    race_position: Option<u16>,
+    laps: Option<u32>,
+    total_time_ms: Option<u64>,
+    practice_position: Option<u16>,
+    best_lap: Option<u32>,
+    best_laptime_ms: Option<u64>,
+    /// `"session"`, `"class"` or `"personal"`, for purple and green highlighting
+    best_laptime_highlight: Option<&'static str>,
+    last_laptime_ms: Option<u64>,
+    last_laptime_highlight: Option<&'static str>,
+}
+
+impl<'a> CompetitorView<'a> {
+    fn new(session: &'a Session, c: &'a CompetitorState) -> Self {
+        Self {
+            registration_number: &c.registration_number,
+            number: &c.number,
+            transponder_number: c.transponder_number,
+            first_name: &c.first_name,
+            last_name: &c.last_name,
+            nationality: &c.nationality,
+            class_number: c.class_number,
+            class_name: c.class_number.and_then(|n| session.class_name(n)),
+            additional_data: c.additional_data.as_deref(),
+            race_position: c.race_position,
+            laps: c.laps,
+            total_time_ms: c.total_time.map(millis),
+            practice_position: c.practice_position,
+            best_lap: c.best_lap,
+            best_laptime_ms: c.best_laptime.map(millis),
+            best_laptime_highlight: highlight(session, c, c.best_laptime),
+            last_laptime_ms: c.last_laptime.map(millis),
+            last_laptime_highlight: highlight(session, c, c.last_laptime),
+        }
+    }
+}
+
+fn highlight(
+    session: &Session,
+    c: &CompetitorState,
+    laptime: Option<Duration>,
+) -> Option<&'static str> {
+    let scope = session.highlight(&c.registration_number, None, laptime?)?;
+    Some(scope.as_str())
+}
+
+/// Returns the JSON representation of a single competitor, as served at `/competitors` by the
+/// HTTP API.
+pub(crate) fn competitor_json(session: &Session, c: &CompetitorState) -> serde_json::Value {
+    serde_json::json!(CompetitorView::new(session, c))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum GapView {
+    TimeMs(u64),
+    Laps(u32),
+}
+
+impl From<Gap> for GapView {
+    fn from(gap: Gap) -> Self {
+        match gap {
+            Gap::Time(d) => GapView::TimeMs(millis(d)),
+            Gap::Laps(n) => GapView::Laps(n),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StandingView<'a> {
+    position: usize,
+    #[serde(flatten)]
+    competitor: CompetitorView<'a>,
+    gap: Option<GapView>,
+    interval: Option<GapView>,
+}
+
+/// Returns the JSON representation of the standings, as served at `/standings` by the HTTP
+/// API, including only the competitors matching `filter`.
+pub(crate) fn standings_json<F>(
+    session: &Session,
+    order: StandingsOrder,
+    class: Option<u8>,
+    filter: F,
+) -> serde_json::Value
+where
+    F: Fn(&CompetitorState) -> bool,
+{
+    let view: Vec<_> = session
+        .standings(order, class)
+        .into_iter()
+        .filter(|s| filter(s.competitor))
+        .map(|s| StandingView {
+            position: s.position,
+            competitor: CompetitorView::new(session, s.competitor),
+            gap: s.gap.map(GapView::from),
+            interval: s.interval.map(GapView::from),
+        })
+        .collect();
+    serde_json::json!(view)
+}
+
+#[derive(Serialize)]
+struct LapView {
+    lap: u32,
+    laptime_ms: u64,
+    total_time_ms: Option<u64>,
+}
+
+impl From<&Lap> for LapView {
+    fn from(l: &Lap) -> Self {
+        Self {
+            lap: l.lap,
+            laptime_ms: millis(l.laptime),
+            total_time_ms: l.total_time.map(millis),
+        }
+    }
+}
+
+/// Returns the JSON representation of a single lap, as listed at `/laps/{registration_number}` by
+/// the HTTP API.
+pub(crate) fn lap_json(lap: &Lap) -> serde_json::Value {
+    serde_json::json!(LapView::from(lap))
+}
+
+#[derive(Serialize)]
+struct FlagChangeView<'a> {
+    flag: &'a Flag,
+    time_of_day: &'a str,
+    race_time: &'a str,
+}
+
+/// Returns the JSON representation of a single change of flag state.
+pub(crate) fn flag_change_json(f: &FlagChange) -> serde_json::Value {
+    serde_json::json!(FlagChangeView {
+        flag: &f.flag,
+        time_of_day: &f.time_of_day,
+        race_time: &f.race_time,
+    })
+}
+
+/// Returns the JSON representation of an inconsistency between records, as a warning for the
+/// timing operator.
+#[cfg(any(feature = "websocket", feature = "mqtt"))]
+pub(crate) fn warning_json(session: &Session, i: &Inconsistency) -> serde_json::Value {
+    let number = session
+        .competitors
+        .get(i.registration_number())
+        .map(|c| c.number.as_str())
+        .filter(|number| !number.is_empty());
+    serde_json::json!({
+        "kind": i.kind(),
+        "registration_number": i.registration_number(),
+        "number": number,
+        "message": i.to_string(),
+    })
+}
+
+/// Returns the JSON representation of a new best lap or sector time.
+#[cfg(any(feature = "websocket", feature = "mqtt"))]
+pub(crate) fn best_json(session: &Session, best: &NewBest) -> serde_json::Value {
+    let number = |registration_number: &str| {
+        session
+            .competitors
+            .get(registration_number)
+            .map(|c| c.number.as_str())
+            .filter(|number| !number.is_empty())
+    };
+    serde_json::json!({
+        "registration_number": best.registration_number,
+        "number": number(&best.registration_number),
+        "scope": best.scope.as_str(),
+        "sector": best.sector,
+        "lap": best.lap,
+        "time_ms": millis(best.time),
+        "previous_ms": best.previous.map(millis),
+        "previous_holder": best.previous_holder,
+        "previous_holder_number": best.previous_holder.as_deref().and_then(number),
+        "improvement_ms": best.improvement.map(millis),
+        "message": best.to_string(),
+    })
+}
+
+/// Returns the JSON representation of the current flag and flag history, as served at `/flags`
+/// by the HTTP API.
+#[cfg(feature = "http")]
+pub(crate) fn flags_json(session: &Session) -> serde_json::Value {
+    let history: Vec<_> = session.flag_history.iter().map(flag_change_json).collect();
+    serde_json::json!({
+        "current": session.flag(),
+        "history": history,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Record;
+    use crate::session::fixtures::session;
+    use serde_json::json;
+
+    const LINES: &[&str] = &[
+        r#"$B,5,"Feature race""#,
+        r#"$C,1,"GT""#,
+        r#"$C,2,"Proto""#,
+        r#"$E,"TRACKNAME","Sebring""#,
+        r#"$A,"1","11",101,"John","Smith","USA",1"#,
+        r#"$A,"2","22",102,"Jane","Doe","GBR",1"#,
+        r#"$A,"3","33",103,"Joe","Bloggs","",2"#,
+        r#"$COMP,"1","11",1,"John","Smith","USA","Team A""#,
+        r#"$J,"1","00:00:00.000","00:00:05.000""#,
+        r#"$J,"1","00:01:10.000","00:01:15.000""#,
+        r#"$G,1,"1",1,"00:01:15.000""#,
+        r#"$J,"2","00:00:00.000","00:00:06.000""#,
+        r#"$J,"2","00:01:12.000","00:01:18.000""#,
+        r#"$G,2,"2",1,"00:01:18.000""#,
+        r#"$F,9999,"00:00:00","14:09:52","00:01:20","Green ""#,
+    ];
+
+    #[test]
+    fn test_session_json() {
+        assert_eq!(
+            session_json(&session(LINES)),
+            json!({
+                "run": {"number": 5, "description": "Feature race"},
+                "track_name": "Sebring",
+                "track_length": null,
+                "heartbeat": {
+                    "laps_to_go": 9999,
+                    "time_to_go": "00:00:00",
+                    "time_of_day": "14:09:52",
+                    "race_time": "00:01:20",
+                    "flag_status": "Green",
+                },
+                "classes": [
+                    {"number": 1, "description": "GT"},
+                    {"number": 2, "description": "Proto"},
+                ],
+                "settings": {"TRACKNAME": "Sebring"},
+                "competitors": 3,
+                "session_type": {"type": "race", "confidence": 0.75, "overridden": false},
+            })
+        );
+
+        let json = session_json(&Session::new());
+        assert_eq!(json["run"], serde_json::Value::Null);
+        assert_eq!(json["classes"], json!([]));
+        assert_eq!(json["competitors"], 0);
+    }
+
+    #[test]
+    fn test_competitor_json() {
+        let session = session(LINES);
+        assert_eq!(
+            competitor_json(&session, &session.competitors["1"]),
+            json!({
+                "registration_number": "1",
+                "number": "11",
+                "transponder_number": 101,
+                "first_name": "John",
+                "last_name": "Smith",
+                "nationality": "USA",
+                "class_number": 1,
+                "class_name": "GT",
+                "additional_data": "Team A",
+                "race_position": 1,
+                "laps": 1,
+                "total_time_ms": 75_000,
+                "practice_position": null,
+                "best_lap": 1,
+                "best_laptime_ms": 70_000,
+                "best_laptime_highlight": "session",
+                "last_laptime_ms": 70_000,
+                "last_laptime_highlight": "session",
+            })
+        );
+
+        let json = competitor_json(&session, &session.competitors["2"]);
+        assert_eq!(json["best_laptime_highlight"], "personal");
+        assert_eq!(json["additional_data"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_competitor_json_without_times() {
+        // Unparseable times and the no time sentinel are both left out
+        let session = session(&[
+            r#"$A,"3","33",103,"Joe","Bloggs","",2"#,
+            r#"$J,"3","1:1x","??""#,
+            r#"$G,3,"3",,"00:59:59.999""#,
+            r#"$H,3,"3",0,"00:59:59.999""#,
+        ]);
+        let json = competitor_json(&session, &session.competitors["3"]);
+        for field in [
+            "class_name",
+            "laps",
+            "total_time_ms",
+            "best_lap",
+            "best_laptime_ms",
+            "best_laptime_highlight",
+            "last_laptime_ms",
+            "last_laptime_highlight",
+        ] {
+            assert_eq!(json[field], serde_json::Value::Null, "{}", field);
+        }
+        assert_eq!(json["race_position"], 3);
+        assert_eq!(json["practice_position"], 3);
+    }
+
+    #[test]
+    fn test_standings_json() {
+        let mut lines = LINES.to_vec();
+        lines.push(r#"$G,3,"3",,"00:59:59.999""#);
+        let session = session(&lines);
+
+        let json = standings_json(&session, StandingsOrder::Race, None, |_| true);
+        let numbers: Vec<_> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["number"].as_str().unwrap())
+            .collect();
+        assert_eq!(numbers, ["11", "22", "33"]);
+        assert_eq!(json[0]["position"], 1);
+        assert_eq!(json[0]["gap"], serde_json::Value::Null);
+        assert_eq!(json[1]["gap"], json!({"time_ms": 3_000}));
+        assert_eq!(json[1]["interval"], json!({"time_ms": 3_000}));
+        assert_eq!(json[2]["gap"], serde_json::Value::Null);
+        // Competitors are flattened into each standing
+        assert_eq!(json[2]["class_name"], "Proto");
+
+        // Filtered competitors keep their position in the class
+        let json = standings_json(&session, StandingsOrder::Race, Some(1), |c| {
+            c.number != "11"
+        });
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["number"], "22");
+        assert_eq!(json[0]["position"], 2);
+
+        let json = standings_json(&session, StandingsOrder::Race, Some(3), |_| true);
+        assert_eq!(json, json!([]));
+    }
+
+    #[cfg(any(feature = "websocket", feature = "mqtt"))]
+    #[test]
+    fn test_event_json() {
+        let mut session = session(LINES);
+        let changes = session
+            .update(&Record::decode(r#"$COR,"2","20",1,"00:01:18.000","+00:00:00.000""#).unwrap());
+        let warnings: Vec<_> = changes
+            .iter()
+            .filter_map(|change| match change {
+                crate::session::SessionChange::Inconsistency(i) => Some(warning_json(&session, i)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0]["kind"], "correction_number_mismatch");
+        assert_eq!(warnings[0]["registration_number"], "2");
+        assert_eq!(warnings[0]["number"], "22");
+
+        let changes =
+            session.update(&Record::decode(r#"$J,"2","00:01:09.500","00:02:27.500""#).unwrap());
+        let bests: Vec<_> = changes
+            .iter()
+            .filter_map(|change| match change {
+                crate::session::SessionChange::NewBest(best) => Some(best_json(&session, best)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            bests.last(),
+            Some(&json!({
+                "registration_number": "2",
+                "number": "22",
+                "scope": "session",
+                "sector": null,
+                "lap": 2,
+                "time_ms": 69_500,
+                "previous_ms": 70_000,
+                "previous_holder": "1",
+                "previous_holder_number": "11",
+                "improvement_ms": 500,
+                "message": "2 set a session best lap of 1:09.500, 0.500 faster than 1",
+            }))
+        );
+    }
+}
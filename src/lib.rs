@@ -28,8 +28,87 @@
 //!     # Ok(())
 //! }
 //! ```
+//!
+//! For blocking use without an async runtime, see [`RMonitorReader`].
+//!
+//! # Features
+//!
+//! The crate is split into layers, so consumers only pull in the runtime support they use:
+//!
+//! - The [`protocol`], [`encoding`], [`session`], [`overtakes`] and [`time`] modules have no
+//!   runtime dependencies, and are `no_std + alloc` compatible when default features are disabled.
+//! - `std` (default): the blocking [`RMonitorReader`].
+//! - `codec` (default): the tokio-util [`RMonitorDecoder`] and [`RMonitorEncoder`].
+//! - `client` / `server`: async TCP client and server, built on tokio networking.
+//! - `serde`: `Serialize` and `Deserialize` implementations for protocol records.
+//! - `http`: an HTTP/JSON API serving the live session state, see [`http`].
+//! - `websocket`: a WebSocket bridge pushing session snapshots and deltas, see [`websocket`].
+//! - `sse`: a Server-Sent Events endpoint streaming every record, see [`sse`].
+//! - `mqtt`: an MQTT publisher for passings, flags and standings, see [`mqtt`].
+//! - `metrics`: Prometheus metrics for monitoring feeds, see [`metrics`].
+//! - `sqlite`: persistence of records to a SQLite database, see [`sqlite`].
+//! - `csv`: CSV export of results, lap charts and lap times, see [`csv`].
+//! - `report`: printable HTML results reports, see [`report`].
+//! - `arrow`: conversion of records to Arrow record batches, see [`arrow`].
+//! - `parquet`: Parquet files written from the Arrow tables, see [`arrow::ParquetWriter`].
+//! - `merge`: merging of primary and backup feeds with failover, see [`merge`].
+//! - `lint`: validation of feeds, and the `rmonitor-lint` binary, see [`lint`].
+//! - `simulator`: the `simulator` binary, which replays a sample session over TCP.
+//! - `tui`: the `leaderboard` binary, a live terminal leaderboard.
+//!
+//! [`RMonitorReader`]: crate::reader::RMonitorReader
+//! [`RMonitorDecoder`]: crate::codec::RMonitorDecoder
+//! [`RMonitorEncoder`]: crate::codec::RMonitorEncoder
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
+#[cfg(feature = "codec")]
 pub mod codec;
-pub use codec::RMonitorDecoder;
+#[cfg(feature = "codec")]
+pub use codec::{RMonitorDecoder, RMonitorEncoder};
+
+#[cfg(feature = "client")]
+pub mod client;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(any(feature = "http", feature = "mqtt"))]
+mod json;
+#[cfg(feature = "lint")]
+pub mod lint;
+#[cfg(feature = "merge")]
+pub mod merge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "report")]
+pub mod report;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "sse")]
+pub mod sse;
+#[cfg(any(feature = "http", feature = "merge", feature = "metrics"))]
+mod sync;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub mod encoding;
+
+#[cfg(feature = "std")]
+pub mod reader;
+#[cfg(feature = "std")]
+pub use reader::RMonitorReader;
 
+pub mod overtakes;
 pub mod protocol;
+pub mod session;
+pub mod time;
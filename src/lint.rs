@@ -0,0 +1,573 @@
+//! Validation of RMonitor feeds, reporting protocol-level problems which span several records.
+//!
+//! A [`Linter`] checks each line of a feed in turn, returning a [`Finding`] for every problem
+//! seen, with its line number and [`Severity`]:
+//!
+//! | Code                   | Severity | Problem                                                 |
+//! |------------------------|----------|---------------------------------------------------------|
+//! | `invalid_utf8`         | error    | A line is not valid UTF-8                               |
+//! | `unknown_record_type`  | warning  | A line has an unrecognised record prefix                |
+//! | `malformed_record` etc | error    | A line can't be decoded, see [`RecordError::kind`]      |
+//! | `unknown_competitor`   | warning  | A `$G`, `$H`, `$J` or `$COR` has no matching `$A`/`$COMP` |
+//! | `unknown_class`        | warning  | An `$A` or `$COMP` class has no `$C` record             |
+//! | `duplicate_position`   | error    | Two competitors hold the same position                  |
+//! | `position_gap`         | error    | Positions skip a number                                 |
+//! | `laps_decreased`       | warning  | A competitor's lap count went down                      |
+//! | `clock_went_backwards` | warning  | A heartbeat time of day or race time went down          |
+//! | `unknown_flag`         | warning  | A heartbeat has an unrecognised flag state              |
+//!
+//! Timing software doesn't always send records in order, so unknown registration numbers and
+//! classes are reported at the next heartbeat, or at the end of the feed, if they still haven't
+//! been described. Likewise position changes are sent as a series of records, which may be split
+//! by a heartbeat, so position problems are only reported if they last from one heartbeat to the
+//! next. Each problem is reported once, with the line of the first reference or the heartbeat
+//! where it was found. State is reset by an `Init` record, or by a `Run` record for a different
+//! run.
+//!
+//! The `rmonitor-lint` binary runs a `Linter` over a capture file or live stream.
+//!
+//! # Example
+//!
+//! ```
+//! use rmonitor::lint::{Linter, Severity};
+//!
+//! let mut linter = Linter::new();
+//! assert!(linter.check_line(br#"$C,1,"GT""#).is_empty());
+//! assert!(linter.check_line(br#"$A,"12","12",52474,"John","Johnson","USA",1"#).is_empty());
+//!
+//! assert!(linter.check_line(br#"$J,"7","00:02:03.826","01:42:17.672""#).is_empty());
+//!
+//! let findings = linter.finish();
+//! assert_eq!(findings[0].line, 3);
+//! assert_eq!(findings[0].severity, Severity::Warning);
+//! assert_eq!(findings[0].code, "unknown_competitor");
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::time::Duration;
+
+use crate::protocol::*;
+use crate::time::parse_duration;
+
+/// The severity of a [`Finding`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Suspicious, but possibly a quirk of the timing software
+    Warning,
+    /// The feed is incorrect
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem found in a feed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// The line number, counting from 1
+    pub line: usize,
+    pub severity: Severity,
+    /// The kind of problem, e.g. `"duplicate_position"`
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {}[{}]: {}",
+            self.line, self.severity, self.code, self.message
+        )
+    }
+}
+
+/// Returns a heartbeat clock as a duration.
+///
+/// Some timing software sends the `00:59:59.999` sentinel without milliseconds before the session
+/// starts, which is also treated as no time.
+fn clock(s: &str) -> Option<Duration> {
+    if s.trim().starts_with("00:59:59") {
+        None
+    } else {
+        parse_duration(s)
+    }
+}
+
+/// Formats a heartbeat clock as sent, e.g. `14:09:52`
+fn format_clock(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+/// Checks the lines of an RMonitor feed for problems
+#[derive(Clone, Debug, Default)]
+pub struct Linter {
+    line: usize,
+    competitors: BTreeSet<String>,
+    classes: BTreeSet<u8>,
+    /// Registration numbers referenced without a competitor record, with the first line
+    unresolved_competitors: BTreeMap<String, usize>,
+    /// Classes referenced without a class record, with the first line
+    unresolved_classes: BTreeMap<u8, usize>,
+    reported_competitors: BTreeSet<String>,
+    reported_classes: BTreeSet<u8>,
+    run: Option<u8>,
+    race_positions: BTreeMap<String, u16>,
+    practice_positions: BTreeMap<String, u16>,
+    laps: BTreeMap<String, u32>,
+    time_of_day: Option<Duration>,
+    race_time: Option<Duration>,
+    /// Position problems seen at the last heartbeat
+    position_problems: BTreeSet<(&'static str, String)>,
+    /// Position problems reported and not yet resolved
+    reported_positions: BTreeSet<(&'static str, String)>,
+    reported_flags: BTreeSet<String>,
+}
+
+impl Linter {
+    /// Returns a `Linter` expecting the first line of a feed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of lines checked
+    pub fn lines(&self) -> usize {
+        self.line
+    }
+
+    /// Checks the next line of the feed, without its line terminator, returning any problems
+    pub fn check_line(&mut self, line: &[u8]) -> Vec<Finding> {
+        self.line += 1;
+        let mut findings = Vec::new();
+
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line.trim_end_matches('\r'),
+            Err(e) => {
+                self.report(
+                    &mut findings,
+                    Severity::Error,
+                    "invalid_utf8",
+                    e.to_string(),
+                );
+                return findings;
+            }
+        };
+        if line.trim().is_empty() {
+            return findings;
+        }
+
+        match Record::decode(line) {
+            Ok(record) => self.check_record(&record, &mut findings),
+            Err(e) => {
+                let severity = match e {
+                    RecordError::UnknownRecordType(_) => Severity::Warning,
+                    _ => Severity::Error,
+                };
+                self.report(&mut findings, severity, e.kind(), e.to_string());
+            }
+        }
+        findings
+    }
+
+    /// Returns any problems which can only be found at the end of the feed, such as references
+    /// which were never resolved
+    pub fn finish(&mut self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        self.check_references(&mut findings);
+        findings
+    }
+
+    /// Checks every remaining line from `reader`, then finishes the feed, passing each problem to
+    /// `report` as it is found
+    pub fn check_reader<R, F>(&mut self, mut reader: R, mut report: F) -> io::Result<()>
+    where
+        R: BufRead,
+        F: FnMut(Finding),
+    {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                self.finish().into_iter().for_each(report);
+                return Ok(());
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            self.check_line(&line).into_iter().for_each(&mut report);
+        }
+    }
+
+    fn report(
+        &self,
+        findings: &mut Vec<Finding>,
+        severity: Severity,
+        code: &'static str,
+        message: String,
+    ) {
+        findings.push(Finding {
+            line: self.line,
+            severity,
+            code,
+            message,
+        });
+    }
+
+    /// Clears the state of the session, keeping the known competitors and classes
+    fn reset_session(&mut self) {
+        self.race_positions.clear();
+        self.practice_positions.clear();
+        self.laps.clear();
+        self.time_of_day = None;
+        self.race_time = None;
+        self.position_problems.clear();
+        self.reported_positions.clear();
+    }
+
+    fn check_record(&mut self, record: &Record, findings: &mut Vec<Finding>) {
+        match record {
+            Record::Init(_) => {
+                *self = Self {
+                    line: self.line,
+                    ..Self::default()
+                };
+            }
+            Record::Run(r) => {
+                if self.run.replace(r.number) != Some(r.number) {
+                    self.reset_session();
+                }
+            }
+            Record::Class(r) => {
+                self.classes.insert(r.number);
+                self.unresolved_classes.remove(&r.number);
+            }
+            Record::Competitor(Competitor {
+                registration_number,
+                class_number,
+                ..
+            })
+            | Record::CompetitorExt(CompetitorExt {
+                registration_number,
+                class_number,
+                ..
+            }) => {
+                self.competitors.insert(registration_number.clone());
+                self.unresolved_competitors.remove(registration_number);
+                self.check_class(*class_number);
+            }
+            Record::Race(r) => {
+                self.check_competitor(&r.registration_number);
+                self.race_positions
+                    .insert(r.registration_number.clone(), r.position);
+                if let Some(laps) = r.laps {
+                    if let Some(previous) = self.laps.insert(r.registration_number.clone(), laps) {
+                        if laps < previous {
+                            self.report(
+                                findings,
+                                Severity::Warning,
+                                "laps_decreased",
+                                format!(
+                                    "laps for {} decreased from {} to {}",
+                                    r.registration_number, previous, laps
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+            Record::PracticeQual(r) => {
+                self.check_competitor(&r.registration_number);
+                self.practice_positions
+                    .insert(r.registration_number.clone(), r.position);
+            }
+            Record::Passing(Passing {
+                registration_number,
+                ..
+            })
+            | Record::Correction(Correction {
+                registration_number,
+                ..
+            }) => self.check_competitor(registration_number),
+            Record::Heartbeat(r) => {
+                self.check_references(findings);
+                self.check_clocks(r, findings);
+                self.check_positions(findings);
+                if let Flag::Other(flag) = &r.flag_status {
+                    if self.reported_flags.insert(flag.clone()) {
+                        self.report(
+                            findings,
+                            Severity::Warning,
+                            "unknown_flag",
+                            format!("unrecognised flag state \"{}\"", flag),
+                        );
+                    }
+                }
+            }
+            Record::Setting(_) | Record::LineCrossing(_) | Record::TrackDescription(_) => {}
+        }
+    }
+
+    fn check_competitor(&mut self, registration_number: &str) {
+        if !self.competitors.contains(registration_number)
+            && !self.reported_competitors.contains(registration_number)
+        {
+            self.unresolved_competitors
+                .entry(registration_number.to_owned())
+                .or_insert(self.line);
+        }
+    }
+
+    fn check_class(&mut self, class_number: u8) {
+        if !self.classes.contains(&class_number) && !self.reported_classes.contains(&class_number) {
+            self.unresolved_classes
+                .entry(class_number)
+                .or_insert(self.line);
+        }
+    }
+
+    /// Reports references which weren't resolved by the end of the refresh cycle, at the line of
+    /// the first reference
+    fn check_references(&mut self, findings: &mut Vec<Finding>) {
+        let mut unresolved = Vec::new();
+        for (registration_number, line) in std::mem::take(&mut self.unresolved_competitors) {
+            unresolved.push(Finding {
+                line,
+                severity: Severity::Warning,
+                code: "unknown_competitor",
+                message: format!(
+                    "registration number {} has no competitor record",
+                    registration_number
+                ),
+            });
+            self.reported_competitors.insert(registration_number);
+        }
+        for (class_number, line) in std::mem::take(&mut self.unresolved_classes) {
+            unresolved.push(Finding {
+                line,
+                severity: Severity::Warning,
+                code: "unknown_class",
+                message: format!("class {} has no class record", class_number),
+            });
+            self.reported_classes.insert(class_number);
+        }
+        unresolved.sort_by_key(|f| f.line);
+        findings.extend(unresolved);
+    }
+
+    fn check_clocks(&mut self, heartbeat: &Heartbeat, findings: &mut Vec<Finding>) {
+        let time_of_day = clock(&heartbeat.time_of_day);
+        if let (Some(previous), Some(current)) = (self.time_of_day, time_of_day) {
+            // Passing midnight is expected
+            let midnight =
+                previous >= Duration::from_secs(23 * 3600) && current < Duration::from_secs(3600);
+            if current < previous && !midnight {
+                self.report(
+                    findings,
+                    Severity::Warning,
+                    "clock_went_backwards",
+                    format!(
+                        "time of day went backwards from {} to {}",
+                        format_clock(previous),
+                        heartbeat.time_of_day.trim()
+                    ),
+                );
+            }
+        }
+        self.time_of_day = time_of_day.or(self.time_of_day);
+
+        let race_time = clock(&heartbeat.race_time);
+        if let (Some(previous), Some(current)) = (self.race_time, race_time) {
+            if current < previous {
+                self.report(
+                    findings,
+                    Severity::Warning,
+                    "clock_went_backwards",
+                    format!(
+                        "race time went backwards from {} to {}",
+                        format_clock(previous),
+                        heartbeat.race_time.trim()
+                    ),
+                );
+            }
+        }
+        self.race_time = race_time.or(self.race_time);
+    }
+
+    fn check_positions(&mut self, findings: &mut Vec<Finding>) {
+        let mut problems = BTreeSet::new();
+        for (order, positions) in [
+            ("race", &self.race_positions),
+            ("practice", &self.practice_positions),
+        ]
+        .iter()
+        {
+            let mut holders: BTreeMap<u16, Vec<&str>> = BTreeMap::new();
+            for (registration_number, position) in positions.iter() {
+                holders
+                    .entry(*position)
+                    .or_default()
+                    .push(registration_number);
+            }
+
+            for (position, cars) in &holders {
+                if cars.len() > 1 {
+                    problems.insert((
+                        "duplicate_position",
+                        format!(
+                            "{} position {} is held by {}",
+                            order,
+                            position,
+                            cars.join(", ")
+                        ),
+                    ));
+                }
+            }
+
+            let missing: Vec<String> = (1..holders.keys().next_back().copied().unwrap_or(0))
+                .filter(|p| !holders.contains_key(p))
+                .map(|p| p.to_string())
+                .collect();
+            if !missing.is_empty() {
+                problems.insert((
+                    "position_gap",
+                    format!("{} positions are missing {}", order, missing.join(", ")),
+                ));
+            }
+        }
+
+        // Problems which lasted a whole heartbeat, so weren't part of a series of updates
+        let lasting: BTreeSet<_> = problems
+            .intersection(&self.position_problems)
+            .cloned()
+            .collect();
+        for (code, message) in lasting.difference(&self.reported_positions) {
+            self.report(findings, Severity::Error, code, message.clone());
+        }
+        self.reported_positions = lasting;
+        self.position_problems = problems;
+    }
+}
+
+/// Checks every line from `reader`, returning the problems found
+pub fn lint<R: BufRead>(reader: R) -> io::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    Linter::new().check_reader(reader, |finding| findings.push(finding))?;
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(feed: &str) -> Vec<(usize, &'static str)> {
+        lint(feed.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.line, f.code))
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_unknown_references() {
+        let feed = r#"$C,1,"GT"
+$J,"1","00:00:00.000","00:00:10.000"
+$A,"1","1",1,"John","Smith","",1
+$A,"2","2",2,"Joe","Bloggs","",2
+$J,"4","00:01:10.000","00:01:10.000"
+$F,9999,"00:00:00","12:00:10","00:00:10","Green "
+$G,1,"4",1,"00:01:10.000"
+$X,1
+$T,"Sebring","SEB","3.7",2,"S1","P1","P2",1000
+$J,"5","00:01:10.000","00:01:10.000"
+"#;
+        assert_eq!(
+            codes(feed),
+            [
+                (4, "unknown_class"),
+                (5, "unknown_competitor"),
+                (8, "unknown_record_type"),
+                (9, "incorrect_section_count"),
+                (10, "unknown_competitor"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_lasting_position_problems() {
+        let feed = r#"$A,"1","1",1,"John","Smith","",1
+$A,"2","2",2,"Joe","Bloggs","",1
+$A,"3","3",3,"Jane","Doe","",1
+$C,1,"GT"
+$G,1,"1",1,"00:01:10.000"
+$G,2,"2",1,"00:01:11.000"
+$G,3,"3",1,"00:01:12.000"
+$F,9999,"00:00:00","12:00:10","00:00:10","Green "
+$G,1,"2",2,"00:02:19.000"
+$F,9999,"00:00:00","12:00:11","00:00:11","Green "
+$G,2,"1",2,"00:02:20.000"
+$F,9999,"00:00:00","12:00:12","00:00:12","Green "
+$G,4,"3",1,"00:01:12.000"
+$F,9999,"00:00:00","12:00:13","00:00:13","Green "
+$F,9999,"00:00:00","12:00:14","00:00:14","Green "
+$F,9999,"00:00:00","12:00:15","00:00:15","Green "
+$G,3,"1",1,"00:02:20.000"
+$F,9999,"00:00:00","12:00:16","00:00:16","Green "
+$F,9999,"00:00:00","12:00:17","00:00:17","Green "
+"#;
+        let findings = lint(feed.as_bytes()).unwrap();
+        assert_eq!(
+            findings
+                .iter()
+                .map(|f| (f.line, f.code, f.message.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (15, "position_gap", "race positions are missing 3"),
+                (17, "laps_decreased", "laps for 1 decreased from 2 to 1"),
+                (19, "position_gap", "race positions are missing 2"),
+            ]
+        );
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(
+            findings[0].to_string(),
+            "line 15: error[position_gap]: race positions are missing 3"
+        );
+    }
+
+    #[test]
+    fn test_reports_clocks_going_backwards() {
+        let feed = r#"$F,9999,"00:00:00","23:59:59","00:59:59","      "
+$F,9999,"00:00:00","00:00:00","00:00:00","Green "
+$F,9999,"00:00:00","00:00:05","00:00:05","Green "
+$F,9999,"00:00:00","00:00:04","00:00:06","Green "
+$F,9999,"00:00:00","00:00:07","00:00:02","Green "
+$I,"00:00:08","27 Jan 09"
+$F,9999,"00:00:00","00:00:08","00:00:00","Green "
+"#;
+        assert_eq!(
+            codes(feed),
+            [(4, "clock_went_backwards"), (5, "clock_went_backwards")]
+        );
+    }
+
+    #[test]
+    fn test_reports_unknown_flags_once() {
+        let feed = r#"$F,9999,"00:00:00","10:00:00","00:00:00","green"
+$F,9999,"00:00:00","10:00:01","00:00:01","Code 60"
+$F,9999,"00:00:00","10:00:02","00:00:02","Code 60"
+"#;
+        assert_eq!(codes(feed), [(2, "unknown_flag")]);
+    }
+}
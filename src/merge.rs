@@ -0,0 +1,403 @@
+//! Merging of redundant RMonitor feeds with automatic failover.
+//!
+//! Big events run primary and backup timing systems side by side, each with its own RMonitor
+//! output. A [`MergedStream`] consumes a stream of records from each of them and presents one
+//! logical stream, which can be tracked like any single feed.
+//!
+//! Sources are given in priority order. Records are passed through from the active source, which
+//! is the first source to have sent a heartbeat within the heartbeat timeout. When the active
+//! source's heartbeats stop, or it ends or returns an error, the next live source takes over,
+//! and control returns to a higher priority source as soon as its heartbeats resume.
+//!
+//! Records from the standby sources are buffered for the de-duplication window. On failover the
+//! new source's buffered records are replayed, so records the failed source never sent are not
+//! lost, while a record identical to one passed through from another source within the window is
+//! dropped. The window should be longer than the heartbeat timeout to cover the gap before a
+//! failover.
+//!
+//! The active source can be monitored from another task with a [`MergeStatus`], and each failover
+//! is logged.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use rmonitor::merge::MergedStream;
+//! use rmonitor::RMonitorDecoder;
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::FramedRead;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let primary = TcpStream::connect("10.0.0.1:50000").await?;
+//!     let backup = TcpStream::connect("10.0.0.2:50000").await?;
+//!     let decoder = || RMonitorDecoder::new_with_max_length(2048);
+//!
+//!     let mut records = MergedStream::new()
+//!         .with_source("primary", FramedRead::new(primary, decoder()))
+//!         .with_source("backup", FramedRead::new(backup, decoder()));
+//!     let status = records.status();
+//!
+//!     while let Some(Ok(record)) = records.next().await {
+//!         println!("{}: {:?}", status.active(), record);
+//!     }
+//!     # Ok(())
+//! }
+//! ```
+use futures::Stream;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+use crate::protocol::Record;
+use crate::sync::lock;
+
+/// The default time without a heartbeat after which a source is considered to have failed
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default time for which records are buffered and de-duplicated
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+/// A handle reporting which source of a [`MergedStream`] is active.
+#[derive(Clone, Debug, Default)]
+pub struct MergeStatus {
+    inner: Arc<Mutex<StatusInner>>,
+}
+
+#[derive(Debug, Default)]
+struct StatusInner {
+    active: String,
+    failovers: u64,
+}
+
+impl MergeStatus {
+    /// Returns the name of the active source
+    pub fn active(&self) -> String {
+        lock(&self.inner).active.clone()
+    }
+
+    /// Returns the number of times the active source has changed
+    pub fn failovers(&self) -> u64 {
+        lock(&self.inner).failovers
+    }
+}
+
+struct Source<S> {
+    name: String,
+    stream: S,
+    /// The time of the last heartbeat, or when the source was added if there has been none
+    last_heartbeat: Instant,
+    ended: bool,
+    /// Records received while on standby, within the de-duplication window
+    buffer: VecDeque<(Instant, Record)>,
+}
+
+/// A stream of records merged from several redundant sources, failing over between them.
+///
+/// Each source is a stream of records, such as a [`client::RMonitorStream`]. The merged stream
+/// ends once every source has ended. An error is only returned if it comes from the active source
+/// and no other source is live, otherwise it's logged and the source is dropped.
+///
+/// [`client::RMonitorStream`]: crate::client::RMonitorStream
+pub struct MergedStream<S> {
+    sources: Vec<Source<S>>,
+    active: usize,
+    /// The source to poll first, so a busy source can't starve the others
+    next_poll: usize,
+    heartbeat_timeout: Duration,
+    dedup_window: Duration,
+    /// Records passed through within the de-duplication window, with their source
+    recent: VecDeque<(Instant, usize, Record)>,
+    pending: VecDeque<Record>,
+    /// Woken when the active source's heartbeat times out
+    sleep: Option<Pin<Box<Sleep>>>,
+    status: MergeStatus,
+}
+
+impl<S> Default for MergedStream<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> MergedStream<S> {
+    /// Creates a merged stream without any sources
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            active: 0,
+            next_poll: 0,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            recent: VecDeque::new(),
+            pending: VecDeque::new(),
+            sleep: None,
+            status: MergeStatus::default(),
+        }
+    }
+
+    /// Adds a source, with a lower priority than the sources already added
+    pub fn with_source(mut self, name: &str, stream: S) -> Self {
+        if self.sources.is_empty() {
+            lock(&self.status.inner).active = name.to_owned();
+        }
+        self.sources.push(Source {
+            name: name.to_owned(),
+            stream,
+            last_heartbeat: Instant::now(),
+            ended: false,
+            buffer: VecDeque::new(),
+        });
+        self
+    }
+
+    /// Sets the time without a heartbeat after which a source is considered to have failed
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Sets the time for which records are buffered and de-duplicated
+    pub fn with_dedup_window(mut self, window: Duration) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
+    /// Returns a handle reporting the active source
+    pub fn status(&self) -> MergeStatus {
+        self.status.clone()
+    }
+
+    /// Returns the name of the active source, or `None` if there are no sources
+    pub fn active(&self) -> Option<&str> {
+        self.sources.get(self.active).map(|s| s.name.as_str())
+    }
+
+    fn is_live(&self, source: &Source<S>, now: Instant) -> bool {
+        !source.ended && now < source.last_heartbeat + self.heartbeat_timeout
+    }
+
+    /// Switches to the highest priority live source, if it isn't already active
+    fn update_active(&mut self, now: Instant) {
+        let preferred = self.sources.iter().position(|s| self.is_live(s, now));
+        let next = match preferred {
+            Some(next) if next != self.active => next,
+            _ => return,
+        };
+
+        log::warn!(
+            "Failing over from {} to {}",
+            self.sources[self.active].name,
+            self.sources[next].name
+        );
+        self.active = next;
+        {
+            let mut status = lock(&self.status.inner);
+            status.active = self.sources[next].name.clone();
+            status.failovers += 1;
+        }
+
+        let window = self.dedup_window;
+        let buffer = std::mem::take(&mut self.sources[next].buffer);
+        for (_, record) in buffer
+            .into_iter()
+            .filter(|(received, _)| now.duration_since(*received) < window)
+        {
+            self.emit(next, record, now);
+        }
+    }
+
+    fn receive(&mut self, index: usize, record: Record, now: Instant) {
+        let window = self.dedup_window;
+        let source = &mut self.sources[index];
+        if let Record::Heartbeat(_) = record {
+            source.last_heartbeat = now;
+        }
+
+        if index == self.active {
+            self.emit(index, record, now);
+        } else {
+            while let Some((received, _)) = source.buffer.front() {
+                if now.duration_since(*received) < window {
+                    break;
+                }
+                source.buffer.pop_front();
+            }
+            source.buffer.push_back((now, record));
+        }
+    }
+
+    /// Queues a record to be passed through, unless another source has already sent it
+    fn emit(&mut self, index: usize, record: Record, now: Instant) {
+        while let Some((sent, _, _)) = self.recent.front() {
+            if now.duration_since(*sent) < self.dedup_window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        if self
+            .recent
+            .iter()
+            .any(|(_, source, r)| *source != index && *r == record)
+        {
+            return;
+        }
+        self.recent.push_back((now, index, record.clone()));
+        self.pending.push_back(record);
+    }
+}
+
+impl<S, E> Stream for MergedStream<S>
+where
+    S: Stream<Item = Result<Record, E>> + Unpin,
+    E: Display,
+{
+    type Item = Result<Record, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let now = Instant::now();
+            this.update_active(now);
+            if let Some(record) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(record)));
+            }
+            if this.sources.iter().all(|s| s.ended) {
+                return Poll::Ready(None);
+            }
+
+            let (first, count) = (this.next_poll, this.sources.len());
+            let mut progressed = false;
+            for i in (0..count).map(|k| (first + k) % count) {
+                if this.sources[i].ended {
+                    continue;
+                }
+                match Pin::new(&mut this.sources[i].stream).poll_next(cx) {
+                    Poll::Ready(Some(Ok(record))) => {
+                        this.receive(i, record, now);
+                    }
+                    Poll::Ready(Some(Err(e))) => {
+                        this.sources[i].ended = true;
+                        let failover = this.sources.iter().any(|s| this.is_live(s, now));
+                        if i == this.active && !failover {
+                            this.next_poll = (i + 1) % count;
+                            return Poll::Ready(Some(Err(e)));
+                        }
+                        log::warn!("Dropped source {}: {}", this.sources[i].name, e);
+                    }
+                    Poll::Ready(None) => {
+                        log::warn!("Source {} ended", this.sources[i].name);
+                        this.sources[i].ended = true;
+                    }
+                    Poll::Pending => continue,
+                }
+                this.next_poll = (i + 1) % count;
+                progressed = true;
+                break;
+            }
+            if progressed {
+                continue;
+            }
+
+            // Every source is pending, so wait for the active source to time out. There's nothing
+            // to wait for if it already has, as only a record can bring back a live source.
+            let active = &this.sources[this.active];
+            let deadline = active.last_heartbeat + this.heartbeat_timeout;
+            if this.is_live(active, now) {
+                let sleep = this
+                    .sleep
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+                sleep.as_mut().reset(deadline);
+                if sleep.as_mut().poll(cx).is_ready() {
+                    continue;
+                }
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc::{unbounded, UnboundedSender};
+    use futures::StreamExt;
+    use std::convert::Infallible;
+
+    type Sender = UnboundedSender<Result<Record, Infallible>>;
+
+    fn send(sender: &Sender, line: &str) {
+        sender
+            .unbounded_send(Ok(Record::decode(line).unwrap()))
+            .unwrap();
+    }
+
+    async fn next<S>(merged: &mut MergedStream<S>) -> String
+    where
+        S: Stream<Item = Result<Record, Infallible>> + Unpin,
+    {
+        merged.next().await.unwrap().unwrap().encode()
+    }
+
+    const HEARTBEAT: &str = r#"$F,14,"00:12:45","13:34:23","00:09:47","Green ""#;
+    const PASSING: &str = r#"$J,"1234BE","00:02:03.826","01:42:17.672""#;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fails_over_and_back() {
+        let (primary, primary_records) = unbounded();
+        let (backup, backup_records) = unbounded();
+        let mut merged = MergedStream::new()
+            .with_source("primary", primary_records)
+            .with_source("backup", backup_records);
+        let status = merged.status();
+        let start = Instant::now();
+
+        for sender in [&primary, &backup].iter() {
+            send(sender, HEARTBEAT);
+            send(sender, PASSING);
+        }
+        assert_eq!(next(&mut merged).await, HEARTBEAT);
+        assert_eq!(next(&mut merged).await, PASSING);
+
+        // The primary stops, so the backup's buffered records are replayed without duplicates
+        tokio::time::advance(Duration::from_secs(3)).await;
+        let heartbeat = r#"$F,14,"00:12:48","13:34:26","00:09:50","Green ""#;
+        let missed = r#"$J,"21","00:01:10.000","01:43:27.672""#;
+        send(&backup, heartbeat);
+        send(&backup, missed);
+        assert_eq!(next(&mut merged).await, heartbeat);
+        assert_eq!(next(&mut merged).await, missed);
+        assert!(start.elapsed() >= DEFAULT_HEARTBEAT_TIMEOUT);
+        assert_eq!(merged.active(), Some("backup"));
+        assert_eq!(status.active(), "backup");
+
+        let heartbeat = r#"$F,14,"00:12:50","13:34:28","00:09:52","Green ""#;
+        send(&primary, heartbeat);
+        assert_eq!(next(&mut merged).await, heartbeat);
+        assert_eq!(status.active(), "primary");
+        assert_eq!(status.failovers(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_passes_errors_without_failover() {
+        let (primary, primary_records) = unbounded();
+        let (backup, backup_records) = unbounded::<Result<Record, &str>>();
+        let mut merged = MergedStream::new()
+            .with_source("primary", primary_records)
+            .with_source("backup", backup_records)
+            .with_heartbeat_timeout(Duration::ZERO);
+
+        primary.unbounded_send(Err("connection reset")).unwrap();
+        drop(backup);
+        assert!(matches!(merged.next().await, Some(Err("connection reset"))));
+        assert!(merged.next().await.is_none());
+    }
+}
@@ -0,0 +1,383 @@
+//! Prometheus metrics for monitoring RMonitor feeds.
+//!
+//! A [`Metrics`] registry holds the metrics of one or more named feeds, each updated by a
+//! decoder configured with [`RMonitorDecoder::with_metrics`]. The registry is rendered in the
+//! Prometheus text format, and can be served at `/metrics` with [`Metrics::router`]:
+//!
+//! | Metric                               | Type    | Labels          |
+//! |--------------------------------------|---------|-----------------|
+//! | `rmonitor_connected`                 | gauge   | `feed`          |
+//! | `rmonitor_seconds_since_heartbeat`   | gauge   | `feed`          |
+//! | `rmonitor_records_total`             | counter | `feed`, `type`  |
+//! | `rmonitor_decode_errors_total`       | counter | `feed`, `error` |
+//! | `rmonitor_flag`                      | gauge   | `feed`, `flag`  |
+//! | `rmonitor_laps_to_go`                | gauge   | `feed`          |
+//! | `rmonitor_active_competitors`        | gauge   | `feed`          |
+//!
+//! Record types and error labels are those returned by [`Record::kind`] and
+//! [`RecordError::kind`]. `rmonitor_flag` is `1` for the current flag and `0` for every other,
+//! and active competitors are those who have crossed the timing line since the last `Init`.
+//!
+//! [`RMonitorDecoder::with_metrics`]: crate::RMonitorDecoder::with_metrics
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::metrics::Metrics;
+//! use rmonitor::RMonitorDecoder;
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::FramedRead;
+//! # use futures::StreamExt;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let metrics = Metrics::new();
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:9090").await?;
+//!     let router = metrics.router();
+//!     tokio::spawn(async move { axum::serve(listener, router).await });
+//!
+//!     let feed = metrics.feed("main");
+//!     let decoder = RMonitorDecoder::new_with_max_length(2048).with_metrics(feed.clone());
+//!     let mut records = FramedRead::new(TcpStream::connect("127.0.0.1:50000").await?, decoder);
+//!     feed.set_connected(true);
+//!     while let Some(Ok(_record)) = records.next().await {}
+//!     feed.set_connected(false);
+//!     # Ok(())
+//! }
+//! ```
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::protocol::{Flag, Record, RecordError};
+use crate::sync::lock;
+
+/// `laps_to_go` value sent when a session is not limited by laps, which isn't exported
+const NO_LAP_LIMIT: u32 = 9999;
+
+/// Every flag state, exported as a Prometheus state set along with `other` for unrecognised
+/// states
+const FLAGS: &[(Flag, &str)] = &[
+    (Flag::None, "none"),
+    (Flag::Green, "green"),
+    (Flag::Yellow, "yellow"),
+    (Flag::Red, "red"),
+    (Flag::Finish, "finish"),
+    (Flag::Purple, "purple"),
+    (Flag::Warmup, "warmup"),
+    (Flag::Caution, "caution"),
+    (Flag::White, "white"),
+];
+
+/// A registry of feed metrics, rendered together in the Prometheus text format.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    feeds: Arc<Mutex<Vec<FeedMetrics>>>,
+}
+
+impl Metrics {
+    /// Returns an empty `Metrics` registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the metrics for the feed with the given name, registering it if it's new
+    pub fn feed(&self, name: &str) -> FeedMetrics {
+        let mut feeds = lock(&self.feeds);
+        if let Some(feed) = feeds.iter().find(|f| f.name.as_str() == name) {
+            return feed.clone();
+        }
+
+        let feed = FeedMetrics {
+            name: Arc::new(name.to_owned()),
+            state: Arc::new(Mutex::new(FeedState::default())),
+        };
+        feeds.push(feed.clone());
+        feed
+    }
+
+    /// Renders every feed's metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let feeds: Vec<_> = lock(&self.feeds)
+            .iter()
+            .map(|f| (escape(&f.name), lock(&f.state).clone()))
+            .collect();
+        let now = Instant::now();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "rmonitor_connected",
+            "gauge",
+            "Whether the feed is connected to its source",
+        );
+        for (feed, state) in &feeds {
+            sample(
+                &mut out,
+                "rmonitor_connected",
+                feed,
+                None,
+                state.connected as u8,
+            );
+        }
+
+        header(
+            &mut out,
+            "rmonitor_seconds_since_heartbeat",
+            "gauge",
+            "Seconds since the last heartbeat record was received",
+        );
+        for (feed, state) in &feeds {
+            if let Some(at) = state.last_heartbeat {
+                let seconds = now.duration_since(at).as_secs_f64();
+                sample(
+                    &mut out,
+                    "rmonitor_seconds_since_heartbeat",
+                    feed,
+                    None,
+                    seconds,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "rmonitor_records_total",
+            "counter",
+            "Records decoded, by record type",
+        );
+        for (feed, state) in &feeds {
+            for (kind, count) in &state.records {
+                sample(
+                    &mut out,
+                    "rmonitor_records_total",
+                    feed,
+                    Some(("type", *kind)),
+                    count,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "rmonitor_decode_errors_total",
+            "counter",
+            "Records which failed to decode, by error",
+        );
+        for (feed, state) in &feeds {
+            for (kind, count) in &state.errors {
+                let label = Some(("error", *kind));
+                sample(&mut out, "rmonitor_decode_errors_total", feed, label, count);
+            }
+        }
+
+        header(&mut out, "rmonitor_flag", "gauge", "The current flag state");
+        for (feed, state) in &feeds {
+            if let Some(current) = &state.flag {
+                for (flag, name) in FLAGS {
+                    let value = (flag == current) as u8;
+                    sample(&mut out, "rmonitor_flag", feed, Some(("flag", name)), value);
+                }
+                let other = matches!(current, Flag::Other(_)) as u8;
+                sample(
+                    &mut out,
+                    "rmonitor_flag",
+                    feed,
+                    Some(("flag", "other")),
+                    other,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "rmonitor_laps_to_go",
+            "gauge",
+            "Laps remaining, if the session is limited by laps",
+        );
+        for (feed, state) in &feeds {
+            if let Some(laps) = state.laps_to_go.filter(|&l| l != NO_LAP_LIMIT) {
+                sample(&mut out, "rmonitor_laps_to_go", feed, None, laps);
+            }
+        }
+
+        header(
+            &mut out,
+            "rmonitor_active_competitors",
+            "gauge",
+            "Competitors who have crossed the timing line this session",
+        );
+        for (feed, state) in &feeds {
+            let active = state.active.len();
+            sample(&mut out, "rmonitor_active_competitors", feed, None, active);
+        }
+
+        out
+    }
+
+    /// Returns a [`Router`] serving the metrics at `/metrics`.
+    ///
+    /// [`Router`]: axum::Router
+    pub fn router(&self) -> Router {
+        let metrics = self.clone();
+        Router::new().route(
+            "/metrics",
+            get(move || async move {
+                (
+                    [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    metrics.render(),
+                )
+                    .into_response()
+            }),
+        )
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample<V: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    feed: &str,
+    label: Option<(&str, &str)>,
+    value: V,
+) {
+    let _ = match label {
+        Some((key, label)) => writeln!(
+            out,
+            "{}{{feed=\"{}\",{}=\"{}\"}} {}",
+            name, feed, key, label, value
+        ),
+        None => writeln!(out, "{}{{feed=\"{}\"}} {}", name, feed, value),
+    };
+}
+
+/// Escapes a label value for the Prometheus text format
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Clone, Debug, Default)]
+struct FeedState {
+    connected: bool,
+    last_heartbeat: Option<Instant>,
+    records: BTreeMap<&'static str, u64>,
+    errors: BTreeMap<&'static str, u64>,
+    flag: Option<Flag>,
+    laps_to_go: Option<u32>,
+    /// Registration numbers of competitors who have crossed the line
+    active: BTreeSet<String>,
+}
+
+/// The metrics of a single feed, which may be cloned and shared between tasks.
+#[derive(Clone, Debug)]
+pub struct FeedMetrics {
+    name: Arc<String>,
+    state: Arc<Mutex<FeedState>>,
+}
+
+impl FeedMetrics {
+    /// Returns the name of the feed
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets whether the feed is connected to its source
+    pub fn set_connected(&self, connected: bool) {
+        lock(&self.state).connected = connected;
+    }
+
+    /// Counts a decoded record, and updates the session gauges from it
+    pub fn observe(&self, record: &Record) {
+        let mut state = lock(&self.state);
+        *state.records.entry(record.kind()).or_default() += 1;
+
+        match record {
+            Record::Heartbeat(r) => {
+                state.last_heartbeat = Some(Instant::now());
+                state.flag = Some(r.flag_status.clone());
+                state.laps_to_go = Some(r.laps_to_go);
+            }
+            Record::Passing(r) => {
+                state.active.insert(r.registration_number.clone());
+            }
+            Record::Init(_) => state.active.clear(),
+            _ => {}
+        }
+    }
+
+    /// Counts a record which failed to decode
+    pub fn observe_error(&self, error: &RecordError) {
+        *lock(&self.state).errors.entry(error.kind()).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RMonitorDecoder;
+    use tokio_util::bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+
+    #[test]
+    fn test_counts_records_and_errors() {
+        let metrics = Metrics::new();
+        let feed = metrics.feed("main");
+        assert_eq!(metrics.feed("main").name(), "main");
+        feed.set_connected(true);
+
+        let mut decoder = RMonitorDecoder::new().with_metrics(feed);
+        let mut bytes = BytesMut::from(
+            concat!(
+                "$F,9999,\"00:00:00\",\"14:09:52\",\"00:59:59\",\"Green \"\r\n",
+                "$F,12,\"00:00:00\",\"14:09:53\",\"00:59:58\",\"Yellow\"\r\n",
+                "$J,\"1\",\"00:01:10.000\",\"00:01:10.000\"\r\n",
+                "$J,\"1\",\"00:01:11.000\",\"00:02:21.000\"\r\n",
+                "$F,12,\"00:00:00\",\"14:09:54\",\"00:59:57\",\"Code 60\"\r\n",
+                "$Z,\"bogus\"\r\n",
+            )
+            .as_bytes(),
+        );
+        while decoder.decode(&mut bytes).unwrap().is_some() {}
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE rmonitor_records_total counter\n"));
+        assert!(out.contains("rmonitor_connected{feed=\"main\"} 1\n"));
+        assert!(out.contains("rmonitor_seconds_since_heartbeat{feed=\"main\"} "));
+        assert!(out.contains("rmonitor_records_total{feed=\"main\",type=\"heartbeat\"} 3\n"));
+        assert!(out.contains("rmonitor_records_total{feed=\"main\",type=\"passing\"} 2\n"));
+        assert!(out.contains(
+            "rmonitor_decode_errors_total{feed=\"main\",error=\"unknown_record_type\"} 1\n"
+        ));
+        assert!(out.contains("rmonitor_flag{feed=\"main\",flag=\"other\"} 1\n"));
+        assert!(out.contains("rmonitor_flag{feed=\"main\",flag=\"yellow\"} 0\n"));
+        assert!(out.contains("rmonitor_flag{feed=\"main\",flag=\"green\"} 0\n"));
+        assert!(out.contains("rmonitor_laps_to_go{feed=\"main\"} 12\n"));
+        assert!(out.contains("rmonitor_active_competitors{feed=\"main\"} 1\n"));
+    }
+
+    #[test]
+    fn test_renders_multiple_feeds() {
+        let metrics = Metrics::new();
+        metrics.feed("main").set_connected(true);
+        metrics.feed("backup \"b\"");
+
+        let out = metrics.render();
+        assert!(out.contains("rmonitor_connected{feed=\"main\"} 1\n"));
+        assert!(out.contains("rmonitor_connected{feed=\"backup \\\"b\\\"\"} 0\n"));
+        // Session gauges are only exported once a heartbeat is received
+        assert!(!out.contains("rmonitor_laps_to_go{"));
+        assert!(!out.contains("rmonitor_flag{"));
+    }
+}
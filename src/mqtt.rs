@@ -0,0 +1,478 @@
+//! Publishes records and session state to an MQTT broker.
+//!
+//! Records are applied to a [`Session`], and the resulting changes published as JSON below a
+//! topic prefix (`rmonitor` by default) and the current run number:
+//!
+//! - `rmonitor/{run}/session`: run, track and clock information, as served at `/session` by the
+//!   [HTTP API](crate::http)
+//! - `rmonitor/{run}/passing/{number}`: the latest passing of each car, with the lap completed
+//!   and the competitor's state
+//! - `rmonitor/{run}/flag`: the current flag state, published when it changes
+//! - `rmonitor/{run}/standings/{class}`: the standings of each class number, and of every
+//!   competitor at `rmonitor/{run}/standings/all`, ordered for the inferred type of session and
+//!   published when a position or lap changes
+//! - `rmonitor/{run}/warning`: a record disagreeing with earlier records, see
+//!   [`Inconsistency`](crate::session::Inconsistency)
+//! - `rmonitor/{run}/best`: a personal, class or session best lap or sector, see
+//!   [`NewBest`](crate::session::NewBest)
+//!
+//! All durations are given in milliseconds. Messages other than warnings and bests are retained
+//! by default, so a newly connected subscriber immediately receives the latest state. The QoS
+//! level is configurable with [`MqttPublisher::with_qos`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::mqtt::{MqttPublisher, QoS};
+//! use rmonitor::RMonitorDecoder;
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::FramedRead;
+//! use rumqttc::{AsyncClient, MqttOptions};
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let (mqtt, mut eventloop) =
+//!         AsyncClient::new(MqttOptions::new("rmonitor", "localhost", 1883), 64);
+//!     tokio::spawn(async move { while eventloop.poll().await.is_ok() {} });
+//!
+//!     let stream = TcpStream::connect("127.0.0.1:50000").await?;
+//!     let records = FramedRead::new(stream, RMonitorDecoder::new_with_max_length(2048));
+//!     MqttPublisher::new()
+//!         .with_qos(QoS::AtLeastOnce)
+//!         .track(mqtt, records)
+//!         .await;
+//!     # Ok(())
+//! }
+//! ```
+use futures::{Stream, StreamExt};
+use rumqttc::{AsyncClient, ClientError};
+use std::collections::BTreeSet;
+use std::fmt::Display;
+
+use crate::json::{
+    best_json, competitor_json, flag_change_json, lap_json, session_json, standings_json,
+    warning_json,
+};
+use crate::protocol::Record;
+use crate::session::{Session, SessionChange};
+use crate::time::parse_millis;
+
+pub use rumqttc::QoS;
+
+/// A message to be published
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+/// Standings which need to be republished after a record
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Standings {
+    All,
+    Class(u8),
+}
+
+/// Tracks the session state and publishes it to MQTT topics.
+#[derive(Clone, Debug)]
+pub struct MqttPublisher {
+    prefix: String,
+    qos: QoS,
+    retain: bool,
+    session: Session,
+}
+
+impl Default for MqttPublisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MqttPublisher {
+    /// Returns a `MqttPublisher` publishing retained messages below `rmonitor` with QoS 1
+    pub fn new() -> Self {
+        Self {
+            prefix: "rmonitor".to_owned(),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            session: Session::new(),
+        }
+    }
+
+    /// Sets the prefix of every topic
+    pub fn with_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the QoS level messages are published with
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Sets whether messages are retained by the broker
+    pub fn with_retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Returns the session state built from every record seen so far
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Applies a record to the session, returning the messages to publish as a result.
+    pub fn messages(&mut self, record: &Record) -> Vec<Message> {
+        let changes = self.session.update(record);
+        let mut messages = Vec::new();
+
+        match record {
+            Record::Heartbeat(_)
+            | Record::Run(_)
+            | Record::Class(_)
+            | Record::Setting(_)
+            | Record::Init(_) => {
+                let payload = session_json(&self.session);
+                messages.push(self.message(&["session"], payload));
+            }
+            Record::Passing(passing) => {
+                if let Some(c) = self.session.competitors.get(&passing.registration_number) {
+                    let lap = changes.iter().find_map(|change| match change {
+                        SessionChange::LapCompleted { lap, .. } => Some(lap_json(lap)),
+                        _ => None,
+                    });
+                    let number = c.display_number();
+                    let payload = serde_json::json!({
+                        "registration_number": c.registration_number,
+                        "number": number,
+                        "lap": lap,
+                        "total_time_ms": parse_millis(&passing.total_time),
+                        "competitor": competitor_json(&self.session, c),
+                    });
+                    messages.push(self.message(&["passing", number], payload));
+                }
+            }
+            _ => {}
+        }
+
+        let mut standings = BTreeSet::new();
+        for change in &changes {
+            match change {
+                SessionChange::FlagChanged { .. } => {
+                    if let Some(f) = self.session.flag_history.last() {
+                        messages.push(self.message(&["flag"], flag_change_json(f)));
+                    }
+                }
+                SessionChange::PositionChanged {
+                    registration_number,
+                    ..
+                }
+                | SessionChange::LapCompleted {
+                    registration_number,
+                    ..
+                }
+                | SessionChange::CompetitorUpdated {
+                    registration_number,
+                } => {
+                    standings.insert(Standings::All);
+                    if let Some(class) = self
+                        .session
+                        .competitors
+                        .get(registration_number)
+                        .and_then(|c| c.class_number)
+                    {
+                        standings.insert(Standings::Class(class));
+                    }
+                }
+                SessionChange::Inconsistency(i) => {
+                    // Warnings are events, so a new subscriber should not receive a stale one
+                    let payload = warning_json(&self.session, i);
+                    messages.push(Message {
+                        retain: false,
+                        ..self.message(&["warning"], payload)
+                    });
+                }
+                SessionChange::NewBest(best) => {
+                    let payload = best_json(&self.session, best);
+                    messages.push(Message {
+                        retain: false,
+                        ..self.message(&["best"], payload)
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let order = self.session.session_type().session_type.standings_order();
+        for s in standings {
+            let (class, topic) = match s {
+                Standings::All => (None, "all".to_owned()),
+                Standings::Class(n) => (Some(n), n.to_string()),
+            };
+            let payload = standings_json(&self.session, order, class, |_| true);
+            messages.push(self.message(&["standings", &topic], payload));
+        }
+
+        messages
+    }
+
+    fn message(&self, path: &[&str], payload: serde_json::Value) -> Message {
+        let run = self
+            .session
+            .run
+            .as_ref()
+            .map_or_else(|| "none".to_owned(), |r| r.number.to_string());
+
+        let mut topic = self.prefix.clone();
+        for segment in core::iter::once(run.as_str()).chain(path.iter().copied()) {
+            topic.push('/');
+            topic.push_str(&topic_segment(segment));
+        }
+
+        Message {
+            topic,
+            payload: payload.to_string(),
+            retain: self.retain,
+        }
+    }
+
+    /// Applies a record to the session, and publishes the resulting messages with `client`.
+    pub async fn publish(
+        &mut self,
+        client: &AsyncClient,
+        record: &Record,
+    ) -> Result<(), ClientError> {
+        for message in self.messages(record) {
+            client
+                .publish(message.topic, self.qos, message.retain, message.payload)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Publishes the changes from every record in `records`, until the stream ends or returns an
+    /// error, or the MQTT client is closed.
+    pub async fn track<S, E>(mut self, client: AsyncClient, records: S)
+    where
+        S: Stream<Item = Result<Record, E>>,
+        E: Display,
+    {
+        futures::pin_mut!(records);
+        while let Some(record) = records.next().await {
+            let result = match record {
+                Ok(record) => self.publish(&client, &record).await,
+                Err(e) => {
+                    log::warn!("Stopped publishing records: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = result {
+                log::warn!("Stopped publishing records: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Replaces the characters which are not allowed in an MQTT topic level
+fn topic_segment(s: &str) -> String {
+    if s.is_empty() {
+        return "_".to_owned();
+    }
+    s.chars()
+        .map(|c| match c {
+            '/' | '+' | '#' | '\0' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, MqttOptions, Packet, PingResp, Publish};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use crate::session::fixtures::ENTRIES;
+
+    /// The shared entries, with a number which must be escaped in topics
+    fn lines() -> Vec<&'static str> {
+        [ENTRIES, &[r#"$A,"1","1/A",1,"John","Smith","",1"#]].concat()
+    }
+
+    fn publisher() -> MqttPublisher {
+        let mut publisher = MqttPublisher::new();
+        for line in lines() {
+            publisher.messages(&Record::decode(line).unwrap());
+        }
+        publisher
+    }
+
+    fn apply(publisher: &mut MqttPublisher, line: &str) -> Vec<Message> {
+        publisher.messages(&Record::decode(line).unwrap())
+    }
+
+    #[test]
+    fn test_publishes_passings_and_standings() {
+        let mut publisher = publisher();
+
+        let messages = apply(&mut publisher, r#"$J,"1","00:01:10.000","00:01:10.000""#);
+        let topics: Vec<_> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "rmonitor/5/passing/1_A",
+                "rmonitor/5/best",
+                "rmonitor/5/best",
+                "rmonitor/5/best",
+                "rmonitor/5/standings/all",
+                "rmonitor/5/standings/1",
+            ]
+        );
+        assert!(messages
+            .iter()
+            .all(|m| m.retain != m.topic.ends_with("/best")));
+
+        let passing: serde_json::Value = serde_json::from_str(&messages[0].payload).unwrap();
+        assert_eq!(passing["number"], "1/A");
+        assert_eq!(passing["lap"]["laptime_ms"], 70_000);
+        assert_eq!(passing["competitor"]["class_name"], "GT");
+        assert_eq!(passing["competitor"]["last_laptime_highlight"], "session");
+
+        // The first lap of the session is a personal, class and session best
+        let best: serde_json::Value = serde_json::from_str(&messages[3].payload).unwrap();
+        assert_eq!(best["scope"], "session");
+        assert_eq!(best["time_ms"], 70_000);
+        assert_eq!(best["previous_holder"], serde_json::Value::Null);
+
+        // Car 2 is in class 2, so the class 1 standings are unchanged
+        let messages = apply(&mut publisher, r#"$G,1,"2",1,"00:01:10.000""#);
+        let topics: Vec<_> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec!["rmonitor/5/standings/all", "rmonitor/5/standings/2"]
+        );
+        // A practice session is ordered by fastest lap
+        let standings: serde_json::Value = serde_json::from_str(&messages[0].payload).unwrap();
+        assert_eq!(standings[0]["number"], "1/A");
+        assert_eq!(standings[1]["number"], "2");
+
+        let messages = apply(&mut publisher, r#"$B,6,"Feature race""#);
+        assert!(messages.iter().all(|m| !m.topic.contains("standings")));
+        let messages = apply(&mut publisher, r#"$G,2,"1",1,"00:01:12.000""#);
+        let standings: serde_json::Value = serde_json::from_str(&messages[0].payload).unwrap();
+        assert_eq!(standings[0]["number"], "2");
+        assert_eq!(standings[1]["number"], "1/A");
+    }
+
+    #[test]
+    fn test_publishes_warnings() {
+        let mut publisher = publisher();
+
+        let messages = apply(
+            &mut publisher,
+            r#"$COR,"2","20",1,"00:01:10.000","+00:00:00.000""#,
+        );
+        let warning = messages
+            .iter()
+            .find(|m| m.topic == "rmonitor/5/warning")
+            .unwrap();
+        assert!(!warning.retain);
+
+        let warning: serde_json::Value = serde_json::from_str(&warning.payload).unwrap();
+        assert_eq!(warning["kind"], "correction_number_mismatch");
+        assert_eq!(warning["number"], "2");
+    }
+
+    #[test]
+    fn test_publishes_flag_changes() {
+        let mut publisher = publisher().with_prefix("paddock").with_retain(false);
+        let heartbeat = r#"$F,9999,"00:00:00","14:09:52","00:59:59","Green ""#;
+
+        let messages = apply(&mut publisher, heartbeat);
+        let topics: Vec<_> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(topics, vec!["paddock/5/session", "paddock/5/flag"]);
+        assert!(!messages[1].retain);
+        let flag: serde_json::Value = serde_json::from_str(&messages[1].payload).unwrap();
+        assert_eq!(flag["flag"], "Green");
+        assert_eq!(flag["time_of_day"], "14:09:52");
+
+        // Only the session clocks change with the next heartbeat
+        assert_eq!(apply(&mut publisher, heartbeat).len(), 1);
+    }
+
+    /// A minimal in-process broker, accepting a single client and forwarding its publishes
+    async fn broker() -> (u16, mpsc::UnboundedReceiver<Publish>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut read = BytesMut::new();
+            loop {
+                let packet = match Packet::read(&mut read, 1 << 20) {
+                    Ok(packet) => packet,
+                    Err(rumqttc::Error::InsufficientBytes(_)) => {
+                        if stream.read_buf(&mut read).await.unwrap() == 0 {
+                            return;
+                        }
+                        continue;
+                    }
+                    Err(e) => panic!("{:?}", e),
+                };
+
+                let mut write = BytesMut::new();
+                match packet {
+                    Packet::Connect(_) => {
+                        ConnAck::new(ConnectReturnCode::Success, false)
+                            .write(&mut write)
+                            .unwrap();
+                    }
+                    Packet::PingReq => {
+                        PingResp.write(&mut write).unwrap();
+                    }
+                    Packet::Publish(publish) => tx.send(publish).unwrap(),
+                    _ => {}
+                }
+                stream.write_all(&write).await.unwrap();
+            }
+        });
+
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn test_publishes_to_broker() {
+        let (port, mut published) = broker().await;
+        let (client, mut eventloop) =
+            AsyncClient::new(MqttOptions::new("rmonitor-test", "127.0.0.1", port), 16);
+        tokio::spawn(async move { while eventloop.poll().await.is_ok() {} });
+
+        let records = futures::stream::iter(
+            lines()
+                .into_iter()
+                .chain([r#"$F,9999,"00:00:00","14:09:52","00:59:59","Green ""#])
+                .map(|line| Record::decode(line).map_err(|e| e.to_string())),
+        );
+        MqttPublisher::new()
+            .with_qos(QoS::AtMostOnce)
+            .track(client, records)
+            .await;
+
+        let mut publish = published.recv().await.unwrap();
+        while publish.topic != "rmonitor/5/flag" {
+            publish = published.recv().await.unwrap();
+        }
+        assert_eq!(publish.qos, QoS::AtMostOnce);
+        assert!(publish.retain);
+        let flag: serde_json::Value = serde_json::from_slice(&publish.payload).unwrap();
+        assert_eq!(flag["flag"], "Green");
+    }
+}
@@ -0,0 +1,613 @@
+//! Position change and overtaking events, derived from the session model.
+//!
+//! The timing system sends a `Race` record for both the passing and the passed competitor, one
+//! after the other, so the raw record stream only shows the running order a car at a time. An
+//! [`OvertakeTracker`] waits for the order to settle, with no two competitors sharing a position,
+//! and compares it with the previous settled order to find who gained and lost places, overall
+//! and in their class, and who they changed places with.
+//!
+//! Position changes are told apart as on-track overtakes or the result of a pit stop where
+//! possible, see [`Cause`]. A competitor is taken to be pitting:
+//!
+//! - From crossing a pit lane timeline until crossing a track timeline, when the IMSA enhanced
+//!   protocol is in use. Pit lane timelines are those with a number starting with `P`, or with
+//!   `pit` in their name.
+//! - While its current lap is running, or the lap it last completed ran, more than 30% slower
+//!   than its fastest lap.
+//!
+//! # Example
+//!
+//! ```
+//! use rmonitor::overtakes::{OvertakeTracker, PositionEvent, Scope};
+//! use rmonitor::protocol::Record;
+//! use rmonitor::session::Session;
+//!
+//! let mut session = Session::new();
+//! let mut tracker = OvertakeTracker::new();
+//! let mut events = Vec::new();
+//! for line in &[
+//!     r#"$A,"12","12",52474,"John","Johnson","USA",1"#,
+//!     r#"$A,"7","7",52475,"Jane","Doe","GBR",1"#,
+//!     r#"$G,1,"12",3,"00:06:14.227""#,
+//!     r#"$G,2,"7",3,"00:06:15.512""#,
+//!     r#"$G,1,"7",4,"00:08:17.109""#,
+//!     r#"$G,2,"12",4,"00:08:18.340""#,
+//! ] {
+//!     let record = Record::decode(line).unwrap();
+//!     session.apply(&record);
+//!     events.extend(tracker.update(&session, &record));
+//! }
+//!
+//! assert!(events.contains(&PositionEvent::LeadChanged {
+//!     registration_number: "7".to_owned(),
+//!     scope: Scope::Overall,
+//!     previous: "12".to_owned(),
+//! }));
+//! ```
+use alloc::borrow::ToOwned;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+use crate::protocol::*;
+use crate::session::{CompetitorState, Session};
+
+/// How much slower than a competitor's fastest lap a lap must be to be taken as a pit stop
+const PIT_LAP_RATIO: f64 = 1.3;
+
+/// The standings a position is counted in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// The overall running order
+    Overall,
+    /// The running order of a single class, by class number
+    Class(u8),
+}
+
+/// Why a competitor's position changed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cause {
+    /// The competitor changed places on track with at least one competitor, neither of which was
+    /// pitting
+    OnTrack,
+    /// Every competitor it changed places with was pitting, or it was pitting itself
+    PitStop,
+    /// It didn't change places with any competitor, e.g. a competitor joined the order ahead
+    Other,
+}
+
+impl Cause {
+    /// Returns the name of the cause, e.g. `"on_track"`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cause::OnTrack => "on_track",
+            Cause::PitStop => "pit_stop",
+            Cause::Other => "other",
+        }
+    }
+}
+
+/// A change in the running order, as returned by [`OvertakeTracker::update`]
+///
+/// Positions count from 1 within the scope, among the competitors which have a position.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PositionEvent {
+    /// A competitor moved up the order
+    PositionGained {
+        registration_number: String,
+        scope: Scope,
+        previous: u16,
+        position: u16,
+        /// The competitors which were ahead and are now behind
+        passed: Vec<String>,
+        cause: Cause,
+    },
+    /// A competitor moved down the order
+    PositionLost {
+        registration_number: String,
+        scope: Scope,
+        previous: u16,
+        position: u16,
+        /// The competitors which were behind and are now ahead
+        passed_by: Vec<String>,
+        cause: Cause,
+    },
+    /// A different competitor took the lead
+    LeadChanged {
+        registration_number: String,
+        scope: Scope,
+        /// The previous leader
+        previous: String,
+    },
+}
+
+impl PositionEvent {
+    /// Returns the name of the event variant, e.g. `"position_gained"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PositionEvent::PositionGained { .. } => "position_gained",
+            PositionEvent::PositionLost { .. } => "position_lost",
+            PositionEvent::LeadChanged { .. } => "lead_changed",
+        }
+    }
+
+    /// Returns the registration number of the competitor the event is about
+    pub fn registration_number(&self) -> &str {
+        match self {
+            PositionEvent::PositionGained {
+                registration_number,
+                ..
+            }
+            | PositionEvent::PositionLost {
+                registration_number,
+                ..
+            }
+            | PositionEvent::LeadChanged {
+                registration_number,
+                ..
+            } => registration_number,
+        }
+    }
+
+    /// Returns the standings the event applies to
+    pub fn scope(&self) -> Scope {
+        match self {
+            PositionEvent::PositionGained { scope, .. }
+            | PositionEvent::PositionLost { scope, .. }
+            | PositionEvent::LeadChanged { scope, .. } => *scope,
+        }
+    }
+}
+
+/// Derives [`PositionEvent`]s from a [`Session`] as records are applied to it.
+///
+/// The tracker starts afresh with an `Init` record or a `Run` record for a different run, so it
+/// can follow the current session of a [`SessionManager`].
+///
+/// [`SessionManager`]: crate::session::SessionManager
+#[derive(Clone, Debug, Default)]
+pub struct OvertakeTracker {
+    /// Registration numbers in the last settled running order
+    order: Vec<String>,
+    run: Option<Run>,
+    /// Competitors whose latest line crossing was in the pit lane, by registration number
+    in_pit_lane: BTreeSet<String>,
+}
+
+impl OvertakeTracker {
+    /// Returns an `OvertakeTracker` which hasn't seen a running order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the events resulting from a record, which must already have been applied to
+    /// `session`.
+    pub fn update(&mut self, session: &Session, record: &Record) -> Vec<PositionEvent> {
+        match record {
+            Record::Init(_) => *self = Self::new(),
+            Record::Run(r) if self.run.as_ref() != Some(r) => {
+                *self = Self::new();
+                self.run = Some(r.clone());
+            }
+            Record::LineCrossing(r) => {
+                // Line crossings identify the competitor by number rather than registration
+                let registration_number = session
+                    .competitor_by_number(&r.number)
+                    .map_or(&r.number, |c| &c.registration_number);
+                if is_pit_timeline(r) {
+                    self.in_pit_lane.insert(registration_number.clone());
+                } else {
+                    self.in_pit_lane.remove(registration_number);
+                }
+            }
+            Record::Race(_) => return self.compare(session),
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn compare(&mut self, session: &Session) -> Vec<PositionEvent> {
+        let mut ranked: Vec<&CompetitorState> = session
+            .competitors
+            .values()
+            .filter(|c| c.race_position.is_some())
+            .collect();
+        ranked.sort_by_key(|c| c.race_position);
+
+        // Part way through the records for an overtake, two competitors share a position
+        if ranked
+            .windows(2)
+            .any(|w| w[0].race_position == w[1].race_position)
+        {
+            return Vec::new();
+        }
+
+        let now = session
+            .competitors
+            .values()
+            .filter_map(|c| c.total_time)
+            .max();
+        let pitting: BTreeSet<&str> = ranked
+            .iter()
+            .filter(|c| self.in_pit_lane.contains(&c.registration_number) || slow_lap(c, now))
+            .map(|c| c.registration_number.as_str())
+            .collect();
+
+        let class = |registration_number: &str| {
+            session
+                .competitors
+                .get(registration_number)
+                .and_then(|c| c.class_number)
+        };
+        let previous: Vec<&str> = self.order.iter().map(String::as_str).collect();
+        let current: Vec<&str> = ranked
+            .iter()
+            .map(|c| c.registration_number.as_str())
+            .collect();
+
+        let mut events = Vec::new();
+        compare_orders(Scope::Overall, &previous, &current, &pitting, &mut events);
+
+        let classes: BTreeSet<u8> = current.iter().filter_map(|r| class(r)).collect();
+        for number in classes {
+            let in_class = |r: &&str| class(r) == Some(number);
+            let previous: Vec<&str> = previous.iter().copied().filter(in_class).collect();
+            let current: Vec<&str> = current.iter().copied().filter(in_class).collect();
+            compare_orders(
+                Scope::Class(number),
+                &previous,
+                &current,
+                &pitting,
+                &mut events,
+            );
+        }
+
+        self.order = current.into_iter().map(ToOwned::to_owned).collect();
+        events
+    }
+}
+
+/// Returns `true` if a line crossing was at a pit lane timeline
+fn is_pit_timeline(crossing: &LineCrossing) -> bool {
+    crossing.timeline_number.starts_with(['P', 'p'])
+        || crossing.timeline_name.to_ascii_lowercase().contains("pit")
+}
+
+/// Returns `true` if the competitor's current or last lap is slow enough to include a pit stop,
+/// where `now` is the latest total time in the session
+fn slow_lap(c: &CompetitorState, now: Option<Duration>) -> bool {
+    let threshold = match c.lap_history.iter().map(|l| l.laptime).min() {
+        Some(fastest) => fastest.mul_f64(PIT_LAP_RATIO),
+        None => return false,
+    };
+    let current = now
+        .zip(c.total_time)
+        .and_then(|(now, total_time)| now.checked_sub(total_time));
+    c.last_laptime.is_some_and(|t| t > threshold) || current.is_some_and(|t| t > threshold)
+}
+
+/// Adds the events for the changes between two running orders of the same scope
+fn compare_orders(
+    scope: Scope,
+    previous: &[&str],
+    current: &[&str],
+    pitting: &BTreeSet<&str>,
+    events: &mut Vec<PositionEvent>,
+) {
+    if let (Some(&leader), Some(&previous_leader)) = (current.first(), previous.first()) {
+        if leader != previous_leader {
+            events.push(PositionEvent::LeadChanged {
+                registration_number: leader.to_owned(),
+                scope,
+                previous: previous_leader.to_owned(),
+            });
+        }
+    }
+
+    let before: BTreeMap<&str, usize> = previous.iter().enumerate().map(|(i, r)| (*r, i)).collect();
+    let after: BTreeMap<&str, usize> = current.iter().enumerate().map(|(i, r)| (*r, i)).collect();
+
+    // Competitors which changed places with `registration_number`, in their current order
+    let swapped = |registration_number: &str, gained: bool| -> Vec<String> {
+        let (b, a) = (before[registration_number], after[registration_number]);
+        current
+            .iter()
+            .filter(|r| {
+                before.get(*r).is_some_and(|&other| {
+                    if gained {
+                        other < b && after[*r] > a
+                    } else {
+                        other > b && after[*r] < a
+                    }
+                })
+            })
+            .map(|r| (*r).to_owned())
+            .collect()
+    };
+    let cause = |registration_number: &str, others: &[String]| {
+        let on_track = |other: &String| {
+            !pitting.contains(registration_number) && !pitting.contains(other.as_str())
+        };
+        if others.is_empty() {
+            Cause::Other
+        } else if others.iter().any(on_track) {
+            Cause::OnTrack
+        } else {
+            Cause::PitStop
+        }
+    };
+
+    for (a, &registration_number) in current.iter().enumerate() {
+        let b = match before.get(registration_number) {
+            Some(&b) => b,
+            None => continue,
+        };
+        let (previous, position) = (position(b), position(a));
+        if a < b {
+            let passed = swapped(registration_number, true);
+            events.push(PositionEvent::PositionGained {
+                registration_number: registration_number.to_owned(),
+                scope,
+                previous,
+                position,
+                cause: cause(registration_number, &passed),
+                passed,
+            });
+        } else if a > b {
+            let passed_by = swapped(registration_number, false);
+            events.push(PositionEvent::PositionLost {
+                registration_number: registration_number.to_owned(),
+                scope,
+                previous,
+                position,
+                cause: cause(registration_number, &passed_by),
+                passed_by,
+            });
+        }
+    }
+}
+
+/// Converts an index in a running order to a position, counting from 1
+fn position(index: usize) -> u16 {
+    u16::try_from(index + 1).unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPETITORS: &[&str] = &[
+        r#"$A,"1","1",1,"","","",1"#,
+        r#"$A,"2","2",2,"","","",1"#,
+        r#"$A,"3","3",3,"","","",2"#,
+        r#"$G,1,"1",2,"00:04:00.000""#,
+        r#"$G,2,"2",2,"00:04:01.000""#,
+        r#"$G,3,"3",2,"00:04:02.000""#,
+    ];
+
+    fn track(
+        tracker: &mut OvertakeTracker,
+        session: &mut Session,
+        lines: &[&str],
+    ) -> Vec<PositionEvent> {
+        let mut events = Vec::new();
+        for line in lines {
+            let record = Record::decode(line).unwrap();
+            session.apply(&record);
+            events.extend(tracker.update(session, &record));
+        }
+        events
+    }
+
+    #[test]
+    fn test_reports_overtakes() {
+        let mut session = Session::new();
+        let mut tracker = OvertakeTracker::new();
+        assert!(track(&mut tracker, &mut session, COMPETITORS).is_empty());
+
+        // Nothing is reported until the passed competitor's record arrives
+        assert!(track(
+            &mut tracker,
+            &mut session,
+            &[r#"$G,2,"3",3,"00:05:58.000""#]
+        )
+        .is_empty());
+        let events = track(
+            &mut tracker,
+            &mut session,
+            &[r#"$G,3,"2",2,"00:04:01.000""#],
+        );
+        assert_eq!(
+            events,
+            vec![
+                PositionEvent::PositionGained {
+                    registration_number: "3".to_owned(),
+                    scope: Scope::Overall,
+                    previous: 3,
+                    position: 2,
+                    passed: vec!["2".to_owned()],
+                    cause: Cause::OnTrack,
+                },
+                PositionEvent::PositionLost {
+                    registration_number: "2".to_owned(),
+                    scope: Scope::Overall,
+                    previous: 2,
+                    position: 3,
+                    passed_by: vec!["3".to_owned()],
+                    cause: Cause::OnTrack,
+                },
+            ]
+        );
+
+        // A lead change in the overall and class standings
+        let events = track(
+            &mut tracker,
+            &mut session,
+            &[
+                r#"$G,1,"2",3,"00:05:59.000""#,
+                r#"$G,2,"1",2,"00:04:00.000""#,
+                r#"$G,3,"3",3,"00:05:58.000""#,
+            ],
+        );
+        let leads: Vec<_> = events
+            .iter()
+            .filter(|e| e.kind() == "lead_changed")
+            .collect();
+        assert_eq!(
+            leads,
+            vec![
+                &PositionEvent::LeadChanged {
+                    registration_number: "2".to_owned(),
+                    scope: Scope::Overall,
+                    previous: "1".to_owned(),
+                },
+                &PositionEvent::LeadChanged {
+                    registration_number: "2".to_owned(),
+                    scope: Scope::Class(1),
+                    previous: "1".to_owned(),
+                },
+            ]
+        );
+        // Competitor 3 is alone in its class
+        assert!(events.iter().all(|e| e.scope() != Scope::Class(2)));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PositionEvent::PositionLost {
+                registration_number,
+                scope: Scope::Overall,
+                passed_by,
+                ..
+            } if registration_number == "3" && passed_by == &["2"]
+        )));
+    }
+
+    #[test]
+    fn test_attributes_pit_stops() {
+        let mut session = Session::new();
+        let mut tracker = OvertakeTracker::new();
+        track(&mut tracker, &mut session, COMPETITORS);
+
+        let events = track(
+            &mut tracker,
+            &mut session,
+            &[
+                r#"$L,"1","P1","PIT IN","01/27/2009","14:19:24.117""#,
+                r#"$G,1,"2",3,"00:06:01.000""#,
+                r#"$G,2,"1",2,"00:04:00.000""#,
+            ],
+        );
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PositionEvent::PositionLost {
+                registration_number,
+                cause: Cause::PitStop,
+                ..
+            } if registration_number == "1"
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PositionEvent::PositionGained {
+                registration_number,
+                cause: Cause::PitStop,
+                ..
+            } if registration_number == "2"
+        )));
+
+        // Back on track
+        let events = track(
+            &mut tracker,
+            &mut session,
+            &[
+                r#"$L,"1","T1","SFT","01/27/2009","14:21:24.117""#,
+                r#"$G,1,"1",3,"00:06:00.000""#,
+                r#"$G,2,"2",3,"00:06:01.000""#,
+            ],
+        );
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PositionEvent::PositionGained {
+                registration_number,
+                cause: Cause::OnTrack,
+                ..
+            } if registration_number == "1"
+        )));
+    }
+
+    #[test]
+    fn test_attributes_slow_laps_to_pit_stops() {
+        let mut session = Session::new();
+        let mut tracker = OvertakeTracker::new();
+        track(&mut tracker, &mut session, COMPETITORS);
+        track(
+            &mut tracker,
+            &mut session,
+            &[
+                r#"$J,"1","00:02:00.000","00:04:00.000""#,
+                r#"$J,"2","00:02:00.000","00:04:01.000""#,
+            ],
+        );
+
+        // Competitor 1 is well overdue when competitor 2 completes its next lap
+        let events = track(
+            &mut tracker,
+            &mut session,
+            &[
+                r#"$J,"2","00:03:00.000","00:07:01.000""#,
+                r#"$G,1,"2",3,"00:07:01.000""#,
+                r#"$G,2,"1",2,"00:04:00.000""#,
+            ],
+        );
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PositionEvent::PositionLost {
+                registration_number,
+                cause: Cause::PitStop,
+                ..
+            } if registration_number == "1"
+        )));
+    }
+
+    #[test]
+    fn test_resets_on_new_run() {
+        let mut session = Session::new();
+        let mut tracker = OvertakeTracker::new();
+        track(&mut tracker, &mut session, &[r#"$B,1,"Race 1""#]);
+        track(&mut tracker, &mut session, COMPETITORS);
+
+        let events = track(
+            &mut tracker,
+            &mut session,
+            &[
+                r#"$B,2,"Race 2""#,
+                r#"$G,1,"3",0,"00:00:00.000""#,
+                r#"$G,2,"2",0,"00:00:00.000""#,
+                r#"$G,3,"1",0,"00:00:00.000""#,
+            ],
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_applies_sample_session() {
+        let data = std::fs::read_to_string("sample/2009_Sebring_ALMS_Session_5.txt").unwrap();
+        let mut session = Session::new();
+        let mut tracker = OvertakeTracker::new();
+        let mut events = Vec::new();
+        for line in data.lines() {
+            let record = Record::decode(line).unwrap();
+            session.apply(&record);
+            events.extend(tracker.update(&session, &record));
+        }
+
+        let cause = |cause| {
+            events
+                .iter()
+                .any(|e| matches!(e, PositionEvent::PositionGained { cause: c, .. } if *c == cause))
+        };
+        assert!(cause(Cause::OnTrack));
+        assert!(cause(Cause::PitStop));
+        assert!(events.iter().any(|e| e.kind() == "lead_changed"));
+    }
+}
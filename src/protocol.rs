@@ -6,7 +6,7 @@
 //! use rmonitor::protocol::Record;
 //!
 //! let data = r#"$A,"1234BE","12X",52474,"John","Johnson","USA",5"#;
-//! let record = Record::decode(&data);
+//! let record = Record::decode(data);
 //!
 //! assert!(record.is_ok());
 //! assert!(matches!(record, Ok(Record::Competitor(_))));
@@ -22,8 +22,12 @@
 //! }
 //! ```
 
-use std::num::ParseIntError;
-use std::str::FromStr;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::num::ParseIntError;
+use core::str::FromStr;
 use thiserror::Error;
 
 /// RMonitor commands are represented in messages by ASCII strings
@@ -51,71 +55,260 @@ pub enum RecordError {
     /// The record prefix was not recognised as a valid record type
     #[error("unknown record type {}", .0)]
     UnknownRecordType(String),
-    /// The input could not be decoded as the record type indicated by the prefix
-    #[error("malformed record")]
-    MalformedRecord,
-    /// A heartbeat record included an unrecognised flag state
-    #[error("unknown flag state '{}'", .0)]
-    UnknownFlagState(String),
+    /// The record had a different number of fields than specified for its type
+    ///
+    /// Field counts exclude the command, and for the variadic IMSA records `expected` is the
+    /// minimum number of fields.
+    #[error("malformed {record} record, expected {expected} fields but found {found}")]
+    MalformedRecord {
+        /// The record type, e.g. `"Competitor"`
+        record: &'static str,
+        expected: usize,
+        found: usize,
+    },
     /// A numeric record field could't be parsed as an integer
-    #[error("invalid integer field")]
-    InvalidIntegerField(#[from] ParseIntError),
+    #[error("invalid integer field {record}::{field} at index {index}: {value:?}")]
+    InvalidIntegerField {
+        /// The record type, e.g. `"Competitor"`
+        record: &'static str,
+        /// The field name, e.g. `"transponder_number"`
+        field: &'static str,
+        /// The position of the field in the line, where the command is at index 0
+        index: usize,
+        /// The raw field value
+        value: String,
+        source: ParseIntError,
+    },
     /// An IMSA track description record had a different number of sections than specified
-    #[error("track description had different number of sections than specified")]
-    IncorrectSectionCount,
+    #[error("track description specified {expected} sections but had {found}")]
+    IncorrectSectionCount { expected: usize, found: usize },
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+impl RecordError {
+    /// Returns the name of the error variant, e.g. `"malformed_record"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RecordError::UnknownRecordType(_) => "unknown_record_type",
+            RecordError::MalformedRecord { .. } => "malformed_record",
+            RecordError::InvalidIntegerField { .. } => "invalid_integer_field",
+            RecordError::IncorrectSectionCount { .. } => "incorrect_section_count",
+        }
+    }
+}
+
+/// A deviation from the specification which lenient decoding accepted, see
+/// [`Record::decode_lenient`]
+///
+/// Field counts exclude the command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// The record had more fields than specified, the extras are kept in its `extra` field
+    ExtraFields { expected: usize, found: usize },
+    /// The record had fewer fields than specified, the missing trailing fields were optional or,
+    /// for a track description, sections which were left out
+    MissingFields { expected: usize, found: usize },
+}
+
+impl DecodeWarning {
+    /// Returns the name of the warning variant, e.g. `"extra_fields"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DecodeWarning::ExtraFields { .. } => "extra_fields",
+            DecodeWarning::MissingFields { .. } => "missing_fields",
+        }
+    }
+}
+
+impl fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeWarning::ExtraFields { expected, found } => write!(
+                f,
+                "expected {} fields but found {}, kept the extra fields",
+                expected, found
+            ),
+            DecodeWarning::MissingFields { expected, found } => write!(
+                f,
+                "expected {} fields but found {}, left out the missing fields",
+                expected, found
+            ),
+        }
+    }
+}
+
+/// The flag state of a session.
+///
+/// The protocol defines five fixed-width states, but timing systems send others, in any case and
+/// with or without padding. States which aren't recognised are kept as [`Flag::Other`], rather
+/// than failing the whole heartbeat.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Flag {
     None,
     Green,
     Yellow,
     Red,
     Finish,
+    /// Sent by some systems before a session starts
+    Purple,
+    Warmup,
+    /// A full course caution
+    Caution,
+    /// The last lap
+    White,
+    /// An unrecognised state, trimmed of whitespace
+    Other(String),
+}
+
+impl Flag {
+    /// Returns the representation of the flag state used on the wire, padded to the six
+    /// characters of the protocol's fixed-width states.
+    ///
+    /// `Caution` is longer than six characters, so it's the exception and is sent unpadded, as
+    /// are unrecognised states.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Flag::None => "      ",
+            Flag::Green => "Green ",
+            Flag::Yellow => "Yellow",
+            Flag::Red => "Red   ",
+            Flag::Finish => "Finish",
+            Flag::Purple => "Purple",
+            Flag::Warmup => "Warmup",
+            Flag::Caution => "Caution",
+            Flag::White => "White ",
+            Flag::Other(s) => s,
+        }
+    }
+
+    /// Returns the name of the flag state, e.g. `"Green"`, or `"None"` without a flag
+    pub fn name(&self) -> &str {
+        match self {
+            Flag::None => "None",
+            Flag::Other(s) => s,
+            flag => flag.as_str().trim_end(),
+        }
+    }
+}
+
+impl From<&str> for Flag {
+    fn from(s: &str) -> Self {
+        // Flag states should be fixed width, with trailing spaces, but aren't always
+        let s = s.trim();
+        let is = |name: &str| s.eq_ignore_ascii_case(name);
+        if s.is_empty() || is("none") {
+            Flag::None
+        } else if is("green") {
+            Flag::Green
+        } else if is("yellow") {
+            Flag::Yellow
+        } else if is("red") {
+            Flag::Red
+        } else if is("finish") || is("checkered") || is("chequered") {
+            Flag::Finish
+        } else if is("purple") {
+            Flag::Purple
+        } else if is("warmup") || is("warm up") || is("warm-up") {
+            Flag::Warmup
+        } else if is("caution") {
+            Flag::Caution
+        } else if is("white") {
+            Flag::White
+        } else {
+            Flag::Other(s.to_owned())
+        }
+    }
 }
 
 impl FromStr for Flag {
-    type Err = RecordError;
+    type Err = core::convert::Infallible;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Flag states are fixed width, with trailing spaces
-        match s {
-            "      " => Ok(Flag::None),
-            "Green " => Ok(Flag::Green),
-            "Yellow" => Ok(Flag::Yellow),
-            "Red   " => Ok(Flag::Red),
-            "Finish" => Ok(Flag::Finish),
-            _ => Err(RecordError::UnknownFlagState(s.to_owned())),
-        }
+        Ok(s.into())
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Serialized as the name, so unrecognised states are plain strings too
+#[cfg(feature = "serde")]
+impl serde::Serialize for Flag {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Flag {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <alloc::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(Flag::from(&*name))
     }
 }
 
 /// Implemented for types which can be constructed from the comma-separated parts of an RMonitor
 /// line.
 trait FromParts: Sized {
-    fn decode(parts: &[&str]) -> Result<Self, RecordError>;
+    /// Decodes the record, tolerating a different number of fields than specified if `lenient`
+    fn decode(parts: &[&str], lenient: bool) -> Result<(Self, Option<DecodeWarning>), RecordError>;
 }
 
 macro_rules! decode_impl {
     ($type:ident, $count:expr, $($field:ident),+) => (
         impl FromParts for $type {
-            fn decode(parts: &[&str]) -> Result<Self, RecordError> {
-                if parts.len() != $count {
-                    return Err(RecordError::MalformedRecord);
-                }
+            fn decode(
+                parts: &[&str],
+                lenient: bool,
+            ) -> Result<(Self, Option<DecodeWarning>), RecordError> {
+                // Counts in warnings exclude the command
+                let warning = match parts.len() {
+                    n if n == $count => None,
+                    n if !lenient => {
+                        return Err(RecordError::MalformedRecord {
+                            record: stringify!($type),
+                            expected: $count - 1,
+                            found: n - 1,
+                        })
+                    }
+                    n if n > $count => Some(DecodeWarning::ExtraFields {
+                        expected: $count - 1,
+                        found: n - 1,
+                    }),
+                    n => Some(DecodeWarning::MissingFields {
+                        expected: $count - 1,
+                        found: n - 1,
+                    }),
+                };
 
                 // A little clunky, but should optimize out
                 let mut idx = 0;
                 $(
                     idx += 1;
-                    let $field = parts[idx].decode()?;
+                    let $field = match parts.get(idx) {
+                        Some(_) => decode_field(parts, stringify!($type), stringify!($field), idx)?,
+                        // Only optional fields can be left out, even leniently
+                        None => missing_field().ok_or(RecordError::MalformedRecord {
+                            record: stringify!($type),
+                            expected: $count - 1,
+                            found: parts.len() - 1,
+                        })?,
+                    };
                 )*
 
-                Ok(Self {
-                    $(
-                        $field,
-                    )*
-                })
+                let extra = parts.iter().skip($count).map(|p| (*p).to_owned()).collect();
+
+                Ok((
+                    Self {
+                        $(
+                            $field,
+                        )*
+                        extra,
+                    },
+                    warning,
+                ))
             }
         }
     )
@@ -123,51 +316,159 @@ macro_rules! decode_impl {
 
 /// Implemented for types which can be constructed from a single RMonitor message part.
 trait FieldExt<T> {
-    fn decode(self) -> Result<T, RecordError>;
+    fn decode(self) -> Result<T, ParseIntError>;
+
+    /// Returns the value of the field when it's left out of a record, if it's optional
+    fn missing() -> Option<T> {
+        None
+    }
 }
 
 impl FieldExt<String> for &str {
-    fn decode(self) -> Result<String, RecordError> {
+    fn decode(self) -> Result<String, ParseIntError> {
         Ok(self.trim_matches('"').to_owned())
     }
 }
 
 impl FieldExt<Flag> for &str {
-    fn decode(self) -> Result<Flag, RecordError> {
-        self.trim_matches('"').parse()
-    }
-}
-
-impl FieldExt<u32> for &str {
-    fn decode(self) -> Result<u32, RecordError> {
-        Ok(self.parse()?)
+    fn decode(self) -> Result<Flag, ParseIntError> {
+        Ok(self.trim_matches('"').into())
     }
 }
 
 impl FieldExt<Option<u32>> for &str {
-    fn decode(self) -> Result<Option<u32>, RecordError> {
+    fn decode(self) -> Result<Option<u32>, ParseIntError> {
         if self.is_empty() {
             Ok(None)
         } else {
             Ok(Some(self.parse()?))
         }
     }
+
+    fn missing() -> Option<Option<u32>> {
+        Some(None)
+    }
+}
+
+macro_rules! field_decode_integer {
+    ($($type:ty),+) => (
+        $(
+            impl FieldExt<$type> for &str {
+                fn decode(self) -> Result<$type, ParseIntError> {
+                    self.parse()
+                }
+            }
+        )*
+    )
+}
+
+field_decode_integer!(u32, u16, u8, usize);
+
+/// Returns the value of a field which was left out of a record, or `None` if it's required.
+fn missing_field<T>() -> Option<T>
+where
+    for<'a> &'a str: FieldExt<T>,
+{
+    <&str as FieldExt<T>>::missing()
+}
+
+/// Decodes the part at `index` as a field of `record`, describing the field in any error.
+///
+/// The part must be present, callers check the number of parts first.
+fn decode_field<'a, T>(
+    parts: &[&'a str],
+    record: &'static str,
+    field: &'static str,
+    index: usize,
+) -> Result<T, RecordError>
+where
+    &'a str: FieldExt<T>,
+{
+    let value = parts[index];
+    value
+        .decode()
+        .map_err(|source| RecordError::InvalidIntegerField {
+            record,
+            field,
+            index,
+            value: value.to_owned(),
+            source,
+        })
+}
+
+/// Implemented for types which can be written out as the comma-separated parts of an RMonitor
+/// line.
+trait ToParts {
+    fn encode(&self, out: &mut String);
+}
+
+macro_rules! encode_impl {
+    ($type:ident, $command:expr, $($field:ident),+) => (
+        impl ToParts for $type {
+            fn encode(&self, out: &mut String) {
+                out.push_str($command);
+                $(
+                    out.push(',');
+                    self.$field.encode(out);
+                )*
+                for extra in &self.extra {
+                    out.push(',');
+                    out.push_str(extra);
+                }
+            }
+        }
+    )
+}
+
+/// Implemented for types which can be written as a single RMonitor message part.
+trait FieldEncode {
+    fn encode(&self, out: &mut String);
+}
+
+impl FieldEncode for String {
+    fn encode(&self, out: &mut String) {
+        out.push('"');
+        out.push_str(self);
+        out.push('"');
+    }
 }
 
-impl FieldExt<u16> for &str {
-    fn decode(self) -> Result<u16, RecordError> {
-        Ok(self.parse()?)
+impl FieldEncode for Flag {
+    fn encode(&self, out: &mut String) {
+        out.push('"');
+        out.push_str(self.as_str());
+        out.push('"');
     }
 }
 
-impl FieldExt<u8> for &str {
-    fn decode(self) -> Result<u8, RecordError> {
-        Ok(self.parse()?)
+macro_rules! field_encode_integer {
+    ($($type:ty),+) => (
+        $(
+            impl FieldEncode for $type {
+                fn encode(&self, out: &mut String) {
+                    // Writing to a String cannot fail
+                    let _ = write!(out, "{}", self);
+                }
+            }
+        )*
+    )
+}
+
+field_encode_integer!(u32, u16, u8);
+
+impl<T: FieldEncode> FieldEncode for Option<T> {
+    fn encode(&self, out: &mut String) {
+        // Absent optional fields are left empty
+        if let Some(value) = self {
+            value.encode(out);
+        }
     }
 }
 
 /// A unit of data from the RMonitor protocol
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 pub enum Record {
     Heartbeat(Heartbeat),
     Competitor(Competitor),
@@ -187,35 +488,159 @@ pub enum Record {
 impl Record {
     /// Decodes a record from a single line of valid UTF-8 text
     pub fn decode(line: &str) -> Result<Self, RecordError> {
+        Self::decode_with(line, false).map(|(record, _)| record)
+    }
+
+    /// Decodes a record from a single line of valid UTF-8 text, tolerating vendor deviations in
+    /// the number of fields
+    ///
+    /// Fields beyond those specified are kept verbatim in the record's `extra` field, and written
+    /// back out by [`Record::encode`]. Missing trailing fields are only accepted if they're
+    /// optional, a record missing a required field still fails to decode, except that a track
+    /// description may have fewer sections than it specifies. Either deviation is returned as a
+    /// [`DecodeWarning`] alongside the record.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rmonitor::protocol::{DecodeWarning, Record};
+    ///
+    /// let (record, warning) = Record::decode_lenient(r#"$B,5,"Practice","X""#).unwrap();
+    ///
+    /// assert!(matches!(record, Record::Run(run) if run.extra == vec![r#""X""#]));
+    /// assert_eq!(warning, Some(DecodeWarning::ExtraFields { expected: 2, found: 3 }));
+    /// ```
+    pub fn decode_lenient(line: &str) -> Result<(Self, Option<DecodeWarning>), RecordError> {
+        Self::decode_with(line, true)
+    }
+
+    fn decode_with(
+        line: &str,
+        lenient: bool,
+    ) -> Result<(Self, Option<DecodeWarning>), RecordError> {
         let splits: Vec<&str> = line.split(',').collect();
 
-        if splits.len() < 2 {
-            return Err(RecordError::MalformedRecord);
+        fn decode<T: FromParts, F: FnOnce(T) -> Record>(
+            splits: &[&str],
+            lenient: bool,
+            variant: F,
+        ) -> Result<(Record, Option<DecodeWarning>), RecordError> {
+            let (record, warning) = T::decode(splits, lenient)?;
+            Ok((variant(record), warning))
         }
 
         match splits[0] {
-            command::HEARTBEAT => Ok(Record::Heartbeat(Heartbeat::decode(&splits)?)),
-            command::COMPETITOR => Ok(Record::Competitor(Competitor::decode(&splits)?)),
-            command::COMPETITOR_EXT => Ok(Record::CompetitorExt(CompetitorExt::decode(&splits)?)),
-            command::RUN => Ok(Record::Run(Run::decode(&splits)?)),
-            command::CLASS => Ok(Record::Class(Class::decode(&splits)?)),
-            command::SETTING => Ok(Record::Setting(Setting::decode(&splits)?)),
-            command::RACE => Ok(Record::Race(Race::decode(&splits)?)),
-            command::PRAC_QUAL => Ok(Record::PracticeQual(PracticeQual::decode(&splits)?)),
-            command::INIT => Ok(Record::Init(Init::decode(&splits)?)),
-            command::PASSING => Ok(Record::Passing(Passing::decode(&splits)?)),
-            command::CORRECTION => Ok(Record::Correction(Correction::decode(&splits)?)),
-            command::LINE_CROSSING => Ok(Record::LineCrossing(LineCrossing::decode(&splits)?)),
-            command::TRACK_DESCRIPTION => {
-                Ok(Record::TrackDescription(TrackDescription::decode(&splits)?))
-            }
+            command::HEARTBEAT => decode(&splits, lenient, Record::Heartbeat),
+            command::COMPETITOR => decode(&splits, lenient, Record::Competitor),
+            command::COMPETITOR_EXT => decode(&splits, lenient, Record::CompetitorExt),
+            command::RUN => decode(&splits, lenient, Record::Run),
+            command::CLASS => decode(&splits, lenient, Record::Class),
+            command::SETTING => decode(&splits, lenient, Record::Setting),
+            command::RACE => decode(&splits, lenient, Record::Race),
+            command::PRAC_QUAL => decode(&splits, lenient, Record::PracticeQual),
+            command::INIT => decode(&splits, lenient, Record::Init),
+            command::PASSING => decode(&splits, lenient, Record::Passing),
+            command::CORRECTION => decode(&splits, lenient, Record::Correction),
+            command::LINE_CROSSING => decode(&splits, lenient, Record::LineCrossing),
+            command::TRACK_DESCRIPTION => decode(&splits, lenient, Record::TrackDescription),
             _ => Err(RecordError::UnknownRecordType(splits[0].to_owned())),
         }
     }
+
+    /// Returns the name of the record type, e.g. `"heartbeat"` or `"passing"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Record::Heartbeat(_) => "heartbeat",
+            Record::Competitor(_) => "competitor",
+            Record::CompetitorExt(_) => "competitor_ext",
+            Record::Run(_) => "run",
+            Record::Class(_) => "class",
+            Record::Setting(_) => "setting",
+            Record::Race(_) => "race",
+            Record::PracticeQual(_) => "practice_qual",
+            Record::Init(_) => "init",
+            Record::Passing(_) => "passing",
+            Record::Correction(_) => "correction",
+            Record::LineCrossing(_) => "line_crossing",
+            Record::TrackDescription(_) => "track_description",
+        }
+    }
+
+    /// Encodes the record as a single line of RMonitor text, without a line terminator
+    ///
+    /// String fields are written verbatim between quotes, the protocol has no escaping mechanism
+    /// so values containing `"` or `,` will not survive a round trip through [`Record::decode`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rmonitor::protocol::Record;
+    ///
+    /// let data = r#"$B,5,"Friday free practice""#;
+    /// let record = Record::decode(data).unwrap();
+    ///
+    /// assert_eq!(record.encode(), data);
+    /// ```
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        match self {
+            Record::Heartbeat(r) => r.encode(&mut out),
+            Record::Competitor(r) => r.encode(&mut out),
+            Record::CompetitorExt(r) => r.encode(&mut out),
+            Record::Run(r) => r.encode(&mut out),
+            Record::Class(r) => r.encode(&mut out),
+            Record::Setting(r) => r.encode(&mut out),
+            Record::Race(r) => r.encode(&mut out),
+            Record::PracticeQual(r) => r.encode(&mut out),
+            Record::Init(r) => r.encode(&mut out),
+            Record::Passing(r) => r.encode(&mut out),
+            Record::Correction(r) => r.encode(&mut out),
+            Record::LineCrossing(r) => r.encode(&mut out),
+            Record::TrackDescription(r) => r.encode(&mut out),
+        }
+        out
+    }
+}
+
+/// Decodes a record from a single line of an RMonitor stream, as shared by the stream decoders.
+///
+/// Lines which don't begin with a record prefix (if we've somehow started decoding in the middle
+/// of a record) or which are completely empty are silently discarded, returning `None`. Lines which
+/// fail to decode are logged, and the error returned for the stream decoder to discard, so a
+/// single bad record doesn't interrupt the stream. In `lenient` mode, deviations accepted by
+/// [`Record::decode_lenient`] are logged as warnings and the record returned.
+#[cfg(feature = "std")]
+pub(crate) fn decode_line(line: &str, lenient: bool) -> Option<Result<Record, RecordError>> {
+    if line.is_empty() || line.as_bytes()[0] != b'$' {
+        return None;
+    }
+
+    let record = match Record::decode_with(line, lenient) {
+        Ok((record, warning)) => {
+            if let Some(warning) = warning {
+                log::warn!(
+                    "Accepting non-conforming RMonitor record from line '{}': {}",
+                    line,
+                    warning
+                );
+            }
+            Ok(record)
+        }
+        Err(source) => {
+            log::warn!(
+                "Skipping invalid RMonitor record from line '{}': {}",
+                line,
+                source
+            );
+            Err(source)
+        }
+    };
+    Some(record)
 }
 
 /// Heartbeat message, sent every second that a session is active
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Heartbeat {
     /// Number of laps to go
     pub laps_to_go: u32,
@@ -227,6 +652,12 @@ pub struct Heartbeat {
     pub race_time: String,
     /// Current flag status
     pub flag_status: Flag,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(
@@ -238,11 +669,21 @@ decode_impl!(
     race_time,
     flag_status
 );
+encode_impl!(
+    Heartbeat,
+    command::HEARTBEAT,
+    laps_to_go,
+    time_to_go,
+    time_of_day,
+    race_time,
+    flag_status
+);
 
 /// Competitor information record
 ///
 /// Competitors are unqiuely keyed on their `registration_number` field.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Competitor {
     pub registration_number: String,
     pub number: String,
@@ -253,6 +694,12 @@ pub struct Competitor {
     pub nationality: String,
     /// Unique class number (matches a `Class` record)
     pub class_number: u8,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(
@@ -266,12 +713,24 @@ decode_impl!(
     nationality,
     class_number
 );
+encode_impl!(
+    Competitor,
+    command::COMPETITOR,
+    registration_number,
+    number,
+    transponder_number,
+    first_name,
+    last_name,
+    nationality,
+    class_number
+);
 
 /// Extended competitor information
 ///
 /// It's unclear why the protocol includes this extra (almost identical) competitor information
 /// message, but it is included for completeness.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompetitorExt {
     pub registration_number: String,
     pub number: String,
@@ -280,6 +739,12 @@ pub struct CompetitorExt {
     pub last_name: String,
     pub nationality: String,
     pub additional_data: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(
@@ -293,26 +758,53 @@ decode_impl!(
     nationality,
     additional_data
 );
+encode_impl!(
+    CompetitorExt,
+    command::COMPETITOR_EXT,
+    registration_number,
+    number,
+    class_number,
+    first_name,
+    last_name,
+    nationality,
+    additional_data
+);
 
 /// Run (session) information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Run {
     /// Defined as 'unique', it's likely this means unique within a single RMonitor session
     pub number: u8,
     pub description: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(Run, 3, number, description);
+encode_impl!(Run, command::RUN, number, description);
 
 /// Class information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Class {
     /// Defined as 'unique', it's likely this means unique within a single RMonitor session
     pub number: u8,
     pub description: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(Class, 3, number, description);
+encode_impl!(Class, command::CLASS, number, description);
 
 /// Track setting information
 ///
@@ -321,15 +813,23 @@ decode_impl!(Class, 3, number, description);
 ///
 /// - 'TRACKNAME': The name of the track / event venue
 /// - 'TRACKLENGTH': The length of the track / event venue
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Setting {
     pub description: String,
     /// Specified as a `String` for both defined keys, however `TRACKLENGTH` is normally a string
     /// representation of a decimal number (e.g. '2.500')
     pub value: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(Setting, 3, description, value);
+encode_impl!(Setting, command::SETTING, description, value);
 
 /// Race position information
 ///
@@ -345,7 +845,8 @@ decode_impl!(Setting, 3, description, value);
 /// Both `Race` and `PracticeQual` messages should be expected in all types of session, in all
 /// scenarios they provide information about the competitor's best lap and total race time, the
 /// interpretation of the standings will depend on the type of session in progress.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Race {
     /// The competitor's position in the running order
     pub position: u16,
@@ -356,9 +857,23 @@ pub struct Race {
     /// Total race time (the sentinel value `00:59:59.999` indicates a competitor for whom no
     /// passing has yet been recorded).
     pub total_time: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(Race, 5, position, registration_number, laps, total_time);
+encode_impl!(
+    Race,
+    command::RACE,
+    position,
+    registration_number,
+    laps,
+    total_time
+);
 
 /// Practice / Qualification position information
 ///
@@ -369,7 +884,8 @@ decode_impl!(Race, 5, position, registration_number, laps, total_time);
 ///
 /// As with a `Race` record, the timing software should issue multiple `PracticeQual` messages when
 /// the standings change.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PracticeQual {
     /// The competitor's position in the fastest-lap standings
     pub position: u16,
@@ -378,6 +894,12 @@ pub struct PracticeQual {
     pub best_lap: u32,
     /// The laptime of the best lap
     pub best_laptime: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(
@@ -388,36 +910,67 @@ decode_impl!(
     best_lap,
     best_laptime
 );
+encode_impl!(
+    PracticeQual,
+    command::PRAC_QUAL,
+    position,
+    registration_number,
+    best_lap,
+    best_laptime
+);
 
 /// Indicates that the scoreboard should be reset
 ///
 /// The timing software may send an `Init` message immediately prior to the start of a new session,
 /// or when it has determined the data is stale and should be completely refreshed.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Init {
     pub time: String,
     pub date: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(Init, 3, time, date);
+encode_impl!(Init, command::INIT, time, date);
 
 /// Passing information
 ///
 /// Sent each time a competitor crosses the main timeline.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Passing {
     pub registration_number: String,
     pub laptime: String,
     pub total_time: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(Passing, 4, registration_number, laptime, total_time);
+encode_impl!(
+    Passing,
+    command::PASSING,
+    registration_number,
+    laptime,
+    total_time
+);
 
 /// Corrected finish time
 ///
 /// Sent each time a passing time is corrected (this can be due to a photocell time being
 /// associated with a competitor after the `Passing` message was already sent).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Correction {
     pub registration_number: String,
     pub number: String,
@@ -426,6 +979,12 @@ pub struct Correction {
     pub total_time: String,
     /// The total time corrections from the previous passing message
     pub correction: String,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 decode_impl!(
@@ -437,12 +996,22 @@ decode_impl!(
     total_time,
     correction
 );
+encode_impl!(
+    Correction,
+    command::CORRECTION,
+    registration_number,
+    number,
+    laps,
+    total_time,
+    correction
+);
 
 /// Timeline crossing message
 ///
 /// Sent each time a competitor crosses a timeline, this message type is part of the IMSA Enhanced
 /// specification.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LineCrossing {
     pub number: String,
     pub timeline_number: String,
@@ -453,34 +1022,97 @@ pub struct LineCrossing {
     // but don't appear in any of the sample data.
     pub driver_id: Option<u8>,
     pub class_name: Option<String>,
+    /// Fields beyond those specified, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 // Manual implementation to support the variadic fields
 impl FromParts for LineCrossing {
-    fn decode(parts: &[&str]) -> Result<Self, RecordError> {
+    fn decode(parts: &[&str], lenient: bool) -> Result<(Self, Option<DecodeWarning>), RecordError> {
+        const RECORD: &str = "LineCrossing";
+        // Including the optional trailing fields, counts exclude the command
+        const FIELDS: usize = 7;
+
         if parts.len() < 6 {
-            return Err(RecordError::MalformedRecord);
+            return Err(RecordError::MalformedRecord {
+                record: RECORD,
+                expected: 5,
+                found: parts.len() - 1,
+            });
         }
 
+        // Fields beyond the optional ones are accepted, but only reported by lenient decoding
+        let warning = if lenient && parts.len() > FIELDS + 1 {
+            Some(DecodeWarning::ExtraFields {
+                expected: FIELDS,
+                found: parts.len() - 1,
+            })
+        } else {
+            None
+        };
+
+        // An empty driver ID is written to position a class name or extra fields
         let driver_id = parts
             .get(6)
-            .map(|p| p.decode())
-            .map_or(Ok(None), |r| r.map(Some))?;
+            .filter(|p| !p.is_empty())
+            .map(|_| decode_field(parts, RECORD, "driver_id", 6))
+            .transpose()?;
 
         let class_name = parts
             .get(7)
-            .map(|p| p.decode())
-            .map_or(Ok(None), |r| r.map(Some))?;
-
-        Ok(Self {
-            number: parts[1].decode()?,
-            timeline_number: parts[2].decode()?,
-            timeline_name: parts[3].decode()?,
-            date: parts[4].decode()?,
-            time: parts[5].decode()?,
+            .map(|_| decode_field(parts, RECORD, "class_name", 7))
+            .transpose()?;
+
+        let record = Self {
+            number: decode_field(parts, RECORD, "number", 1)?,
+            timeline_number: decode_field(parts, RECORD, "timeline_number", 2)?,
+            timeline_name: decode_field(parts, RECORD, "timeline_name", 3)?,
+            date: decode_field(parts, RECORD, "date", 4)?,
+            time: decode_field(parts, RECORD, "time", 5)?,
             driver_id,
             class_name,
-        })
+            extra: parts
+                .iter()
+                .skip(FIELDS + 1)
+                .map(|p| (*p).to_owned())
+                .collect(),
+        };
+        Ok((record, warning))
+    }
+}
+
+impl ToParts for LineCrossing {
+    fn encode(&self, out: &mut String) {
+        out.push_str(command::LINE_CROSSING);
+        for field in [
+            &self.number,
+            &self.timeline_number,
+            &self.timeline_name,
+            &self.date,
+            &self.time,
+        ] {
+            out.push(',');
+            field.encode(out);
+        }
+
+        // The optional trailing fields are positional, so a class name can only be written if a
+        // driver ID field precedes it, and extra fields if both do.
+        if self.driver_id.is_some() || self.class_name.is_some() || !self.extra.is_empty() {
+            out.push(',');
+            self.driver_id.encode(out);
+        }
+        if self.class_name.is_some() || !self.extra.is_empty() {
+            out.push(',');
+            self.class_name.encode(out);
+        }
+        for extra in &self.extra {
+            out.push(',');
+            out.push_str(extra);
+        }
     }
 }
 
@@ -492,18 +1124,26 @@ impl FromParts for LineCrossing {
 /// This message type is part of the IMSA Enhanced specification.
 ///
 /// [`TrackSection`]: crate::protocol::TrackSection
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackDescription {
     pub name: String,
     pub short_name: String,
     pub distance: String,
     pub sections: Vec<TrackSection>,
+    /// Fields beyond the specified sections, kept verbatim so the record can be re-encoded
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<String>,
 }
 
 /// Track section field
 ///
 /// Describes a section of track between two timelines.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackSection {
     /// Section name
     pub name: String,
@@ -516,35 +1156,87 @@ pub struct TrackSection {
 }
 
 impl FromParts for TrackDescription {
-    fn decode(parts: &[&str]) -> Result<Self, RecordError> {
+    fn decode(parts: &[&str], lenient: bool) -> Result<(Self, Option<DecodeWarning>), RecordError> {
+        const RECORD: &str = "TrackDescription";
+
         if parts.len() < 5 {
-            return Err(RecordError::MalformedRecord);
+            return Err(RecordError::MalformedRecord {
+                record: RECORD,
+                expected: 4,
+                found: parts.len() - 1,
+            });
         }
 
-        let expected: usize = parts[4].parse()?;
-        let sections: Vec<TrackSection> = parts[5..]
-            .chunks(4)
-            .filter(|s| s.len() == 4) // Discard short sections
-            .map(|s| {
+        let count: usize = decode_field(parts, RECORD, "section_count", 4)?;
+        // Each section has four fields, counts exclude the command
+        let expected = count.saturating_mul(4).saturating_add(4);
+        let found = parts.len() - 1;
+        let complete = (parts.len() - 5) / 4;
+
+        // Strict decoding requires every complete section to be counted, ignoring a short
+        // trailing one
+        let warning = match found {
+            _ if !lenient && complete != count => {
+                return Err(RecordError::IncorrectSectionCount {
+                    expected: count,
+                    found: complete,
+                })
+            }
+            n if n == expected || !lenient => None,
+            n if n > expected => Some(DecodeWarning::ExtraFields { expected, found }),
+            // Missing sections are left out
+            _ => Some(DecodeWarning::MissingFields { expected, found }),
+        };
+
+        let sections = (0..count.min(complete))
+            .map(|section| {
+                let start = 5 + section * 4;
                 Ok(TrackSection {
-                    name: s[0].decode()?,
-                    start: s[1].decode()?,
-                    end: s[2].decode()?,
-                    distance: s[3].decode()?,
+                    name: decode_field(parts, "TrackSection", "name", start)?,
+                    start: decode_field(parts, "TrackSection", "start", start + 1)?,
+                    end: decode_field(parts, "TrackSection", "end", start + 2)?,
+                    distance: decode_field(parts, "TrackSection", "distance", start + 3)?,
                 })
             })
             .collect::<Result<Vec<TrackSection>, RecordError>>()?;
 
-        if sections.len() != expected {
-            return Err(RecordError::IncorrectSectionCount);
+        let record = Self {
+            name: decode_field(parts, RECORD, "name", 1)?,
+            short_name: decode_field(parts, RECORD, "short_name", 2)?,
+            distance: decode_field(parts, RECORD, "distance", 3)?,
+            extra: parts
+                .iter()
+                .skip(5 + sections.len() * 4)
+                .map(|p| (*p).to_owned())
+                .collect(),
+            sections,
+        };
+        Ok((record, warning))
+    }
+}
+
+impl ToParts for TrackDescription {
+    fn encode(&self, out: &mut String) {
+        out.push_str(command::TRACK_DESCRIPTION);
+        for field in [&self.name, &self.short_name, &self.distance] {
+            out.push(',');
+            field.encode(out);
         }
+        out.push(',');
+        (self.sections.len() as u32).encode(out);
 
-        Ok(Self {
-            name: parts[1].decode()?,
-            short_name: parts[2].decode()?,
-            distance: parts[3].decode()?,
-            sections,
-        })
+        for section in &self.sections {
+            for field in [&section.name, &section.start, &section.end] {
+                out.push(',');
+                field.encode(out);
+            }
+            out.push(',');
+            section.distance.encode(out);
+        }
+        for extra in &self.extra {
+            out.push(',');
+            out.push_str(extra);
+        }
     }
 }
 
@@ -555,7 +1247,7 @@ mod tests {
     #[test]
     fn test_decodes_unknown_record() {
         let data = "$ZZZ,5,\"Friday free practice\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_err());
         assert!(matches!(record, Err(RecordError::UnknownRecordType(_))));
@@ -564,7 +1256,7 @@ mod tests {
     #[test]
     fn test_decodes_heartbeat() {
         let data = "$F,14,\"00:12:45\",\"13:34:23\",\"00:09:47\",\"Green \"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(
@@ -573,10 +1265,171 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_decodes_flags_leniently() {
+        assert_eq!("Green ".parse(), Ok(Flag::Green));
+        assert_eq!(Flag::from("      "), Flag::None);
+        assert_eq!(Flag::from(" yellow"), Flag::Yellow);
+        assert_eq!(Flag::from("White "), Flag::White);
+        assert_eq!(Flag::from("WARMUP"), Flag::Warmup);
+        assert_eq!(Flag::from("Code 60 "), Flag::Other("Code 60".to_owned()));
+        assert_eq!(Flag::None.name(), "None");
+        assert_eq!(Flag::White.name(), "White");
+        assert_eq!(Flag::Caution.as_str(), "Caution");
+
+        let data = r#"$F,14,"00:12:45","13:34:23","00:09:47","Caution""#;
+        let record = Record::decode(data).unwrap();
+        assert!(matches!(
+            &record,
+            Record::Heartbeat(Heartbeat {
+                flag_status: Flag::Caution,
+                ..
+            })
+        ));
+        assert_eq!(record.encode(), data);
+
+        let data = r#"$F,14,"00:12:45","13:34:23","00:09:47","Purple""#;
+        assert!(matches!(
+            Record::decode(data),
+            Ok(Record::Heartbeat(Heartbeat {
+                flag_status: Flag::Purple,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_describes_invalid_fields() {
+        let data = r#"$A,"1234BE","12X",5247A,"John","Johnson","USA",5"#;
+        let error = Record::decode(data).unwrap_err();
+        assert!(matches!(
+            &error,
+            RecordError::InvalidIntegerField {
+                record: "Competitor",
+                field: "transponder_number",
+                index: 3,
+                value,
+                ..
+            } if value == "5247A"
+        ));
+        assert_eq!(
+            error.to_string(),
+            r#"invalid integer field Competitor::transponder_number at index 3: "5247A""#
+        );
+
+        let error = Record::decode(r#"$L,"13","P2","Pit In""#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "malformed LineCrossing record, expected 5 fields but found 3"
+        );
+
+        let data = r#"$T,"Circuit","C","3.40",1,"S01","T1","T2",33x5"#;
+        assert!(matches!(
+            Record::decode(data),
+            Err(RecordError::InvalidIntegerField {
+                record: "TrackSection",
+                field: "distance",
+                index: 8,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_decodes_leniently() {
+        let data = r#"$J,"1234BE","00:02:03.826","01:42:17.672","P",17"#;
+        assert!(matches!(
+            Record::decode(data),
+            Err(RecordError::MalformedRecord {
+                record: "Passing",
+                expected: 3,
+                found: 5
+            })
+        ));
+
+        let (record, warning) = Record::decode_lenient(data).unwrap();
+        assert_eq!(
+            warning,
+            Some(DecodeWarning::ExtraFields {
+                expected: 3,
+                found: 5
+            })
+        );
+        match &record {
+            Record::Passing(passing) => {
+                assert_eq!(passing.total_time, "01:42:17.672");
+                assert_eq!(passing.extra, vec![r#""P""#, "17"]);
+            }
+            _ => panic!("expected a passing"),
+        }
+        assert_eq!(record.encode(), data);
+
+        // Required fields can't be left out
+        for data in &[
+            r#"$G,3,"1234BE""#,
+            r#"$J,"1234BE""#,
+            "$I",
+            r#"$A,"1234BE","12X""#,
+        ] {
+            assert!(matches!(
+                Record::decode_lenient(data),
+                Err(RecordError::MalformedRecord { .. })
+            ));
+        }
+        assert!(matches!(
+            Record::decode_lenient(r#"$G,3,"1234BE",5"#),
+            Err(RecordError::MalformedRecord {
+                record: "Race",
+                expected: 4,
+                found: 3
+            })
+        ));
+
+        // Line crossings keep fields beyond the optional ones
+        let data = r#"$L,"13","P2","POP","01/27/2009","10:10:20.589",1,"PC","X""#;
+        let (record, warning) = Record::decode_lenient(data).unwrap();
+        assert_eq!(
+            warning,
+            Some(DecodeWarning::ExtraFields {
+                expected: 7,
+                found: 8
+            })
+        );
+        assert!(matches!(
+            &record,
+            Record::LineCrossing(crossing) if crossing.extra == vec![r#""X""#]
+        ));
+        assert_eq!(record.encode(), data);
+
+        // Track descriptions keep fields beyond their sections, and may leave sections out
+        let data = r#"$T,"Circuit","C","3.40",1,"S01","T1","T2",3375,"X""#;
+        let (record, warning) = Record::decode_lenient(data).unwrap();
+        assert_eq!(warning.map(|w| w.kind()), Some("extra_fields"));
+        assert_eq!(record.encode(), data);
+
+        let data = r#"$T,"Circuit","C","3.40",2,"S01","T1","T2",3375"#;
+        let (record, warning) = Record::decode_lenient(data).unwrap();
+        assert_eq!(
+            warning,
+            Some(DecodeWarning::MissingFields {
+                expected: 12,
+                found: 8
+            })
+        );
+        assert!(matches!(
+            record,
+            Record::TrackDescription(td) if td.sections.len() == 1 && td.extra.is_empty()
+        ));
+
+        // A conforming record has no warning
+        let (_, warning) = Record::decode_lenient(r#"$B,5,"Friday free practice""#).unwrap();
+        assert_eq!(warning, None);
+    }
+
     #[test]
     fn test_decodes_competitor() {
         let data = "$A,\"1234BE\",\"12X\",52474,\"John\",\"Johnson\",\"USA\",5";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Competitor(_))));
@@ -595,7 +1448,7 @@ mod tests {
     #[test]
     fn test_decodes_competitor_ext() {
         let data = "$COMP,\"1234BE\",\"12X\",5,\"John\",\"Johnson\",\"USA\",\"CAMEL\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::CompetitorExt(_))));
@@ -614,7 +1467,7 @@ mod tests {
     #[test]
     fn test_decodes_run() {
         let data = "$B,5,\"Friday free practice\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Run(_))));
@@ -628,7 +1481,7 @@ mod tests {
     #[test]
     fn test_decodes_class() {
         let data = "$C,5,\"Formula 3000\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Class(_))));
@@ -643,7 +1496,7 @@ mod tests {
     fn test_decodes_settings() {
         // Two samples provided for this protocol record
         let data = "$E,\"TRACKNAME\",\"Indianapolis Motor Speedway\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Setting(_))));
@@ -654,7 +1507,7 @@ mod tests {
         }
 
         let data = "$E,\"TRACKLENGTH\",\"2.500\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Setting(_))));
@@ -668,7 +1521,7 @@ mod tests {
     #[test]
     fn test_decodes_race() {
         let data = "$G,3,\"1234BE\",14,\"01:12:47.872\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Race(_))));
@@ -684,7 +1537,7 @@ mod tests {
     #[test]
     fn test_decodes_practice_qual() {
         let data = "$H,2,\"1234BE\",3,\"00:02:17.872\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::PracticeQual(_))));
@@ -700,7 +1553,7 @@ mod tests {
     #[test]
     fn test_decodes_init_command() {
         let data = "$I,\"16:36:08.000\",\"12 jan 01\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Init(_))));
@@ -714,7 +1567,7 @@ mod tests {
     #[test]
     fn test_decodes_passing() {
         let data = "$J,\"1234BE\",\"00:02:03.826\",\"01:42:17.672\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Passing(_))));
@@ -729,7 +1582,7 @@ mod tests {
     #[test]
     fn test_decodes_correction() {
         let data = "$COR,\"123BE\",\"658\",2,\"00:00:35.272\",\"+00:00:00.012\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::Correction(_))));
@@ -746,7 +1599,7 @@ mod tests {
     fn test_decodes_line_crossing() {
         // Fields seen in protocol spec
         let data = "$L,\"13\",\"P2\",\"POP\",\"01/27/2009\",\"10:10:20.589\",1,\"PC\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::LineCrossing(_))));
@@ -763,7 +1616,7 @@ mod tests {
 
         // Fields seen in sample data
         let data = "$L,\"15\",\"P1\",\"SFP\",\"01/27/2009\",\"14:13:22.818\"";
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::LineCrossing(_))));
@@ -777,6 +1630,17 @@ mod tests {
             assert_eq!(c.driver_id, None);
             assert_eq!(c.class_name, None);
         }
+
+        // Fields beyond the optional ones are ignored
+        let data = r#"$L,"13","P2","POP","01/27/2009","10:10:20.589",1,"PC","X","Y""#;
+        let record = Record::decode(data);
+
+        assert!(matches!(record, Ok(Record::LineCrossing(_))));
+
+        if let Ok(Record::LineCrossing(c)) = record {
+            assert_eq!(c.driver_id, Some(1));
+            assert_eq!(c.class_name, Some("PC".to_owned()));
+        }
     }
 
     #[test]
@@ -789,7 +1653,7 @@ mod tests {
             r#"7",1181,"SP5","T8","T9",1181"#
         );
 
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
 
         assert!(record.is_ok());
         assert!(matches!(record, Ok(Record::TrackDescription(_))));
@@ -800,6 +1664,15 @@ mod tests {
             assert_eq!(td.distance, "3.40");
             assert_eq!(td.sections.len(), 15);
         }
+
+        // A short trailing section is ignored
+        let data = r#"$T,"Circuit","C","3.40",1,"S01","T1","T2",3375,"S02","T2""#;
+        let record = Record::decode(data);
+
+        assert!(matches!(
+            record,
+            Ok(Record::TrackDescription(td)) if td.sections.len() == 1
+        ));
     }
 
     #[test]
@@ -811,8 +1684,318 @@ mod tests {
             r#"0","TA","TB",41744,"S11","TB","T1",16113,"LAP","T1","P1",217379,"PIT","PB","P2",19688"#
         );
 
-        let record = Record::decode(&data);
+        let record = Record::decode(data);
         assert!(record.is_err());
-        assert!(matches!(record, Err(RecordError::IncorrectSectionCount)))
+        assert!(matches!(
+            record,
+            Err(RecordError::IncorrectSectionCount {
+                expected: 15,
+                found: 13
+            })
+        ))
+    }
+}
+
+/// Proptest strategies for generating valid records, shared with the codec tests.
+#[cfg(test)]
+pub(crate) mod strategies {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+
+    /// Quoted string fields may contain anything except the quote and separator characters,
+    /// which the protocol has no way of escaping.
+    pub fn field() -> impl Strategy<Value = String> {
+        "[^\",\r\n]{0,24}"
+    }
+
+    pub fn flag() -> impl Strategy<Value = Flag> {
+        prop_oneof![
+            Just(Flag::None),
+            Just(Flag::Green),
+            Just(Flag::Yellow),
+            Just(Flag::Red),
+            Just(Flag::Finish),
+            Just(Flag::Purple),
+            Just(Flag::Warmup),
+            Just(Flag::Caution),
+            Just(Flag::White),
+            field().prop_map(|s| Flag::from(s.as_str())),
+        ]
+    }
+
+    fn heartbeat() -> impl Strategy<Value = Heartbeat> {
+        (any::<u32>(), field(), field(), field(), flag()).prop_map(
+            |(laps_to_go, time_to_go, time_of_day, race_time, flag_status)| Heartbeat {
+                laps_to_go,
+                time_to_go,
+                time_of_day,
+                race_time,
+                flag_status,
+                extra: Vec::new(),
+            },
+        )
+    }
+
+    fn competitor() -> impl Strategy<Value = Competitor> {
+        (
+            field(),
+            field(),
+            any::<u32>(),
+            field(),
+            field(),
+            field(),
+            any::<u8>(),
+        )
+            .prop_map(
+                |(
+                    registration_number,
+                    number,
+                    transponder_number,
+                    first_name,
+                    last_name,
+                    nationality,
+                    class_number,
+                )| Competitor {
+                    registration_number,
+                    number,
+                    transponder_number,
+                    first_name,
+                    last_name,
+                    nationality,
+                    class_number,
+                    extra: Vec::new(),
+                },
+            )
+    }
+
+    fn competitor_ext() -> impl Strategy<Value = CompetitorExt> {
+        (
+            field(),
+            field(),
+            any::<u8>(),
+            field(),
+            field(),
+            field(),
+            field(),
+        )
+            .prop_map(
+                |(
+                    registration_number,
+                    number,
+                    class_number,
+                    first_name,
+                    last_name,
+                    nationality,
+                    additional_data,
+                )| CompetitorExt {
+                    registration_number,
+                    number,
+                    class_number,
+                    first_name,
+                    last_name,
+                    nationality,
+                    additional_data,
+                    extra: Vec::new(),
+                },
+            )
+    }
+
+    fn track_section() -> impl Strategy<Value = TrackSection> {
+        (field(), field(), field(), any::<u32>()).prop_map(|(name, start, end, distance)| {
+            TrackSection {
+                name,
+                start,
+                end,
+                distance,
+            }
+        })
+    }
+
+    fn line_crossing() -> impl Strategy<Value = LineCrossing> {
+        (
+            field(),
+            field(),
+            field(),
+            field(),
+            field(),
+            // A class name can only be present when preceded by a driver ID
+            option::of((any::<u8>(), option::of(field()))),
+        )
+            .prop_map(
+                |(number, timeline_number, timeline_name, date, time, trailing)| LineCrossing {
+                    number,
+                    timeline_number,
+                    timeline_name,
+                    date,
+                    time,
+                    driver_id: trailing.as_ref().map(|(id, _)| *id),
+                    class_name: trailing.and_then(|(_, class)| class),
+                    extra: Vec::new(),
+                },
+            )
+    }
+
+    pub fn record() -> impl Strategy<Value = Record> {
+        prop_oneof![
+            heartbeat().prop_map(Record::Heartbeat),
+            competitor().prop_map(Record::Competitor),
+            competitor_ext().prop_map(Record::CompetitorExt),
+            (any::<u8>(), field()).prop_map(|(number, description)| Record::Run(Run {
+                number,
+                description,
+                extra: Vec::new(),
+            })),
+            (any::<u8>(), field()).prop_map(|(number, description)| Record::Class(Class {
+                number,
+                description,
+                extra: Vec::new(),
+            })),
+            (field(), field()).prop_map(|(description, value)| Record::Setting(Setting {
+                description,
+                value,
+                extra: Vec::new(),
+            })),
+            (any::<u16>(), field(), option::of(any::<u32>()), field()).prop_map(
+                |(position, registration_number, laps, total_time)| Record::Race(Race {
+                    position,
+                    registration_number,
+                    laps,
+                    total_time,
+                    extra: Vec::new(),
+                })
+            ),
+            (any::<u16>(), field(), any::<u32>(), field()).prop_map(
+                |(position, registration_number, best_lap, best_laptime)| {
+                    Record::PracticeQual(PracticeQual {
+                        position,
+                        registration_number,
+                        best_lap,
+                        best_laptime,
+                        extra: Vec::new(),
+                    })
+                }
+            ),
+            (field(), field()).prop_map(|(time, date)| Record::Init(Init {
+                time,
+                date,
+                extra: Vec::new(),
+            })),
+            (field(), field(), field()).prop_map(|(registration_number, laptime, total_time)| {
+                Record::Passing(Passing {
+                    registration_number,
+                    laptime,
+                    total_time,
+                    extra: Vec::new(),
+                })
+            }),
+            (field(), field(), any::<u32>(), field(), field()).prop_map(
+                |(registration_number, number, laps, total_time, correction)| {
+                    Record::Correction(Correction {
+                        registration_number,
+                        number,
+                        laps,
+                        total_time,
+                        correction,
+                        extra: Vec::new(),
+                    })
+                }
+            ),
+            line_crossing().prop_map(Record::LineCrossing),
+            (field(), field(), field(), vec(track_section(), 0..8)).prop_map(
+                |(name, short_name, distance, sections)| {
+                    Record::TrackDescription(TrackDescription {
+                        name,
+                        short_name,
+                        distance,
+                        sections,
+                        extra: Vec::new(),
+                    })
+                }
+            ),
+        ]
+    }
+
+    /// A record line which has been damaged in one of the ways seen from misbehaving feeds.
+    pub fn near_valid_line() -> impl Strategy<Value = String> {
+        (record(), 0..5usize, any::<prop::sample::Index>(), field()).prop_map(
+            |(record, mutation, index, garbage)| {
+                let line = record.encode();
+                let mut parts: Vec<&str> = line.split(',').collect();
+                let i = index.index(parts.len());
+                match mutation {
+                    // Missing field
+                    0 => {
+                        parts.remove(i);
+                    }
+                    // Extra field
+                    1 => parts.insert(i, &garbage),
+                    // Replaced field
+                    2 => parts[i] = &garbage,
+                    // Truncated mid-line
+                    3 => {
+                        let mut end = line.len() * i / parts.len();
+                        while !line.is_char_boundary(end) {
+                            end -= 1;
+                        }
+                        return line[..end].to_owned();
+                    }
+                    // Trailing separator
+                    _ => parts.push(""),
+                }
+                parts.join(",")
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::strategies::*;
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_round_trips(record in record()) {
+            let line = record.encode();
+            prop_assert_eq!(Record::decode(&line).unwrap(), record);
+        }
+
+        #[test]
+        fn prop_near_valid_does_not_panic(line in near_valid_line()) {
+            let _ = Record::decode(&line);
+            let _ = Record::decode_lenient(&line);
+        }
+
+        #[test]
+        fn prop_arbitrary_input_does_not_panic(line in "\\PC*") {
+            let _ = Record::decode(&line);
+        }
+
+        #[test]
+        fn prop_rejects_missing_fixed_fields(record in record(), drop in 1..3usize) {
+            // Variadic records may legitimately have fewer fields
+            prop_assume!(!matches!(record, Record::LineCrossing(_) | Record::TrackDescription(_)));
+
+            let line = record.encode();
+            let parts: Vec<&str> = line.split(',').collect();
+            prop_assume!(parts.len() > drop + 1);
+            let truncated = parts[..parts.len() - drop].join(",");
+
+            let malformed = matches!(
+                Record::decode(&truncated),
+                Err(RecordError::MalformedRecord { .. })
+            );
+            prop_assert!(malformed);
+
+            // No fixed record has optional trailing fields to leave out
+            let malformed = matches!(
+                Record::decode_lenient(&truncated),
+                Err(RecordError::MalformedRecord { .. })
+            );
+            prop_assert!(malformed);
+        }
     }
 }
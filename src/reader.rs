@@ -0,0 +1,241 @@
+//! A blocking reader for RMonitor streams, for use without an async runtime.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::RMonitorReader;
+//! use std::net::TcpStream;
+//!
+//! let stream = TcpStream::connect("127.0.0.1:4000").unwrap();
+//!
+//! for record in RMonitorReader::new_with_max_length(stream, 2048) {
+//!     println!("{:?}", record);
+//! }
+//! ```
+use std::io::{self, BufRead, BufReader, Read};
+use thiserror::Error;
+
+use crate::encoding::Encoding;
+use crate::protocol::*;
+
+/// An error was encountered when trying to read an RMonitor record from the input.
+#[derive(Error, Debug)]
+pub enum RMonitorReadError {
+    /// A line exceeded the maximum length, the remainder of the line has been discarded
+    #[error("max line length exceeded")]
+    MaxLineLengthExceeded,
+    /// A line could not be decoded in the configured encoding, or reading from the underlying
+    /// reader failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A blocking reader for RMonitor records, yielding each record decoded from the underlying
+/// [`Read`] as an [`Iterator`].
+///
+/// Lines which can't be decoded as a record are logged and skipped, in the same way as the
+/// [`RMonitorDecoder`]. Iteration ends when the underlying reader reaches EOF.
+///
+/// [`Read`]: std::io::Read
+/// [`RMonitorDecoder`]: crate::codec::RMonitorDecoder
+#[derive(Debug)]
+pub struct RMonitorReader<R> {
+    inner: BufReader<R>,
+    line: Vec<u8>,
+    max_length: usize,
+    encoding: Encoding,
+    lenient: bool,
+}
+
+impl<R: Read> RMonitorReader<R> {
+    /// Returns an `RMonitorReader` reading records from `inner`.
+    ///
+    /// # Note
+    ///
+    /// The returned `RMonitorReader` will have no upper bound on the length of a buffered line.
+    /// Consider using [`new_with_max_length`] instead.
+    ///
+    /// [`new_with_max_length`]: crate::reader::RMonitorReader::new_with_max_length()
+    pub fn new(inner: R) -> Self {
+        Self::new_with_max_length(inner, usize::MAX)
+    }
+
+    /// Returns an `RMonitorReader` with a maximum line length limit.
+    ///
+    /// It is recommended to set such a limit where the input is untrusted, as an attacker could
+    /// send an unbounded amount of input with no newline characters.
+    pub fn new_with_max_length(inner: R, max_length: usize) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            line: Vec::new(),
+            max_length,
+            encoding: Encoding::default(),
+            lenient: false,
+        }
+    }
+
+    /// Sets the character encoding of the input, which defaults to strict UTF-8.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Accepts records with extra or missing trailing fields, logging a warning for each, see
+    /// [`Record::decode_lenient`].
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref()
+    }
+
+    /// Consumes the `RMonitorReader`, returning the underlying reader.
+    ///
+    /// Any buffered input which has not yet been decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+
+    /// Reads the next line into `self.line`, without the line terminator.
+    ///
+    /// Returns `Ok(false)` at EOF, when no more lines are available.
+    fn read_line(&mut self) -> Result<bool, RMonitorReadError> {
+        self.line.clear();
+        let mut discarding = false;
+
+        loop {
+            let available = match self.inner.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if available.is_empty() {
+                // A final line without a terminator is still a line
+                return if discarding {
+                    Err(RMonitorReadError::MaxLineLengthExceeded)
+                } else {
+                    Ok(!self.line.is_empty())
+                };
+            }
+
+            let (chunk, found) = match available.iter().position(|&b| b == b'\n') {
+                Some(i) => (&available[..i], i + 1),
+                None => (available, 0),
+            };
+            let consumed = if found > 0 { found } else { chunk.len() };
+
+            if !discarding {
+                if self.line.len() + chunk.len() > self.max_length {
+                    discarding = true;
+                    self.line.clear();
+                } else {
+                    self.line.extend_from_slice(chunk);
+                }
+            }
+            self.inner.consume(consumed);
+
+            if found > 0 {
+                if discarding {
+                    return Err(RMonitorReadError::MaxLineLengthExceeded);
+                }
+                if self.line.last() == Some(&b'\r') {
+                    self.line.pop();
+                }
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for RMonitorReader<R> {
+    type Item = Result<Record, RMonitorReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.read_line() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+
+            let line = match self.encoding.decode(&self.line) {
+                Some(line) => line,
+                None => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Unable to decode input as UTF8",
+                    )
+                    .into()))
+                }
+            };
+
+            if let Some(Ok(record)) = decode_line(&line, self.lenient) {
+                return Some(Ok(record));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_until_eof() {
+        let data = "$F,9999,\"00:00:00\",\"14:09:52\",\"00:59:59\",\"      \"\r\n$B,5,\"Friday free practice\"";
+        let records: Vec<_> = RMonitorReader::new(data.as_bytes()).collect();
+
+        assert_eq!(2, records.len());
+        assert!(matches!(records[0], Ok(Record::Heartbeat(_))));
+        assert!(matches!(records[1], Ok(Record::Run(_))));
+    }
+
+    #[test]
+    fn test_reads_large_sample() {
+        let file = std::fs::File::open("sample/2009_Sebring_ALMS_Session_5.txt").unwrap();
+        let records: Vec<_> = RMonitorReader::new_with_max_length(file, 2048).collect();
+
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_skips_invalid_records() {
+        let data = "$F,invalid,data,here\r\n\r\ngarbage\r\n$B,5,\"Friday free practice\"\r\n";
+        let records: Vec<_> = RMonitorReader::new(data.as_bytes()).collect();
+
+        assert_eq!(1, records.len());
+        assert!(matches!(records[0], Ok(Record::Run(_))));
+    }
+
+    #[test]
+    fn test_recovers_from_long_line() {
+        let data = "$B,5,\"A description that is much too long\"\r\n$C,5,\"Formula 3000\"\r\n";
+        let mut reader = RMonitorReader::new_with_max_length(data.as_bytes(), 24);
+
+        assert!(matches!(
+            reader.next(),
+            Some(Err(RMonitorReadError::MaxLineLengthExceeded))
+        ));
+        assert!(matches!(reader.next(), Some(Ok(Record::Class(_)))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_reads_with_encoding() {
+        let data = &b"$B,1,\"Gro\xdfer Preis\"\n"[..];
+
+        let mut reader = RMonitorReader::new(data);
+        assert!(matches!(reader.next(), Some(Err(RMonitorReadError::Io(_)))));
+
+        let mut reader = RMonitorReader::new(data).with_encoding(Encoding::Latin1);
+        assert!(matches!(
+            reader.next(),
+            Some(Ok(Record::Run(Run { description, .. }))) if description == "Großer Preis"
+        ));
+    }
+}
@@ -0,0 +1,530 @@
+//! Printable HTML results sheets.
+//!
+//! A [`Report`] renders a [`Session`] as a single self-contained HTML document, with no external
+//! stylesheets, scripts or fonts, ready to be posted on a noticeboard or printed to PDF from a
+//! browser. It contains:
+//!
+//! - a header with the run name from the `Run` record, and the track name and length from the
+//!   `TRACKNAME` and `TRACKLENGTH` settings
+//! - the overall classification, followed by the classification of each class
+//! - the fastest lap in each class
+//! - a summary of every flag period
+//!
+//! The page can be branded with a series name, logo and footer, and the stylesheet or the whole
+//! template can be replaced.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::report::Report;
+//! use rmonitor::session::Session;
+//! use rmonitor::RMonitorReader;
+//! use std::fs::File;
+//! # use std::error::Error;
+//!
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! let mut session = Session::new();
+//! for record in RMonitorReader::new(File::open("sample/2009_Sebring_ALMS_Session_5.txt")?) {
+//!     session.apply(&record?);
+//! }
+//!
+//! Report::new()
+//!     .with_series("American Le Mans Series")
+//!     .with_footer("Provisional results, subject to the approval of the stewards")
+//!     .write(&session, File::create("results.html")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Templates
+//!
+//! Templates are HTML documents containing placeholders, which are replaced when rendering:
+//!
+//! | Placeholder   | Replaced with                                              |
+//! |---------------|------------------------------------------------------------|
+//! | `{{title}}`   | The run name, or "Results" if no `Run` record was received |
+//! | `{{style}}`   | The stylesheet                                             |
+//! | `{{series}}`  | The series name                                            |
+//! | `{{logo}}`    | An `<img>` element showing the logo, if one is set         |
+//! | `{{header}}`  | The event header                                           |
+//! | `{{content}}` | The classifications, fastest laps and flag summary         |
+//! | `{{footer}}`  | The footer text                                            |
+//!
+//! Every value except the stylesheet and the generated HTML is escaped. Unknown placeholders are
+//! left unchanged.
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::protocol::Flag;
+use crate::session::{CompetitorState, Session, StandingsOrder};
+use crate::time::{format_duration, parse_duration};
+
+/// The template used unless replaced with [`Report::with_template`]
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+<style>
+{{style}}
+</style>
+</head>
+<body>
+<header>
+{{logo}}
+<p class="series">{{series}}</p>
+{{header}}
+</header>
+<main>
+{{content}}
+</main>
+<footer>{{footer}}</footer>
+</body>
+</html>
+"#;
+
+/// The stylesheet used unless replaced with [`Report::with_stylesheet`]
+pub const DEFAULT_STYLESHEET: &str = r#"@page { size: A4; margin: 12mm; }
+body { font-family: Helvetica, Arial, sans-serif; font-size: 10pt; color: #000; margin: 0; }
+header { border-bottom: 2px solid #000; margin-bottom: 1em; overflow: hidden; }
+header img { float: right; max-height: 60px; }
+.series { font-weight: bold; text-transform: uppercase; margin: 0; }
+h1 { font-size: 18pt; margin: 0.2em 0; }
+h2 { font-size: 12pt; margin: 1.2em 0 0.4em; }
+.event { margin: 0 0 0.5em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { padding: 2px 6px; text-align: left; white-space: nowrap; }
+th { border-bottom: 1px solid #000; }
+tr:nth-child(even) td { background: #eee; }
+td.number { text-align: right; }
+section { page-break-inside: avoid; }
+footer { border-top: 1px solid #000; margin-top: 2em; padding-top: 0.5em; font-size: 8pt; }
+@media print {
+  tr:nth-child(even) td { -webkit-print-color-adjust: exact; print-color-adjust: exact; }
+}
+"#;
+
+/// Escapes text for inclusion in HTML content or attribute values
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Replaces each `{{name}}` in `template` with the value returned by `value`, leaving unknown
+/// placeholders unchanged.
+fn fill<'a, F>(template: &str, value: F) -> String
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => match value(after[..end].trim()) {
+                Some(value) => {
+                    out.push_str(value);
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    out.push_str("{{");
+                    rest = after;
+                }
+            },
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn class_label(session: &Session, class_number: Option<u8>) -> String {
+    match class_number {
+        Some(n) => session
+            .class_name(n)
+            .map_or_else(|| format!("Class {}", n), str::to_owned),
+        None => "No class".to_owned(),
+    }
+}
+
+/// Renders sessions as printable HTML results sheets
+#[derive(Clone, Debug)]
+pub struct Report {
+    template: String,
+    stylesheet: String,
+    series: String,
+    logo: Option<String>,
+    footer: String,
+    order: Option<StandingsOrder>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Report {
+    /// Returns a `Report` using the default template and stylesheet, without any branding
+    pub fn new() -> Self {
+        Self {
+            template: DEFAULT_TEMPLATE.to_owned(),
+            stylesheet: DEFAULT_STYLESHEET.to_owned(),
+            series: String::new(),
+            logo: None,
+            footer: String::new(),
+            order: None,
+        }
+    }
+
+    /// Replaces the template, see the [module documentation](self#templates) for placeholders
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Replaces the stylesheet, which is included in the document as-is
+    pub fn with_stylesheet(mut self, stylesheet: impl Into<String>) -> Self {
+        self.stylesheet = stylesheet.into();
+        self
+    }
+
+    /// Sets the series name shown above the event header
+    pub fn with_series(mut self, series: impl Into<String>) -> Self {
+        self.series = series.into();
+        self
+    }
+
+    /// Sets the URL of a logo shown in the header.
+    ///
+    /// Use a `data:` URL to keep the document self-contained.
+    pub fn with_logo(mut self, url: impl Into<String>) -> Self {
+        self.logo = Some(url.into());
+        self
+    }
+
+    /// Sets the text shown at the foot of the page
+    pub fn with_footer(mut self, footer: impl Into<String>) -> Self {
+        self.footer = footer.into();
+        self
+    }
+
+    /// Sets the order of the classification.
+    ///
+    /// By default, the order follows the session type inferred by [`Session::session_type`].
+    pub fn with_order(mut self, order: StandingsOrder) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Renders the results sheet for `session`
+    pub fn render(&self, session: &Session) -> String {
+        let title = escape(
+            session
+                .run
+                .as_ref()
+                .map(|r| r.description.trim())
+                .filter(|d| !d.is_empty())
+                .unwrap_or("Results"),
+        );
+        let series = escape(&self.series);
+        let logo = self
+            .logo
+            .as_ref()
+            .map(|url| format!(r#"<img src="{}" alt="">"#, escape(url)))
+            .unwrap_or_default();
+        let header = self.header(session, &title);
+        let content = self.content(session);
+        let footer = escape(&self.footer);
+
+        fill(&self.template, |name| match name {
+            "title" => Some(&title),
+            "style" => Some(&self.stylesheet),
+            "series" => Some(&series),
+            "logo" => Some(&logo),
+            "header" => Some(&header),
+            "content" => Some(&content),
+            "footer" => Some(&footer),
+            _ => None,
+        })
+    }
+
+    /// Writes the results sheet for `session` to `writer`
+    pub fn write<W: Write>(&self, session: &Session, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.render(session).as_bytes())?;
+        writer.flush()
+    }
+
+    fn header(&self, session: &Session, title: &str) -> String {
+        let mut event = Vec::new();
+        if let Some(name) = session.track_name() {
+            event.push(escape(name));
+        }
+        if let Some(length) = session.track_length() {
+            event.push(escape(length));
+        }
+        if let Some(heartbeat) = &session.heartbeat {
+            event.push(format!(
+                "Results at {}",
+                escape(heartbeat.time_of_day.trim())
+            ));
+        }
+
+        let mut html = format!("<h1>{}</h1>\n", title);
+        if !event.is_empty() {
+            let _ = writeln!(html, r#"<p class="event">{}</p>"#, event.join(" &middot; "));
+        }
+        html
+    }
+
+    fn content(&self, session: &Session) -> String {
+        let order = self
+            .order
+            .unwrap_or_else(|| session.session_type().session_type.standings_order());
+
+        let mut html = String::new();
+        classification(&mut html, session, order, None, "Classification");
+
+        // Classes with a description, followed by any only referenced by competitors
+        let mut classes: Vec<u8> = session.classes.keys().copied().collect();
+        for c in session.competitors.values() {
+            if let Some(n) = c.class_number.filter(|n| !classes.contains(n)) {
+                classes.push(n);
+            }
+        }
+        if classes.len() > 1 {
+            for n in classes {
+                let heading = format!("{} Classification", class_label(session, Some(n)));
+                classification(&mut html, session, order, Some(n), &heading);
+            }
+        }
+
+        fastest_laps(&mut html, session);
+        flag_summary(&mut html, session);
+        html
+    }
+}
+
+fn classification(
+    html: &mut String,
+    session: &Session,
+    order: StandingsOrder,
+    class: Option<u8>,
+    heading: &str,
+) {
+    let standings = session.classification(order, class);
+    if standings.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(html, "<section>\n<h2>{}</h2>\n<table>", escape(heading));
+    html.push_str(
+        "<thead><tr><th>Pos</th><th>No.</th><th>Driver</th><th>Class</th><th>Laps</th>\
+         <th>Total Time</th><th>Gap</th><th>Best Lap</th><th>Best Time</th></tr></thead>\n\
+         <tbody>\n",
+    );
+    for s in standings {
+        let c = s.competitor;
+        let _ = writeln!(
+            html,
+            "<tr><td class=\"number\">{}</td><td class=\"number\">{}</td><td>{}</td><td>{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+            s.position,
+            escape(c.display_number()),
+            escape(&c.name()),
+            c.class_number
+                .map(|n| escape(&class_label(session, Some(n))))
+                .unwrap_or_default(),
+            c.laps.map(|l| l.to_string()).unwrap_or_default(),
+            c.total_time.map(format_duration).unwrap_or_default(),
+            s.gap.map(|g| g.to_string()).unwrap_or_default(),
+            c.best_lap.map(|l| l.to_string()).unwrap_or_default(),
+            c.best_laptime.map(format_duration).unwrap_or_default(),
+        );
+    }
+    html.push_str("</tbody>\n</table>\n</section>\n");
+}
+
+fn fastest_laps(html: &mut String, session: &Session) {
+    // The fastest competitor in each class, ordered by laptime
+    let mut fastest: Vec<&CompetitorState> = Vec::new();
+    for c in session.competitors.values() {
+        let laptime = match c.best_laptime {
+            Some(laptime) => laptime,
+            None => continue,
+        };
+        match fastest
+            .iter_mut()
+            .find(|f| f.class_number == c.class_number)
+        {
+            Some(f) if f.best_laptime.is_some_and(|best| best <= laptime) => {}
+            Some(f) => *f = c,
+            None => fastest.push(c),
+        }
+    }
+    if fastest.is_empty() {
+        return;
+    }
+    fastest.sort_by_key(|c| c.best_laptime);
+
+    html.push_str(
+        "<section>\n<h2>Fastest Laps</h2>\n<table>\n\
+         <thead><tr><th>Class</th><th>No.</th><th>Driver</th><th>Lap</th><th>Time</th></tr></thead>\n\
+         <tbody>\n",
+    );
+    for c in fastest {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"number\">{}</td><td>{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td></tr>",
+            escape(&class_label(session, c.class_number)),
+            escape(c.display_number()),
+            escape(&c.name()),
+            c.best_lap.map(|l| l.to_string()).unwrap_or_default(),
+            c.best_laptime.map(format_duration).unwrap_or_default(),
+        );
+    }
+    html.push_str("</tbody>\n</table>\n</section>\n");
+}
+
+fn flag_summary(html: &mut String, session: &Session) {
+    let periods: Vec<_> = session
+        .flag_history
+        .iter()
+        .filter(|f| f.flag != Flag::None)
+        .collect();
+    if periods.is_empty() {
+        return;
+    }
+
+    // Each period lasts until the next change, or the latest heartbeat for the current flag
+    let end = session
+        .heartbeat
+        .as_ref()
+        .and_then(|h| parse_duration(&h.race_time));
+
+    html.push_str(
+        "<section>\n<h2>Flag Summary</h2>\n<table>\n\
+         <thead><tr><th>Flag</th><th>Race Time</th><th>Time of Day</th><th>Duration</th></tr>\
+         </thead>\n<tbody>\n",
+    );
+    for (i, f) in periods.iter().enumerate() {
+        let until = match periods.get(i + 1) {
+            Some(next) => parse_duration(&next.race_time),
+            None => end,
+        };
+        let duration = parse_duration(&f.race_time)
+            .zip(until)
+            .and_then(|(start, until)| until.checked_sub(start))
+            .map(format_duration)
+            .unwrap_or_default();
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td></tr>",
+            escape(f.flag.name()),
+            escape(f.race_time.trim()),
+            escape(f.time_of_day.trim()),
+            duration,
+        );
+    }
+    html.push_str("</tbody>\n</table>\n</section>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::fixtures::{sample_session, session};
+
+    const LINES: &[&str] = &[
+        r#"$B,5,"Race 1""#,
+        r#"$C,1,"GT""#,
+        r#"$C,2,"P2""#,
+        r#"$E,"TRACKNAME","Sebring International Raceway""#,
+        r#"$E,"TRACKLENGTH","3.700""#,
+        r#"$A,"1","1",1,"John","Smith","",1"#,
+        r#"$A,"2","2",2,"Joe","<Bloggs>","",2"#,
+        r#"$F,9999,"00:00:00","12:00:00","00:00:00","Green ""#,
+        r#"$G,1,"2",2,"00:02:19.000""#,
+        r#"$G,2,"1",2,"00:02:20.000""#,
+        r#"$H,1,"2",2,"00:01:08.000""#,
+        r#"$H,2,"1",1,"00:01:10.000""#,
+        r#"$F,9999,"00:00:00","12:01:30","00:01:30","Yellow""#,
+        r#"$F,9999,"00:00:00","12:02:00","00:02:00","Green ""#,
+        r#"$F,9999,"00:00:00","12:02:20","00:02:20","Finish""#,
+        r#"$F,9999,"00:00:00","12:02:30","00:02:30","Finish""#,
+    ];
+
+    #[test]
+    fn test_renders_report() {
+        let html = Report::new()
+            .with_series("Endurance Cup")
+            .render(&session(LINES));
+
+        assert!(html.contains("<title>Race 1</title>"));
+        assert!(html.contains(r#"<p class="series">Endurance Cup</p>"#));
+        assert!(html.contains(
+            "<p class=\"event\">Sebring International Raceway &middot; 3.700 &middot; \
+             Results at 12:02:30</p>"
+        ));
+        assert!(html.contains("<h2>Classification</h2>"));
+        assert!(html.contains("<h2>GT Classification</h2>"));
+        assert!(html.contains("<h2>P2 Classification</h2>"));
+        assert!(html.contains("Joe &lt;Bloggs&gt;"));
+        assert!(html.contains("<td class=\"number\">+1.000</td>"));
+        assert!(html.contains(
+            "<tr><td>P2</td><td class=\"number\">2</td><td>Joe &lt;Bloggs&gt;</td>\
+             <td class=\"number\">2</td><td class=\"number\">1:08.000</td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr><td>Yellow</td><td class=\"number\">00:01:30</td>\
+             <td class=\"number\">12:01:30</td><td class=\"number\">0:30.000</td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr><td>Finish</td><td class=\"number\">00:02:20</td>\
+             <td class=\"number\">12:02:20</td><td class=\"number\">0:10.000</td></tr>"
+        ));
+    }
+
+    #[test]
+    fn test_renders_sample_report() {
+        let html = Report::new().render(&sample_session("2009_Sebring_ALMS_Session_5.txt"));
+
+        assert!(html.contains("<title>Test Session 5</title>"));
+        assert!(html.contains("<h2>LMP1 Classification</h2>"));
+        assert!(html.contains(
+            "<tbody>\n<tr><td class=\"number\">1</td><td class=\"number\">66</td>\
+             <td>de Ferran / Pagenaud</td><td>LMP1</td><td class=\"number\">41</td>\
+             <td class=\"number\">1:56:27.497</td><td class=\"number\"></td>\
+             <td class=\"number\">33</td><td class=\"number\">1:45.963</td></tr>"
+        ));
+    }
+
+    #[test]
+    fn test_fills_custom_template() {
+        let report = Report::new()
+            .with_template("<h1>{{ title }}</h1>{{series}}|{{footer}}|{{unknown}}|{{logo}}")
+            .with_series("A & B")
+            .with_footer("Provisional")
+            .with_logo("logo.png");
+        assert_eq!(
+            report.render(&Session::new()),
+            r#"<h1>Results</h1>A &amp; B|Provisional|{{unknown}}|<img src="logo.png" alt="">"#
+        );
+    }
+}
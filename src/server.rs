@@ -0,0 +1,137 @@
+//! An async TCP server which relays RMonitor records to any number of connected clients.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::protocol::Record;
+//! use rmonitor::server::RMonitorServer;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let server = RMonitorServer::bind("127.0.0.1:50000").await?;
+//!     let sender = server.sender();
+//!     tokio::spawn(server.run());
+//!
+//!     let record = Record::decode(r#"$B,5,"Friday free practice""#)?;
+//!     // Sending only fails when no clients are connected
+//!     let _ = sender.send(record);
+//!     # Ok(())
+//! }
+//! ```
+use futures::SinkExt;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::broadcast;
+use tokio_util::codec::FramedWrite;
+
+use crate::codec::RMonitorEncoder;
+use crate::protocol::Record;
+
+/// The number of records buffered for each client before slow clients start missing records.
+const CLIENT_BUFFER: usize = 1024;
+
+/// A TCP server which writes every record sent to it to all connected clients.
+#[derive(Debug)]
+pub struct RMonitorServer {
+    listener: TcpListener,
+    sender: broadcast::Sender<Record>,
+    encoder: RMonitorEncoder,
+}
+
+impl RMonitorServer {
+    /// Binds a server to the given address, ready to accept connections with [`run`].
+    ///
+    /// [`run`]: crate::server::RMonitorServer::run()
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let (sender, _) = broadcast::channel(CLIENT_BUFFER);
+        Ok(Self {
+            listener,
+            sender,
+            encoder: RMonitorEncoder::new(),
+        })
+    }
+
+    /// Sets the encoder used for writing records to clients, to configure the output encoding.
+    pub fn with_encoder(mut self, encoder: RMonitorEncoder) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Returns the local address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a sender for records to be written to all clients connected at the time.
+    pub fn sender(&self) -> broadcast::Sender<Record> {
+        self.sender.clone()
+    }
+
+    /// Accepts connections until an error occurs, spawning a task to serve each client.
+    pub async fn run(self) -> io::Result<()> {
+        loop {
+            let (socket, addr) = self.listener.accept().await?;
+            log::debug!("RMonitor client connected from {}", addr);
+
+            let receiver = self.sender.subscribe();
+            let encoder = self.encoder.clone();
+            tokio::spawn(async move {
+                // Dropped connections only affect that client
+                if let Err(e) = serve_client(socket, receiver, encoder).await {
+                    log::debug!("RMonitor client {} disconnected: {}", addr, e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve_client(
+    socket: TcpStream,
+    mut receiver: broadcast::Receiver<Record>,
+    encoder: RMonitorEncoder,
+) -> Result<(), crate::codec::RMonitorCodecError> {
+    let mut writer = FramedWrite::new(socket, encoder);
+
+    loop {
+        match receiver.recv().await {
+            Ok(record) => writer.send(record).await?,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                log::warn!("RMonitor client lagging, skipped {} records", missed);
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::client;
+    use crate::codec::RMonitorDecoder;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_relays_records_to_client() {
+        let server = RMonitorServer::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let sender = server.sender();
+        tokio::spawn(server.run());
+
+        let mut records = client::connect(addr, RMonitorDecoder::new_with_max_length(2048))
+            .await
+            .unwrap();
+
+        // Wait for the client to be subscribed before sending
+        while sender.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let record = Record::decode(r#"$B,5,"Friday free practice""#).unwrap();
+        sender.send(record.clone()).unwrap();
+
+        assert_eq!(records.next().await.unwrap().unwrap(), record);
+    }
+}
@@ -0,0 +1,1777 @@
+//! A model of the current state of an RMonitor session, built up from the record stream.
+//!
+//! # Example
+//!
+//! ```
+//! use rmonitor::protocol::Record;
+//! use rmonitor::session::{Session, StandingsOrder};
+//!
+//! let mut session = Session::new();
+//! for line in &[
+//!     r#"$A,"12","12",52474,"John","Johnson","USA",1"#,
+//!     r#"$A,"7","7",52475,"Jane","Doe","GBR",1"#,
+//!     r#"$G,1,"7",3,"00:06:14.227""#,
+//!     r#"$G,2,"12",3,"00:06:15.512""#,
+//! ] {
+//!     session.apply(&Record::decode(line).unwrap());
+//! }
+//!
+//! let standings = session.standings(StandingsOrder::Race, None);
+//! assert_eq!(standings[0].competitor.number, "7");
+//! assert_eq!(standings[1].competitor.number, "12");
+//! ```
+//!
+//! A [`SessionManager`] follows a connection carrying many sessions, archiving each in turn.
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+use core::time::Duration;
+
+use crate::protocol::*;
+use crate::time::{format_duration, parse_duration};
+
+/// The `Setting` key for the name of the track
+pub const TRACK_NAME: &str = "TRACKNAME";
+/// The `Setting` key for the length of the track
+pub const TRACK_LENGTH: &str = "TRACKLENGTH";
+
+/// A single completed lap
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lap {
+    /// The lap number, counting from 1
+    pub lap: u32,
+    pub laptime: Duration,
+    /// Total elapsed time at the end of the lap
+    pub total_time: Option<Duration>,
+}
+
+/// The known state of a single competitor, keyed on `registration_number`.
+///
+/// Entries are created for competitors referenced by position or passing records even before a
+/// `Competitor` record describing them has been received, in which case the descriptive fields
+/// will be empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompetitorState {
+    pub registration_number: String,
+    pub number: String,
+    pub transponder_number: Option<u32>,
+    pub first_name: String,
+    pub last_name: String,
+    pub nationality: String,
+    pub class_number: Option<u8>,
+    /// Additional data from a `CompetitorExt` record
+    pub additional_data: Option<String>,
+    /// Position in the running order from the latest `Race` record
+    pub race_position: Option<u16>,
+    /// Laps completed, from the latest `Race` record
+    pub laps: Option<u32>,
+    /// Total race time, from the latest `Race` or `Passing` record
+    pub total_time: Option<Duration>,
+    /// Position in the fastest-lap standings from the latest `PracticeQual` record
+    pub practice_position: Option<u16>,
+    /// The lap number of the best lap
+    pub best_lap: Option<u32>,
+    pub best_laptime: Option<Duration>,
+    pub last_laptime: Option<Duration>,
+    /// Every lap completed in this session, in order
+    pub lap_history: Vec<Lap>,
+    /// The best time for each track section, keyed on section name, if the IMSA enhanced
+    /// protocol is in use
+    pub best_sectors: BTreeMap<String, Duration>,
+}
+
+impl CompetitorState {
+    fn new(registration_number: &str) -> Self {
+        Self {
+            registration_number: registration_number.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the number of the lap the competitor is on, counted from passings, or from the
+    /// lap count of `Race` records if passings were missed, e.g. when joining a session late.
+    fn current_lap(&self) -> u32 {
+        let counted = self.lap_history.last().map_or(0, |l| l.lap);
+        match self.laps {
+            // A `Race` record may be sent before the passing completing the lap
+            Some(laps) if laps > counted + 1 => laps + 1,
+            _ => counted + 1,
+        }
+    }
+
+    /// Returns the car number, or the registration number for a competitor timed before its
+    /// `Competitor` record was received
+    pub fn display_number(&self) -> &str {
+        match self.number.as_str() {
+            "" => &self.registration_number,
+            number => number,
+        }
+    }
+
+    /// Returns the competitor's full name
+    pub fn name(&self) -> String {
+        let mut name = self.first_name.trim().to_owned();
+        let last = self.last_name.trim();
+        if !name.is_empty() && !last.is_empty() {
+            name.push(' ');
+        }
+        name.push_str(last);
+        name
+    }
+}
+
+/// A change of flag state, as seen in the heartbeat
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlagChange {
+    pub flag: Flag,
+    /// Time of day from the first heartbeat showing the new flag state
+    pub time_of_day: String,
+    /// Race time from the first heartbeat showing the new flag state
+    pub race_time: String,
+}
+
+/// Updates the descriptive fields shared by `Competitor` and `CompetitorExt`, returning `true` if
+/// any of them changed.
+fn describe(
+    c: &mut CompetitorState,
+    number: &str,
+    first_name: &str,
+    last_name: &str,
+    nationality: &str,
+    class_number: u8,
+) -> bool {
+    let changed = c.number != number
+        || c.first_name != first_name
+        || c.last_name != last_name
+        || c.nationality != nationality
+        || c.class_number != Some(class_number);
+
+    if changed {
+        c.number = number.to_owned();
+        c.first_name = first_name.to_owned();
+        c.last_name = last_name.to_owned();
+        c.nationality = nationality.to_owned();
+        c.class_number = Some(class_number);
+    }
+    changed
+}
+
+/// A change to the session state resulting from a single record, as returned by
+/// [`Session::update`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionChange {
+    /// A competitor was referenced for the first time
+    CompetitorAdded { registration_number: String },
+    /// A competitor's descriptive information (number, name, class, etc.) changed
+    CompetitorUpdated { registration_number: String },
+    /// A competitor's position changed in one of the standings orders
+    PositionChanged {
+        registration_number: String,
+        order: StandingsOrder,
+        previous: Option<u16>,
+        position: u16,
+    },
+    /// A competitor completed a lap
+    LapCompleted {
+        registration_number: String,
+        lap: Lap,
+    },
+    /// The flag state changed
+    FlagChanged { previous: Option<Flag>, flag: Flag },
+    /// A different run (session) was announced
+    RunChanged,
+    /// The session was reset by an `Init` record
+    Reset,
+    /// A record disagreed with earlier records
+    Inconsistency(Inconsistency),
+    /// A competitor set a personal, class or session best lap or sector time
+    NewBest(NewBest),
+}
+
+/// A disagreement between records, reported as a [`SessionChange::Inconsistency`] when the later
+/// record is applied.
+///
+/// These point to a problem with the timing system or its operation, such as a missed or
+/// corrected passing, and should be brought to the attention of the timing operator. The record
+/// is still applied.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inconsistency {
+    /// A `Passing` gave an earlier total time than the competitor's previous total time
+    TotalTimeWentBackwards {
+        registration_number: String,
+        previous: Duration,
+        total_time: Duration,
+    },
+    /// A `Race` record gave a lap count different to the laps counted from passings
+    LapCountMismatch {
+        registration_number: String,
+        laps: u32,
+        /// The number of the last lap completed by a passing
+        passings: u32,
+    },
+    /// A `Correction` gave a different car number to the competitor's `Competitor` record
+    CorrectionNumberMismatch {
+        registration_number: String,
+        number: String,
+        competitor_number: String,
+    },
+    /// A `CompetitorExt` record disagreed with the latest `Competitor` record on a field
+    CompetitorExtMismatch {
+        registration_number: String,
+        /// The name of the field, e.g. `"last_name"`
+        field: &'static str,
+        competitor: String,
+        competitor_ext: String,
+    },
+}
+
+impl Inconsistency {
+    /// Returns the name of the inconsistency, e.g. `"lap_count_mismatch"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Inconsistency::TotalTimeWentBackwards { .. } => "total_time_went_backwards",
+            Inconsistency::LapCountMismatch { .. } => "lap_count_mismatch",
+            Inconsistency::CorrectionNumberMismatch { .. } => "correction_number_mismatch",
+            Inconsistency::CompetitorExtMismatch { .. } => "competitor_ext_mismatch",
+        }
+    }
+
+    /// Returns the registration number of the competitor concerned
+    pub fn registration_number(&self) -> &str {
+        match self {
+            Inconsistency::TotalTimeWentBackwards {
+                registration_number,
+                ..
+            }
+            | Inconsistency::LapCountMismatch {
+                registration_number,
+                ..
+            }
+            | Inconsistency::CorrectionNumberMismatch {
+                registration_number,
+                ..
+            }
+            | Inconsistency::CompetitorExtMismatch {
+                registration_number,
+                ..
+            } => registration_number,
+        }
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::TotalTimeWentBackwards {
+                registration_number,
+                previous,
+                total_time,
+            } => write!(
+                f,
+                "total time of {} went backwards from {} to {}",
+                registration_number,
+                format_duration(*previous),
+                format_duration(*total_time)
+            ),
+            Inconsistency::LapCountMismatch {
+                registration_number,
+                laps,
+                passings,
+            } => write!(
+                f,
+                "{} has completed {} laps, but {} were counted from passings",
+                registration_number, laps, passings
+            ),
+            Inconsistency::CorrectionNumberMismatch {
+                registration_number,
+                number,
+                competitor_number,
+            } => write!(
+                f,
+                "correction for {} gives number {}, but the competitor's number is {}",
+                registration_number, number, competitor_number
+            ),
+            Inconsistency::CompetitorExtMismatch {
+                registration_number,
+                field,
+                competitor,
+                competitor_ext,
+            } => write!(
+                f,
+                "competitor records for {} disagree on {}: \"{}\" and \"{}\"",
+                registration_number, field, competitor, competitor_ext
+            ),
+        }
+    }
+}
+
+/// The standings a best time is the best of.
+///
+/// Timing screens highlight session bests in purple and personal bests in green.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BestScope {
+    /// The competitor's own best
+    Personal,
+    /// The best in the competitor's class
+    Class,
+    /// The best of the session
+    Session,
+}
+
+impl BestScope {
+    /// Returns the name of the scope, e.g. `"personal"`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BestScope::Personal => "personal",
+            BestScope::Class => "class",
+            BestScope::Session => "session",
+        }
+    }
+}
+
+/// A new best lap or sector time, reported as a [`SessionChange::NewBest`].
+///
+/// A time which is a session best is also reported as a class and personal best, each with the
+/// previous best in that scope.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewBest {
+    pub registration_number: String,
+    pub scope: BestScope,
+    /// The track section name for a sector time, or `None` for a lap time
+    pub sector: Option<String>,
+    /// The lap the time was set on, if known
+    pub lap: Option<u32>,
+    pub time: Duration,
+    /// The previous best time in the scope
+    pub previous: Option<Duration>,
+    /// The registration number of the competitor which set the previous best
+    pub previous_holder: Option<String>,
+    /// How much faster the time is than the previous best
+    pub improvement: Option<Duration>,
+}
+
+impl fmt::Display for NewBest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} set a {} best ",
+            self.registration_number,
+            self.scope.as_str()
+        )?;
+        match &self.sector {
+            Some(sector) => write!(f, "sector {}", sector)?,
+            None => f.write_str("lap")?,
+        }
+        write!(f, " of {}", format_duration(self.time))?;
+        if let (Some(improvement), Some(holder)) = (self.improvement, &self.previous_holder) {
+            // Written as a gap, without the sign
+            let improvement = Gap::Time(improvement).to_string();
+            write!(f, ", {} faster than ", improvement.trim_start_matches('+'))?;
+            if *holder == self.registration_number {
+                f.write_str("their previous best")?;
+            } else {
+                f.write_str(holder)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The ordering used when computing standings.
+///
+/// Both `Race` and `PracticeQual` records are sent in every session, so the interpretation
+/// depends on the type of session in progress.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StandingsOrder {
+    /// Ordered by position in the running order, i.e. laps completed and total time
+    Race,
+    /// Ordered by fastest lap
+    PracticeQual,
+}
+
+/// The type of a session, which the protocol never states
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SessionType {
+    Practice,
+    Qualifying,
+    Race,
+}
+
+impl SessionType {
+    /// Returns the name of the session type, e.g. `"qualifying"`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SessionType::Practice => "practice",
+            SessionType::Qualifying => "qualifying",
+            SessionType::Race => "race",
+        }
+    }
+
+    /// Returns the standings order used for this type of session
+    pub fn standings_order(self) -> StandingsOrder {
+        match self {
+            SessionType::Race => StandingsOrder::Race,
+            SessionType::Practice | SessionType::Qualifying => StandingsOrder::PracticeQual,
+        }
+    }
+}
+
+/// The session type inferred by [`Session::session_type`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SessionTypeEstimate {
+    pub session_type: SessionType,
+    /// From `0.0` when there is no evidence either way, to `1.0`
+    pub confidence: f32,
+    /// Whether the session type was set with [`Session::set_session_type`]
+    pub overridden: bool,
+}
+
+/// Keywords in run descriptions, matched against the start of each word
+const RACE_KEYWORDS: &[&str] = &["race", "feature", "heat", "final", "sprint"];
+const QUALIFYING_KEYWORDS: &[&str] = &["qual", "pole", "superpole", "shootout"];
+const PRACTICE_KEYWORDS: &[&str] = &["practi", "warm", "test", "fp"];
+
+/// Returns the session type suggested by a run description
+fn session_type_keyword(description: &str) -> Option<SessionType> {
+    let matches = |keywords: &[&str]| {
+        description
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| {
+                keywords.iter().any(|k| {
+                    word.get(..k.len())
+                        .is_some_and(|w| w.eq_ignore_ascii_case(k))
+                })
+            })
+    };
+
+    // Race keywords come first, as a "Qualifying Race" or a "Test Race" is still a race
+    if matches(RACE_KEYWORDS) {
+        Some(SessionType::Race)
+    } else if matches(QUALIFYING_KEYWORDS) {
+        Some(SessionType::Qualifying)
+    } else if matches(PRACTICE_KEYWORDS) {
+        Some(SessionType::Practice)
+    } else {
+        None
+    }
+}
+
+/// The gap between two competitors
+///
+/// Displayed as on a timing screen, e.g. `+1.234`, `+1:02.345` or `+2 laps`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gap {
+    Time(Duration),
+    Laps(u32),
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Gap::Time(d) if d < Duration::from_secs(60) => {
+                write!(f, "+{}.{:03}", d.as_secs(), d.subsec_millis())
+            }
+            Gap::Time(d) => write!(f, "+{}", format_duration(d)),
+            Gap::Laps(1) => f.write_str("+1 lap"),
+            Gap::Laps(n) => write!(f, "+{} laps", n),
+        }
+    }
+}
+
+/// A competitor's place in the standings
+#[derive(Clone, Debug)]
+pub struct Standing<'a> {
+    /// Position within the standings, which is the class position if filtered by class
+    pub position: usize,
+    pub competitor: &'a CompetitorState,
+    /// Gap to the leader of these standings, `None` for the leader or if not known
+    pub gap: Option<Gap>,
+    /// Gap to the competitor immediately ahead, `None` for the leader or if not known
+    pub interval: Option<Gap>,
+}
+
+/// The current state of a session
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    /// The current run, from the latest `Run` record
+    pub run: Option<Run>,
+    /// Class descriptions, keyed on class number
+    pub classes: BTreeMap<u8, String>,
+    /// Track settings, keyed on description (e.g. `TRACKNAME`)
+    pub settings: BTreeMap<String, String>,
+    /// The latest heartbeat
+    pub heartbeat: Option<Heartbeat>,
+    /// Every change of flag state seen in this session, in order
+    pub flag_history: Vec<FlagChange>,
+    /// The latest track description, if the IMSA enhanced protocol is in use
+    pub track: Option<TrackDescription>,
+    /// Competitors, keyed on registration number
+    pub competitors: BTreeMap<String, CompetitorState>,
+    /// The latest `Competitor` record for each registration number, to check `CompetitorExt`
+    /// records against
+    competitor_records: BTreeMap<String, Competitor>,
+    session_type: Option<SessionType>,
+    /// Whether a heartbeat has given a number of laps to go
+    lap_limited: bool,
+    race_position_changes: u32,
+    practice_position_changes: u32,
+    /// The time of day each competitor last crossed each timeline, keyed on registration number
+    /// then timeline number
+    crossings: BTreeMap<String, BTreeMap<String, Duration>>,
+}
+
+impl Session {
+    /// Returns an empty `Session`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the session state with a single record
+    pub fn apply(&mut self, record: &Record) {
+        self.update(record);
+    }
+
+    /// Updates the session state with a single record, returning the resulting changes.
+    pub fn update(&mut self, record: &Record) -> Vec<SessionChange> {
+        let mut changes = Vec::new();
+
+        match record {
+            Record::Heartbeat(r) => {
+                let previous = self.flag();
+                if previous != Some(&r.flag_status) {
+                    changes.push(SessionChange::FlagChanged {
+                        previous: previous.cloned(),
+                        flag: r.flag_status.clone(),
+                    });
+                    self.flag_history.push(FlagChange {
+                        flag: r.flag_status.clone(),
+                        time_of_day: r.time_of_day.clone(),
+                        race_time: r.race_time.clone(),
+                    });
+                }
+                // 9999 is sent when the session isn't limited by laps, and 0 before it starts
+                if r.laps_to_go > 0 && r.laps_to_go < 9999 {
+                    self.lap_limited = true;
+                }
+                self.heartbeat = Some(r.clone());
+            }
+            Record::Competitor(r) => {
+                self.competitor_records
+                    .insert(r.registration_number.clone(), r.clone());
+                let (c, added) = self.competitor_mut(&r.registration_number, &mut changes);
+                let changed = describe(
+                    c,
+                    &r.number,
+                    &r.first_name,
+                    &r.last_name,
+                    &r.nationality,
+                    r.class_number,
+                ) | (c.transponder_number != Some(r.transponder_number));
+                c.transponder_number = Some(r.transponder_number);
+                if changed && !added {
+                    changes.push(SessionChange::CompetitorUpdated {
+                        registration_number: r.registration_number.clone(),
+                    });
+                }
+            }
+            Record::CompetitorExt(r) => {
+                if let Some(a) = self.competitor_records.get(&r.registration_number) {
+                    let fields = [
+                        ("number", &a.number, &r.number),
+                        ("first_name", &a.first_name, &r.first_name),
+                        ("last_name", &a.last_name, &r.last_name),
+                        ("nationality", &a.nationality, &r.nationality),
+                    ];
+                    let class = (a.class_number.to_string(), r.class_number.to_string());
+                    for (field, competitor, competitor_ext) in fields
+                        .iter()
+                        .copied()
+                        .chain(core::iter::once(("class_number", &class.0, &class.1)))
+                    {
+                        if competitor.trim() != competitor_ext.trim() {
+                            changes.push(SessionChange::Inconsistency(
+                                Inconsistency::CompetitorExtMismatch {
+                                    registration_number: r.registration_number.clone(),
+                                    field,
+                                    competitor: competitor.clone(),
+                                    competitor_ext: competitor_ext.clone(),
+                                },
+                            ));
+                        }
+                    }
+                }
+
+                let (c, added) = self.competitor_mut(&r.registration_number, &mut changes);
+                let changed = describe(
+                    c,
+                    &r.number,
+                    &r.first_name,
+                    &r.last_name,
+                    &r.nationality,
+                    r.class_number,
+                ) | (c.additional_data.as_ref() != Some(&r.additional_data));
+                c.additional_data = Some(r.additional_data.clone());
+                if changed && !added {
+                    changes.push(SessionChange::CompetitorUpdated {
+                        registration_number: r.registration_number.clone(),
+                    });
+                }
+            }
+            Record::Run(r) => {
+                if self.run.as_ref() != Some(r) {
+                    changes.push(SessionChange::RunChanged);
+                }
+                self.run = Some(r.clone());
+            }
+            Record::Class(r) => {
+                self.classes.insert(r.number, r.description.clone());
+            }
+            Record::Setting(r) => {
+                self.settings.insert(r.description.clone(), r.value.clone());
+            }
+            Record::Race(r) => {
+                let (c, _) = self.competitor_mut(&r.registration_number, &mut changes);
+                let previous = c.race_position.replace(r.position);
+
+                // Only checked when the lap count changes, as each `Race` record is resent. Feeds
+                // resend stale records with earlier lap counts, and may send a record a lap ahead
+                // before the passing completing it, so only counts further ahead are reported.
+                if let (Some(laps), Some(last)) = (r.laps, c.lap_history.last()) {
+                    if c.laps != r.laps && laps > last.lap + 1 {
+                        changes.push(SessionChange::Inconsistency(
+                            Inconsistency::LapCountMismatch {
+                                registration_number: r.registration_number.clone(),
+                                laps,
+                                passings: last.lap,
+                            },
+                        ));
+                    }
+                }
+                c.laps = r.laps;
+                c.total_time = parse_duration(&r.total_time);
+                if previous != Some(r.position) {
+                    changes.push(SessionChange::PositionChanged {
+                        registration_number: r.registration_number.clone(),
+                        order: StandingsOrder::Race,
+                        previous,
+                        position: r.position,
+                    });
+                }
+            }
+            Record::PracticeQual(r) => {
+                let (c, _) = self.competitor_mut(&r.registration_number, &mut changes);
+                let previous = c.practice_position.replace(r.position);
+                let best_laptime = parse_duration(&r.best_laptime).filter(|t| !t.is_zero());
+                if previous != Some(r.position) {
+                    changes.push(SessionChange::PositionChanged {
+                        registration_number: r.registration_number.clone(),
+                        order: StandingsOrder::PracticeQual,
+                        previous,
+                        position: r.position,
+                    });
+                }
+
+                // The best lap is authoritative, even if slower than one seen from passings,
+                // but is only reported if it improves on it. A competitor without a best lap is
+                // sent a zero or sentinel time, which doesn't clear one seen from passings.
+                match best_laptime {
+                    Some(t) if c.best_laptime.is_none_or(|best| t < best) => self.record_best(
+                        &r.registration_number,
+                        None,
+                        Some(r.best_lap),
+                        t,
+                        &mut changes,
+                    ),
+                    Some(t) => {
+                        c.best_laptime = Some(t);
+                        c.best_lap = Some(r.best_lap);
+                    }
+                    None => {}
+                }
+            }
+            Record::Init(_) => {
+                // The scoreboard should be completely refreshed
+                *self = Self::new();
+                changes.push(SessionChange::Reset);
+            }
+            Record::Passing(r) => {
+                let (c, _) = self.competitor_mut(&r.registration_number, &mut changes);
+                let total_time = parse_duration(&r.total_time);
+                if let (Some(previous), Some(total_time)) = (c.total_time, total_time) {
+                    if total_time < previous {
+                        changes.push(SessionChange::Inconsistency(
+                            Inconsistency::TotalTimeWentBackwards {
+                                registration_number: r.registration_number.clone(),
+                                previous,
+                                total_time,
+                            },
+                        ));
+                    }
+                }
+                c.total_time = total_time;
+
+                // The first crossing of the line is reported with a zero laptime
+                if let Some(laptime) = parse_duration(&r.laptime).filter(|t| !t.is_zero()) {
+                    let lap = Lap {
+                        lap: c.current_lap(),
+                        laptime,
+                        total_time,
+                    };
+                    c.last_laptime = Some(laptime);
+                    c.lap_history.push(lap.clone());
+                    let improved = c.best_laptime.is_none_or(|best| laptime < best);
+                    changes.push(SessionChange::LapCompleted {
+                        registration_number: r.registration_number.clone(),
+                        lap: lap.clone(),
+                    });
+                    if improved {
+                        self.record_best(
+                            &r.registration_number,
+                            None,
+                            Some(lap.lap),
+                            laptime,
+                            &mut changes,
+                        );
+                    }
+                }
+            }
+            Record::Correction(r) => {
+                let (c, _) = self.competitor_mut(&r.registration_number, &mut changes);
+                if !c.number.is_empty() && c.number.trim() != r.number.trim() {
+                    changes.push(SessionChange::Inconsistency(
+                        Inconsistency::CorrectionNumberMismatch {
+                            registration_number: r.registration_number.clone(),
+                            number: r.number.clone(),
+                            competitor_number: c.number.clone(),
+                        },
+                    ));
+                }
+                c.total_time = parse_duration(&r.total_time);
+                if let Some(lap) = c.lap_history.iter_mut().find(|l| l.lap == r.laps) {
+                    lap.total_time = c.total_time;
+                }
+            }
+            Record::LineCrossing(r) => self.cross_line(r, &mut changes),
+            Record::TrackDescription(r) => self.track = Some(r.clone()),
+        }
+
+        for change in &changes {
+            if let SessionChange::PositionChanged {
+                order,
+                previous: Some(_),
+                ..
+            } = change
+            {
+                match order {
+                    StandingsOrder::Race => self.race_position_changes += 1,
+                    StandingsOrder::PracticeQual => self.practice_position_changes += 1,
+                }
+            }
+        }
+        changes
+    }
+
+    /// Returns the competitor with the given registration number, creating it if this is the
+    /// first time it has been seen. The returned flag is `true` if the competitor was added.
+    fn competitor_mut(
+        &mut self,
+        registration_number: &str,
+        changes: &mut Vec<SessionChange>,
+    ) -> (&mut CompetitorState, bool) {
+        let added = !self.competitors.contains_key(registration_number);
+        if added {
+            changes.push(SessionChange::CompetitorAdded {
+                registration_number: registration_number.to_owned(),
+            });
+        }
+
+        let c = self
+            .competitors
+            .entry(registration_number.to_owned())
+            .or_insert_with(|| CompetitorState::new(registration_number));
+        (c, added)
+    }
+
+    /// Records a lap or sector time which improves on the competitor's personal best, reporting
+    /// it along with any class or session best it also sets
+    fn record_best(
+        &mut self,
+        registration_number: &str,
+        sector: Option<&str>,
+        lap: Option<u32>,
+        time: Duration,
+        changes: &mut Vec<SessionChange>,
+    ) {
+        let c = &self.competitors[registration_number];
+        let personal = self.best(c, sector);
+        let class = c
+            .class_number
+            .map(|class| self.fastest(Some(class), sector));
+        let session = self.fastest(None, sector);
+
+        let c = self
+            .competitors
+            .get_mut(registration_number)
+            .expect("competitor is present");
+        match sector {
+            Some(sector) => {
+                c.best_sectors.insert(sector.to_owned(), time);
+            }
+            None => {
+                c.best_laptime = Some(time);
+                c.best_lap = lap;
+            }
+        }
+
+        // The previous best in each scope and its holder, with no class scope for a competitor
+        // without a class
+        let personal = personal.map(|t| (registration_number.to_owned(), t));
+        for (scope, previous) in [
+            (BestScope::Personal, Some(personal)),
+            (BestScope::Class, class),
+            (BestScope::Session, Some(session)),
+        ] {
+            let previous = match previous {
+                Some(previous) => previous,
+                None => continue,
+            };
+            if previous.as_ref().is_some_and(|(_, t)| time >= *t) {
+                continue;
+            }
+            changes.push(SessionChange::NewBest(NewBest {
+                registration_number: registration_number.to_owned(),
+                scope,
+                sector: sector.map(str::to_owned),
+                lap,
+                time,
+                previous: previous.as_ref().map(|(_, t)| *t),
+                improvement: previous.as_ref().and_then(|(_, t)| t.checked_sub(time)),
+                previous_holder: previous.map(|(holder, _)| holder),
+            }));
+        }
+    }
+
+    /// Returns the competitor's best lap time, or best time for a track section
+    fn best(&self, c: &CompetitorState, sector: Option<&str>) -> Option<Duration> {
+        match sector {
+            Some(sector) => c.best_sectors.get(sector).copied(),
+            None => c.best_laptime,
+        }
+    }
+
+    /// Returns the fastest lap or sector time of the session, optionally in a single class, and
+    /// the registration number of the competitor which set it
+    fn fastest(&self, class: Option<u8>, sector: Option<&str>) -> Option<(String, Duration)> {
+        self.competitors
+            .values()
+            .filter(|c| class.is_none() || c.class_number == class)
+            .filter_map(|c| Some((c, self.best(c, sector)?)))
+            .min_by_key(|(_, t)| *t)
+            .map(|(c, t)| (c.registration_number.clone(), t))
+    }
+
+    /// Times the track sections ending at the timeline crossed, from the competitor's crossing
+    /// of the timeline at the start of each section
+    fn cross_line(&mut self, r: &LineCrossing, changes: &mut Vec<SessionChange>) {
+        let registration_number = match self.competitor_by_number(&r.number) {
+            Some(c) => c.registration_number.clone(),
+            None => return,
+        };
+        let time = match parse_duration(&r.time) {
+            Some(time) => time,
+            None => return,
+        };
+
+        let crossings = self
+            .crossings
+            .entry(registration_number.clone())
+            .or_default();
+        let mut sectors = Vec::new();
+        for section in self.track.iter().flat_map(|t| &t.sections) {
+            if section.end != r.timeline_number {
+                continue;
+            }
+            // Crossings aren't dated, so a section spanning midnight isn't timed
+            if let Some(sector) = crossings
+                .get(&section.start)
+                .and_then(|start| time.checked_sub(*start))
+                .filter(|t| !t.is_zero())
+            {
+                sectors.push((section.name.clone(), sector));
+            }
+        }
+        crossings.insert(r.timeline_number.clone(), time);
+
+        let lap = Some(self.competitors[&registration_number].current_lap());
+        for (name, sector) in sectors {
+            let c = &self.competitors[&registration_number];
+            if self.best(c, Some(&name)).is_none_or(|best| sector < best) {
+                self.record_best(&registration_number, Some(&name), lap, sector, changes);
+            }
+        }
+    }
+
+    /// Returns the competitor with the given car number, as used by `LineCrossing` records
+    pub fn competitor_by_number(&self, number: &str) -> Option<&CompetitorState> {
+        self.competitors.values().find(|c| c.number == number)
+    }
+
+    /// Returns the widest scope a competitor's lap time, or time for a track section, is the
+    /// best of, for highlighting on timing screens.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rmonitor::protocol::Record;
+    /// use rmonitor::session::{BestScope, Session};
+    ///
+    /// let mut session = Session::new();
+    /// for line in &[
+    ///     r#"$A,"12","12",52474,"John","Johnson","USA",1"#,
+    ///     r#"$J,"12","00:01:52.104","00:01:52.104""#,
+    /// ] {
+    ///     session.apply(&Record::decode(line).unwrap());
+    /// }
+    ///
+    /// let c = &session.competitors["12"];
+    /// let highlight = session.highlight("12", None, c.last_laptime.unwrap());
+    /// assert_eq!(highlight, Some(BestScope::Session));
+    /// ```
+    pub fn highlight(
+        &self,
+        registration_number: &str,
+        sector: Option<&str>,
+        time: Duration,
+    ) -> Option<BestScope> {
+        let c = self.competitors.get(registration_number)?;
+        let is_fastest = |class| {
+            self.fastest(class, sector)
+                .is_some_and(|(_, fastest)| fastest == time)
+        };
+        if is_fastest(None) {
+            Some(BestScope::Session)
+        } else if c.class_number.is_some() && is_fastest(c.class_number) {
+            Some(BestScope::Class)
+        } else if self.best(c, sector) == Some(time) {
+            Some(BestScope::Personal)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the track name, from the `TRACKNAME` setting
+    pub fn track_name(&self) -> Option<&str> {
+        self.settings.get(TRACK_NAME).map(String::as_str)
+    }
+
+    /// Returns the track length, from the `TRACKLENGTH` setting
+    pub fn track_length(&self) -> Option<&str> {
+        self.settings.get(TRACK_LENGTH).map(String::as_str)
+    }
+
+    /// Returns the description of a class
+    pub fn class_name(&self, class_number: u8) -> Option<&str> {
+        self.classes.get(&class_number).map(String::as_str)
+    }
+
+    /// Returns the current flag state, if a heartbeat has been received
+    pub fn flag(&self) -> Option<&Flag> {
+        self.heartbeat.as_ref().map(|h| &h.flag_status)
+    }
+
+    /// Infers whether this is a practice, qualifying or race session, unless it has been set with
+    /// [`set_session_type`](Self::set_session_type).
+    ///
+    /// The evidence weighed is, from strongest to weakest: keywords in the run description, such
+    /// as "Race" or "Qualifying", a number of laps to go in heartbeats, whether competitors
+    /// completed their first lap together as from a race start, and whether the fastest lap order
+    /// has changed more often than the running order. Without any evidence the session is assumed
+    /// to be a race, with a confidence of zero.
+    pub fn session_type(&self) -> SessionTypeEstimate {
+        if let Some(session_type) = self.session_type {
+            return SessionTypeEstimate {
+                session_type,
+                confidence: 1.0,
+                overridden: true,
+            };
+        }
+
+        let keyword = self
+            .run
+            .as_ref()
+            .and_then(|r| session_type_keyword(&r.description));
+        let (mut race, mut other) = match keyword {
+            Some(SessionType::Race) => (3, 0),
+            Some(_) => (0, 3),
+            None => (0, 0),
+        };
+
+        if self.lap_limited {
+            race += 2;
+        }
+
+        // Cars take the start together, but leave the pit lane one by one in practice
+        let mut first_laps: Vec<Duration> = self
+            .competitors
+            .values()
+            .filter_map(|c| c.lap_history.first())
+            .filter(|l| l.lap == 1)
+            .filter_map(|l| l.total_time)
+            .collect();
+        first_laps.sort();
+        if first_laps.len() >= 3 {
+            let together = first_laps
+                .iter()
+                .filter(|t| **t - first_laps[0] <= Duration::from_secs(15))
+                .count();
+            if together * 4 >= first_laps.len() * 3 {
+                race += 2;
+            } else {
+                other += 1;
+            }
+        }
+
+        // The running order changes as cars pit in any session, so only the fastest lap order
+        // changing more often is evidence
+        let (race_changes, practice_changes) =
+            (self.race_position_changes, self.practice_position_changes);
+        if race_changes + practice_changes >= 20 && practice_changes > race_changes {
+            other += 1;
+        }
+
+        let session_type = if other > race {
+            match keyword {
+                Some(SessionType::Qualifying) => SessionType::Qualifying,
+                _ => SessionType::Practice,
+            }
+        } else {
+            SessionType::Race
+        };
+        let (winner, loser) = (race.max(other) as f32, race.min(other) as f32);
+        SessionTypeEstimate {
+            session_type,
+            confidence: (winner - loser) / (winner + loser + 1.0),
+            overridden: false,
+        }
+    }
+
+    /// Sets the session type, overriding the inferred type until the session is reset. `None`
+    /// returns to inferring it.
+    pub fn set_session_type(&mut self, session_type: Option<SessionType>) {
+        self.session_type = session_type;
+    }
+
+    /// Returns the current standings, optionally filtered to a single class.
+    ///
+    /// Competitors without a position in the requested ordering are placed at the end.
+    pub fn standings(&self, order: StandingsOrder, class: Option<u8>) -> Vec<Standing<'_>> {
+        let mut competitors: Vec<&CompetitorState> = self
+            .competitors
+            .values()
+            .filter(|c| class.is_none() || c.class_number == class)
+            .collect();
+
+        competitors.sort_by_key(|c| (position(c, order).is_none(), position(c, order)));
+        ranked(order, competitors)
+    }
+
+    /// Returns the classification, optionally filtered to a single class.
+    ///
+    /// Unlike [`Session::standings`], which follows the positions sent by the timing system, the
+    /// order is calculated from each competitor's laps completed and total time, or best lap time
+    /// for [`StandingsOrder::PracticeQual`]. Feeds resend stale positions, so a competitor which
+    /// never set a time may share a position with one which did. Competitors without a time are
+    /// placed at the end, in the order of their positions.
+    pub fn classification(&self, order: StandingsOrder, class: Option<u8>) -> Vec<Standing<'_>> {
+        let mut competitors: Vec<&CompetitorState> = self
+            .competitors
+            .values()
+            .filter(|c| class.is_none() || c.class_number == class)
+            .collect();
+
+        competitors.sort_by_key(|c| {
+            let time = match order {
+                StandingsOrder::Race => c.total_time,
+                StandingsOrder::PracticeQual => c.best_laptime,
+            };
+            let laps = match order {
+                StandingsOrder::Race => c.laps.unwrap_or(0),
+                StandingsOrder::PracticeQual => 0,
+            };
+            (
+                time.is_none(),
+                Reverse(laps),
+                time,
+                position(c, order).is_none(),
+                position(c, order),
+            )
+        });
+        ranked(order, competitors)
+    }
+}
+
+/// Returns a competitor's position in the given standings order, as sent by the timing system
+fn position(c: &CompetitorState, order: StandingsOrder) -> Option<u16> {
+    match order {
+        StandingsOrder::Race => c.race_position,
+        StandingsOrder::PracticeQual => c.practice_position,
+    }
+}
+
+/// Returns the standings of competitors which are already in order, with the gaps between them
+fn ranked(order: StandingsOrder, competitors: Vec<&CompetitorState>) -> Vec<Standing<'_>> {
+    let gap = |ahead: &CompetitorState, behind: &CompetitorState| match order {
+        StandingsOrder::Race => match (ahead.laps, behind.laps) {
+            (Some(a), Some(b)) if a > b => Some(Gap::Laps(a - b)),
+            (a, b) if a == b => behind
+                .total_time?
+                .checked_sub(ahead.total_time?)
+                .map(Gap::Time),
+            _ => None,
+        },
+        StandingsOrder::PracticeQual => behind
+            .best_laptime?
+            .checked_sub(ahead.best_laptime?)
+            .map(Gap::Time),
+    };
+
+    competitors
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Standing {
+            position: i + 1,
+            competitor: c,
+            gap: if i == 0 { None } else { gap(competitors[0], c) },
+            interval: if i == 0 {
+                None
+            } else {
+                gap(competitors[i - 1], c)
+            },
+        })
+        .collect()
+}
+
+/// Splits a continuous record stream into a session for each run.
+///
+/// Over a race day one connection carries many sessions, each started by an `Init` record or a
+/// `Run` record for a different run. The previous session is archived with its final state, so
+/// it can still be queried while the next is live. Sessions without any competitors, such as
+/// one announced and immediately reset, are discarded rather than archived.
+///
+/// Sessions are identified by their run number and description. If a session is archived twice,
+/// for example after a timing system refresh with `Init`, the later state replaces the earlier.
+#[derive(Clone, Debug, Default)]
+pub struct SessionManager {
+    current: Session,
+    archived: Vec<Session>,
+}
+
+impl SessionManager {
+    /// Returns a `SessionManager` without any sessions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the current session with a single record, archiving it first if the record starts
+    /// a new session
+    pub fn apply(&mut self, record: &Record) {
+        self.update(record);
+    }
+
+    /// Updates the current session with a single record, returning the resulting changes.
+    ///
+    /// A new session starts with [`SessionChange::Reset`] or [`SessionChange::RunChanged`].
+    pub fn update(&mut self, record: &Record) -> Vec<SessionChange> {
+        let new_session = match record {
+            Record::Init(_) => true,
+            Record::Run(r) => self.current.run.as_ref().is_some_and(|run| run != r),
+            _ => false,
+        };
+        if new_session {
+            self.archive();
+        }
+        self.current.update(record)
+    }
+
+    fn archive(&mut self) {
+        let session = core::mem::take(&mut self.current);
+        if session.competitors.is_empty() {
+            return;
+        }
+        if let Some(run) = &session.run {
+            self.archived.retain(|s| s.run.as_ref() != Some(run));
+        }
+        self.archived.push(session);
+    }
+
+    /// Returns the live session
+    pub fn current(&self) -> &Session {
+        &self.current
+    }
+
+    /// Returns the archived sessions, oldest first
+    pub fn archived(&self) -> &[Session] {
+        &self.archived
+    }
+
+    /// Returns the live or archived session for a run
+    pub fn get(&self, run_number: u8, description: &str) -> Option<&Session> {
+        core::iter::once(&self.current)
+            .chain(self.archived.iter().rev())
+            .find(|s| {
+                s.run
+                    .as_ref()
+                    .is_some_and(|r| r.number == run_number && r.description == description)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{sample_session, session};
+    use super::*;
+
+    #[test]
+    fn test_builds_competitors() {
+        let session = session(&[
+            r#"$A,"1234BE","12X",52474,"John","Johnson","USA",5"#,
+            r#"$COMP,"1234BE","12X",5,"John","Johnson","USA","CAMEL""#,
+            r#"$C,5,"Formula 3000""#,
+            r#"$E,"TRACKNAME","Indianapolis Motor Speedway""#,
+        ]);
+
+        let c = &session.competitors["1234BE"];
+        assert_eq!(c.name(), "John Johnson");
+        assert_eq!(c.transponder_number, Some(52474));
+        assert_eq!(c.additional_data.as_deref(), Some("CAMEL"));
+        assert_eq!(session.class_name(5), Some("Formula 3000"));
+        assert_eq!(session.track_name(), Some("Indianapolis Motor Speedway"));
+    }
+
+    #[test]
+    fn test_records_laps() {
+        let session = session(&[
+            r#"$J,"21","00:00:00.000","00:00:13.335""#,
+            r#"$G,1,"21",,"00:00:13.335""#,
+            r#"$J,"21","00:02:03.826","00:02:17.161""#,
+            r#"$G,1,"21",1,"00:02:17.161""#,
+            r#"$J,"21","00:02:01.100","00:04:18.261""#,
+            r#"$G,1,"21",2,"00:04:18.261""#,
+        ]);
+
+        let c = &session.competitors["21"];
+        assert_eq!(c.lap_history.len(), 2);
+        assert_eq!(c.lap_history[0].lap, 1);
+        assert_eq!(c.lap_history[1].lap, 2);
+        assert_eq!(c.last_laptime, Some(Duration::from_millis(121_100)));
+        assert_eq!(c.total_time, Some(Duration::from_millis(258_261)));
+    }
+
+    #[test]
+    fn test_computes_race_standings() {
+        let session = session(&[
+            r#"$A,"1","1",1,"","","",1"#,
+            r#"$A,"2","2",2,"","","",2"#,
+            r#"$A,"3","3",3,"","","",1"#,
+            r#"$G,3,"3",9,"00:20:00.000""#,
+            r#"$G,1,"1",10,"00:20:00.000""#,
+            r#"$G,2,"2",10,"00:20:01.500""#,
+        ]);
+
+        let standings = session.standings(StandingsOrder::Race, None);
+        let order: Vec<_> = standings
+            .iter()
+            .map(|s| s.competitor.number.as_str())
+            .collect();
+        assert_eq!(order, ["1", "2", "3"]);
+        assert_eq!(standings[0].gap, None);
+        assert_eq!(
+            standings[1].gap,
+            Some(Gap::Time(Duration::from_millis(1500)))
+        );
+        assert_eq!(standings[2].gap, Some(Gap::Laps(1)));
+        assert_eq!(standings[2].interval, Some(Gap::Laps(1)));
+
+        let class = session.standings(StandingsOrder::Race, Some(1));
+        assert_eq!(class.len(), 2);
+        assert_eq!(class[1].position, 2);
+        assert_eq!(class[1].competitor.number, "3");
+    }
+
+    #[test]
+    fn test_computes_practice_standings() {
+        let session = session(&[
+            r#"$H,2,"1",3,"00:02:17.872""#,
+            r#"$H,1,"2",5,"00:02:15.000""#,
+            r#"$H,3,"3",0,"00:00:00.000""#,
+        ]);
+
+        let standings = session.standings(StandingsOrder::PracticeQual, None);
+        assert_eq!(standings[0].competitor.registration_number, "2");
+        assert_eq!(
+            standings[1].gap,
+            Some(Gap::Time(Duration::from_millis(2872)))
+        );
+        assert_eq!(standings[2].competitor.best_laptime, None);
+        assert_eq!(standings[2].gap, None);
+    }
+
+    #[test]
+    fn test_reports_changes() {
+        let mut session = session(&[r#"$A,"1","1",1,"John","Smith","",1"#]);
+
+        let changes =
+            session.update(&Record::decode(r#"$A,"1","1",1,"John","Smith","",1"#).unwrap());
+        assert!(changes.is_empty());
+
+        let changes =
+            session.update(&Record::decode(r#"$A,"1","1",1,"Jon","Smith","",1"#).unwrap());
+        assert_eq!(
+            changes,
+            [SessionChange::CompetitorUpdated {
+                registration_number: "1".to_owned()
+            }]
+        );
+
+        let changes = session.update(&Record::decode(r#"$G,2,"2",,"00:00:13.335""#).unwrap());
+        assert_eq!(
+            changes,
+            [
+                SessionChange::CompetitorAdded {
+                    registration_number: "2".to_owned()
+                },
+                SessionChange::PositionChanged {
+                    registration_number: "2".to_owned(),
+                    order: StandingsOrder::Race,
+                    previous: None,
+                    position: 2,
+                }
+            ]
+        );
+
+        let changes =
+            session.update(&Record::decode(r#"$J,"2","00:01:10.000","00:01:23.335""#).unwrap());
+        assert!(matches!(
+            &changes[..],
+            [
+                SessionChange::LapCompleted {
+                    lap: Lap { lap: 1, .. },
+                    ..
+                },
+                SessionChange::NewBest(NewBest {
+                    scope: BestScope::Personal,
+                    ..
+                }),
+                SessionChange::NewBest(NewBest {
+                    scope: BestScope::Session,
+                    ..
+                }),
+            ]
+        ));
+
+        let changes = session.update(
+            &Record::decode(r#"$F,9999,"00:00:00","14:09:52","00:59:59","Green ""#).unwrap(),
+        );
+        assert_eq!(
+            changes,
+            [SessionChange::FlagChanged {
+                previous: None,
+                flag: Flag::Green
+            }]
+        );
+    }
+
+    /// Applies the lines to a new session, returning it and any inconsistencies reported
+    fn checked_session(lines: &[&str]) -> (Session, Vec<Inconsistency>) {
+        let mut session = Session::new();
+        let mut inconsistencies = Vec::new();
+        for line in lines {
+            for change in session.update(&Record::decode(line).unwrap()) {
+                if let SessionChange::Inconsistency(i) = change {
+                    inconsistencies.push(i);
+                }
+            }
+        }
+        (session, inconsistencies)
+    }
+
+    #[test]
+    fn test_numbers_laps_from_passings() {
+        // Practice and qualifying feeds may not send `Race` records at all
+        let (session, inconsistencies) = checked_session(&[
+            r#"$J,"21","00:00:00.000","00:00:13.335""#,
+            r#"$J,"21","00:02:03.826","00:02:17.161""#,
+            r#"$H,1,"21",1,"00:02:03.826""#,
+            r#"$J,"21","00:02:01.100","00:04:18.261""#,
+            r#"$H,1,"21",2,"00:02:01.100""#,
+            r#"$J,"21","00:02:05.000","00:06:23.261""#,
+        ]);
+
+        let c = &session.competitors["21"];
+        let laps: Vec<u32> = c.lap_history.iter().map(|l| l.lap).collect();
+        assert_eq!(laps, [1, 2, 3]);
+        assert_eq!(c.best_lap, Some(2));
+        assert!(inconsistencies.is_empty());
+    }
+
+    #[test]
+    fn test_numbers_laps_with_race_records_first() {
+        let (session, inconsistencies) = checked_session(&[
+            r#"$J,"21","00:00:00.000","00:00:13.335""#,
+            r#"$G,1,"21",1,"00:02:17.161""#,
+            r#"$J,"21","00:02:03.826","00:02:17.161""#,
+            r#"$G,1,"21",2,"00:04:18.261""#,
+            r#"$J,"21","00:02:01.100","00:04:18.261""#,
+            r#"$COR,"21","21",2,"00:04:18.300","+00:00:00.039""#,
+        ]);
+
+        let c = &session.competitors["21"];
+        let laps: Vec<u32> = c.lap_history.iter().map(|l| l.lap).collect();
+        assert_eq!(laps, [1, 2]);
+        assert_eq!(
+            c.lap_history[1].total_time,
+            Some(Duration::from_millis(258_300))
+        );
+        assert!(inconsistencies.is_empty());
+
+        // Joining a session late, the lap count is taken from `Race` records
+        let (late, inconsistencies) = checked_session(&[
+            r#"$G,1,"21",14,"00:29:00.000""#,
+            r#"$J,"21","00:02:00.000","00:31:00.000""#,
+        ]);
+        assert_eq!(late.competitors["21"].lap_history[0].lap, 15);
+        assert!(inconsistencies.is_empty());
+    }
+
+    #[test]
+    fn test_reports_inconsistencies() {
+        let mut session = session(&[
+            r#"$A,"1","12",1,"John","Smith","",1"#,
+            r#"$J,"1","00:01:10.000","00:01:10.000""#,
+            r#"$G,1,"1",1,"00:01:10.000""#,
+        ]);
+        let mut inconsistencies = |line: &str| -> Vec<_> {
+            session
+                .update(&Record::decode(line).unwrap())
+                .into_iter()
+                .filter_map(|change| match change {
+                    SessionChange::Inconsistency(i) => Some(i),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            inconsistencies(r#"$J,"1","00:01:05.000","00:01:05.000""#),
+            [Inconsistency::TotalTimeWentBackwards {
+                registration_number: "1".to_owned(),
+                previous: Duration::from_secs(70),
+                total_time: Duration::from_secs(65),
+            }]
+        );
+
+        // A lap ahead may be sent before its passing, resending the same lap count is not
+        // checked again
+        assert!(inconsistencies(r#"$G,1,"1",3,"00:01:05.000""#).is_empty());
+        let mismatch = inconsistencies(r#"$G,1,"1",4,"00:01:05.000""#);
+        assert_eq!(
+            mismatch,
+            [Inconsistency::LapCountMismatch {
+                registration_number: "1".to_owned(),
+                laps: 4,
+                passings: 2,
+            }]
+        );
+        assert_eq!(
+            mismatch[0].to_string(),
+            "1 has completed 4 laps, but 2 were counted from passings"
+        );
+        assert!(inconsistencies(r#"$G,1,"1",4,"00:01:05.000""#).is_empty());
+
+        assert_eq!(
+            inconsistencies(r#"$COR,"1","21",3,"00:01:05.000","+00:00:00.000""#)
+                .iter()
+                .map(Inconsistency::kind)
+                .collect::<Vec<_>>(),
+            ["correction_number_mismatch"]
+        );
+
+        assert!(inconsistencies(r#"$COMP,"1","12",1,"John","Smith","",""#).is_empty());
+        assert_eq!(
+            inconsistencies(r#"$COMP,"1","12",1,"John","Smyth","",""#),
+            [Inconsistency::CompetitorExtMismatch {
+                registration_number: "1".to_owned(),
+                field: "last_name",
+                competitor: "Smith".to_owned(),
+                competitor_ext: "Smyth".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reports_new_bests() {
+        let mut session = session(&[
+            r#"$A,"1","1",1,"","","",1"#,
+            r#"$A,"2","2",2,"","","",1"#,
+            r#"$A,"3","3",3,"","","",2"#,
+            r#"$J,"1","00:01:50.000","00:01:50.000""#,
+            r#"$J,"2","00:01:52.000","00:01:52.000""#,
+            r#"$G,2,"2",1,"00:01:52.000""#,
+        ]);
+        let bests = |changes: Vec<SessionChange>| -> Vec<NewBest> {
+            changes
+                .into_iter()
+                .filter_map(|c| match c {
+                    SessionChange::NewBest(best) => Some(best),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // A class best, but not a session best
+        let changes =
+            session.update(&Record::decode(r#"$J,"3","00:01:51.000","00:01:51.000""#).unwrap());
+        let scopes: Vec<_> = bests(changes).iter().map(|b| b.scope).collect();
+        assert_eq!(scopes, [BestScope::Personal, BestScope::Class]);
+
+        let changes =
+            session.update(&Record::decode(r#"$J,"2","00:01:49.500","00:03:41.500""#).unwrap());
+        let bests = bests(changes);
+        assert_eq!(bests.len(), 3);
+        assert_eq!(
+            bests[2],
+            NewBest {
+                registration_number: "2".to_owned(),
+                scope: BestScope::Session,
+                sector: None,
+                lap: Some(2),
+                time: Duration::from_millis(109_500),
+                previous: Some(Duration::from_secs(110)),
+                previous_holder: Some("1".to_owned()),
+                improvement: Some(Duration::from_millis(500)),
+            }
+        );
+        assert_eq!(
+            bests[2].to_string(),
+            "2 set a session best lap of 1:49.500, 0.500 faster than 1"
+        );
+        assert_eq!(bests[0].previous_holder.as_deref(), Some("2"));
+
+        // A best lap from the standings is only reported if it improves on the passings
+        let changes = session.update(&Record::decode(r#"$H,1,"2",2,"00:01:49.500""#).unwrap());
+        assert!(!changes
+            .iter()
+            .any(|c| matches!(c, SessionChange::NewBest(_))));
+        let changes = session.update(&Record::decode(r#"$H,2,"1",3,"00:01:49.000""#).unwrap());
+        assert!(changes.contains(&SessionChange::NewBest(NewBest {
+            registration_number: "1".to_owned(),
+            scope: BestScope::Session,
+            sector: None,
+            lap: Some(3),
+            time: Duration::from_secs(109),
+            previous: Some(Duration::from_millis(109_500)),
+            previous_holder: Some("2".to_owned()),
+            improvement: Some(Duration::from_millis(500)),
+        })));
+
+        let highlight = |registration_number, millis| {
+            session.highlight(registration_number, None, Duration::from_millis(millis))
+        };
+        assert_eq!(highlight("1", 109_000), Some(BestScope::Session));
+        assert_eq!(highlight("2", 109_500), Some(BestScope::Personal));
+        assert_eq!(highlight("3", 111_000), Some(BestScope::Class));
+        assert_eq!(highlight("2", 112_000), None);
+    }
+
+    #[test]
+    fn test_keeps_best_lap_without_standings_time() {
+        let mut session = session(&[
+            r#"$A,"1","1",1,"","","",1"#,
+            r#"$J,"1","00:00:00.000","00:00:10.000""#,
+            r#"$J,"1","00:01:50.000","00:02:00.000""#,
+            r#"$H,1,"1",0,"00:00:00.000""#,
+            r#"$H,1,"1",0,"00:59:59.999""#,
+        ]);
+        let c = &session.competitors["1"];
+        assert_eq!(c.best_laptime, Some(Duration::from_secs(110)));
+        assert_eq!(c.best_lap, Some(1));
+
+        // A slower lap isn't reported as a new best
+        let changes =
+            session.update(&Record::decode(r#"$J,"1","00:01:51.000","00:03:51.000""#).unwrap());
+        assert!(!changes
+            .iter()
+            .any(|c| matches!(c, SessionChange::NewBest(_))));
+    }
+
+    #[test]
+    fn test_times_sectors() {
+        let mut session = session(&[
+            r#"$A,"1","1",1,"","","",1"#,
+            r#"$T,"Sebring","SEB","3.70",2,"S1","T1","T2",1000,"S2","T2","T1",1000"#,
+            r#"$L,"1","T1","SFT","01/27/2009","14:19:00.000""#,
+        ]);
+
+        let changes = session
+            .update(&Record::decode(r#"$L,"1","T2","S1","01/27/2009","14:19:40.250""#).unwrap());
+        assert!(changes.contains(&SessionChange::NewBest(NewBest {
+            registration_number: "1".to_owned(),
+            scope: BestScope::Session,
+            sector: Some("S1".to_owned()),
+            lap: Some(1),
+            time: Duration::from_millis(40_250),
+            previous: None,
+            previous_holder: None,
+            improvement: None,
+        })));
+        assert_eq!(
+            session.competitors["1"].best_sectors["S1"],
+            Duration::from_millis(40_250)
+        );
+
+        session.apply(&Record::decode(r#"$L,"1","T1","SFT","01/27/2009","14:20:50.000""#).unwrap());
+        let changes = session
+            .update(&Record::decode(r#"$L,"1","T2","S1","01/27/2009","14:21:31.000""#).unwrap());
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_displays_gaps() {
+        assert_eq!(
+            Gap::Time(Duration::from_millis(1_234)).to_string(),
+            "+1.234"
+        );
+        assert_eq!(
+            Gap::Time(Duration::from_millis(62_345)).to_string(),
+            "+1:02.345"
+        );
+        assert_eq!(Gap::Laps(1).to_string(), "+1 lap");
+        assert_eq!(Gap::Laps(3).to_string(), "+3 laps");
+    }
+
+    #[test]
+    fn test_resets_on_init() {
+        let mut session = session(&[r#"$A,"1","1",1,"","","",1"#, r#"$B,5,"Practice""#]);
+        session.apply(&Record::decode(r#"$I,"16:36:08.000","12 jan 01""#).unwrap());
+
+        assert!(session.competitors.is_empty());
+        assert!(session.run.is_none());
+    }
+
+    #[test]
+    fn test_infers_session_type() {
+        let mut qualifying = session(&[r#"$B,5,"Friday Qualifying 2""#]);
+        let estimate = qualifying.session_type();
+        assert_eq!(estimate.session_type, SessionType::Qualifying);
+        assert_eq!(estimate.confidence, 0.75);
+        assert_eq!(
+            estimate.session_type.standings_order(),
+            StandingsOrder::PracticeQual
+        );
+
+        qualifying.set_session_type(Some(SessionType::Race));
+        assert_eq!(
+            qualifying.session_type(),
+            SessionTypeEstimate {
+                session_type: SessionType::Race,
+                confidence: 1.0,
+                overridden: true,
+            }
+        );
+
+        // Without a description, a lap limited session with a bunched start is a race
+        let mut lines = vec![r#"$F,12,"00:00:00","14:09:52","00:20:00","Green ""#.to_owned()];
+        for (car, total) in &[("1", "01:50.000"), ("2", "01:51.200"), ("3", "01:53.900")] {
+            lines.push(format!(r#"$J,"{}","00:{}","00:{}""#, car, total, total));
+        }
+        let lines: Vec<_> = lines.iter().map(String::as_str).collect();
+        let estimate = session(&lines).session_type();
+        assert_eq!(estimate.session_type, SessionType::Race);
+        assert!(estimate.confidence > 0.75);
+        assert_eq!(Session::new().session_type().confidence, 0.0);
+    }
+
+    #[test]
+    fn test_archives_sessions() {
+        let mut manager = SessionManager::new();
+        for line in &[
+            r#"$B,1,"Practice""#,
+            r#"$A,"1","1",1,"","","",1"#,
+            r#"$G,1,"1",3,"00:03:10.000""#,
+            r#"$B,2,"Qualifying""#,
+            r#"$I,"10:03:08","27 Jan 09""#,
+            r#"$B,3,"Race""#,
+            r#"$A,"7","7",7,"","","",1"#,
+        ] {
+            manager.apply(&Record::decode(line).unwrap());
+        }
+
+        // The qualifying session was reset before any competitors were seen
+        assert_eq!(manager.archived().len(), 1);
+        let practice = manager.get(1, "Practice").unwrap();
+        assert_eq!(practice.competitors["1"].laps, Some(3));
+        assert!(manager.get(2, "Qualifying").is_none());
+
+        assert_eq!(manager.current().run.as_ref().unwrap().number, 3);
+        assert!(manager.current().competitors.contains_key("7"));
+        assert!(!manager.current().competitors.contains_key("1"));
+    }
+
+    #[test]
+    fn test_applies_sample_session() {
+        // The samples are consistent, so replaying them reports no inconsistencies
+        for path in &[
+            "sample/2009_Sebring_ALMS_Session_4.txt",
+            "sample/2009_Sebring_ALMS_Session_4_Lites.txt",
+            "sample/2009_Sebring_ALMS_Session_5.txt",
+            "sample/Orbits_Mock_Session.txt",
+        ] {
+            let data = std::fs::read_to_string(path).unwrap();
+            let (_, inconsistencies) = checked_session(&data.lines().collect::<Vec<_>>());
+            assert_eq!(inconsistencies, [], "{}", path);
+        }
+
+        let session = sample_session("2009_Sebring_ALMS_Session_5.txt");
+        assert_eq!(session.track_name(), Some("Sebring International Raceway"));
+        assert_eq!(session.flag_history[0].flag, Flag::None);
+        assert!(session.flag_history.iter().any(|f| f.flag == Flag::Green));
+        assert!(session
+            .competitors
+            .values()
+            .any(|c| !c.lap_history.is_empty()));
+    }
+}
+
+/// Test fixtures shared with the modules built on a session.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// A practice session with two competitors, each in their own class
+    #[cfg(any(feature = "http", feature = "mqtt", feature = "sqlite"))]
+    pub const ENTRIES: &[&str] = &[
+        r#"$B,5,"Friday free practice""#,
+        r#"$C,1,"GT""#,
+        r#"$C,2,"Proto""#,
+        r#"$A,"1","1",1,"John","Smith","",1"#,
+        r#"$A,"2","2",2,"Joe","Bloggs","",2"#,
+    ];
+
+    /// Applies the lines to a new session
+    pub fn session(lines: &[&str]) -> Session {
+        let mut session = Session::new();
+        for line in lines {
+            session.apply(&Record::decode(line).unwrap());
+        }
+        session
+    }
+
+    /// Applies a capture from the `sample` directory to a new session
+    pub fn sample_session(file: &str) -> Session {
+        let data = std::fs::read_to_string(format!("sample/{}", file)).unwrap();
+        session(&data.lines().collect::<Vec<_>>())
+    }
+}
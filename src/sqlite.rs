@@ -0,0 +1,630 @@
+//! Persistence of decoded records to a normalized SQLite database.
+//!
+//! [`SqliteStore`] writes each record to the following tables, with every time stored as whole
+//! milliseconds (`*_ms` columns) alongside the time of day of the latest heartbeat:
+//!
+//! | Table            | Contents                                                            |
+//! |------------------|---------------------------------------------------------------------|
+//! | `runs`           | Each run (`$B`), started by the first record after an `$I` reset    |
+//! | `classes`        | Class descriptions (`$C`) for each run                              |
+//! | `settings`       | Track settings (`$E`) for each run                                  |
+//! | `competitors`    | Competitors (`$A` and `$COMP`) for each run                         |
+//! | `passings`       | Every passing (`$J`)                                                |
+//! | `corrections`    | Every passing correction (`$COR`)                                   |
+//! | `positions`      | Snapshots of race (`$G`) and practice / qualifying (`$H`) positions |
+//! | `flag_periods`   | Each period of a flag state, from the heartbeat (`$F`)              |
+//! | `line_crossings` | Every timeline crossing (`$L`)                                      |
+//!
+//! Each record, or batch of records with [`SqliteStore::apply_all`], is written in a single
+//! transaction. Opening an existing database resumes the latest run, and records which are
+//! received again (for example when the timing software replays the session on reconnection)
+//! are ignored rather than duplicated. Times which identify a record, but are missing or the
+//! `00:59:59.999` sentinel, are stored as `-1` so that they're still compared as equal.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::sqlite::SqliteStore;
+//! use rmonitor::RMonitorReader;
+//! use std::net::TcpStream;
+//! # use std::error::Error;
+//!
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! let mut store = SqliteStore::open("results.db")?;
+//! for record in RMonitorReader::new(TcpStream::connect("127.0.0.1:50000")?) {
+//!     store.apply(&record?)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+
+use crate::protocol::{Flag, Record};
+use crate::time::{parse_millis, parse_signed_millis};
+
+/// Version of the schema created by this module, stored in `PRAGMA user_version`
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    number INTEGER,
+    description TEXT,
+    init_date TEXT,
+    init_time TEXT
+);
+CREATE TABLE IF NOT EXISTS classes (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    number INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    PRIMARY KEY (run_id, number)
+);
+CREATE TABLE IF NOT EXISTS settings (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    description TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (run_id, description)
+);
+CREATE TABLE IF NOT EXISTS competitors (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    registration_number TEXT NOT NULL,
+    number TEXT,
+    transponder_number INTEGER,
+    first_name TEXT,
+    last_name TEXT,
+    nationality TEXT,
+    class_number INTEGER,
+    additional_data TEXT,
+    UNIQUE (run_id, registration_number)
+);
+CREATE TABLE IF NOT EXISTS passings (
+    id INTEGER PRIMARY KEY,
+    competitor_id INTEGER NOT NULL REFERENCES competitors (id),
+    laptime_ms INTEGER,
+    total_time_ms INTEGER NOT NULL DEFAULT -1,
+    time_of_day TEXT,
+    UNIQUE (competitor_id, total_time_ms)
+);
+CREATE TABLE IF NOT EXISTS corrections (
+    id INTEGER PRIMARY KEY,
+    competitor_id INTEGER NOT NULL REFERENCES competitors (id),
+    laps INTEGER NOT NULL,
+    total_time_ms INTEGER NOT NULL DEFAULT -1,
+    correction_ms INTEGER,
+    time_of_day TEXT,
+    UNIQUE (competitor_id, laps, total_time_ms)
+);
+CREATE TABLE IF NOT EXISTS positions (
+    id INTEGER PRIMARY KEY,
+    competitor_id INTEGER NOT NULL REFERENCES competitors (id),
+    kind TEXT NOT NULL CHECK (kind IN ('race', 'practice')),
+    position INTEGER NOT NULL,
+    laps INTEGER NOT NULL DEFAULT -1,
+    total_time_ms INTEGER NOT NULL DEFAULT -1,
+    best_lap INTEGER NOT NULL DEFAULT -1,
+    best_laptime_ms INTEGER NOT NULL DEFAULT -1,
+    time_of_day TEXT,
+    UNIQUE (competitor_id, kind, position, laps, total_time_ms, best_lap, best_laptime_ms)
+);
+CREATE TABLE IF NOT EXISTS flag_periods (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    flag TEXT NOT NULL,
+    start_time_of_day TEXT NOT NULL,
+    start_race_time TEXT NOT NULL,
+    end_time_of_day TEXT,
+    end_race_time TEXT
+);
+CREATE TABLE IF NOT EXISTS line_crossings (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    number TEXT NOT NULL,
+    timeline_number TEXT NOT NULL,
+    timeline_name TEXT NOT NULL,
+    date TEXT NOT NULL,
+    time TEXT NOT NULL,
+    driver_id INTEGER,
+    class_name TEXT,
+    UNIQUE (run_id, number, timeline_number, date, time)
+);
+"#;
+
+/// State carried between records, which is only updated once a transaction commits
+#[derive(Clone, Debug, Default)]
+struct State {
+    run_id: Option<i64>,
+    /// The open flag period and its flag
+    flag_period: Option<(i64, Flag)>,
+    time_of_day: Option<String>,
+    race_time: Option<String>,
+    /// The `Init` record which reset the session, to be stored with the next run
+    init: Option<(String, String)>,
+}
+
+/// Writes decoded records to a SQLite database.
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Connection,
+    state: State,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `path`, resuming its latest run.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Returns a `SqliteStore` writing to a new in-memory database
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Returns a `SqliteStore` writing to an open connection, creating the schema if required
+    /// and resuming the latest run.
+    pub fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            log::warn!(
+                "Database schema version {} is newer than supported version {}",
+                version,
+                SCHEMA_VERSION
+            );
+        }
+        conn.execute_batch(SCHEMA)?;
+        if version < SCHEMA_VERSION {
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+
+        let mut state = State {
+            run_id: conn.query_row("SELECT max(id) FROM runs", [], |row| row.get(0))?,
+            ..State::default()
+        };
+        if let Some(run_id) = state.run_id {
+            let open_period = conn
+                .query_row(
+                    "SELECT id, flag, start_time_of_day, start_race_time FROM flag_periods
+                     WHERE run_id = ?1 AND end_time_of_day IS NULL ORDER BY id DESC LIMIT 1",
+                    [run_id],
+                    |row| {
+                        let flag: String = row.get(1)?;
+                        Ok((row.get(0)?, flag, row.get(2)?, row.get(3)?))
+                    },
+                )
+                .optional()?;
+            if let Some((id, flag, time_of_day, race_time)) = open_period {
+                state.flag_period = Some((id, Flag::from(flag.as_str())));
+                state.time_of_day = Some(time_of_day);
+                state.race_time = Some(race_time);
+            }
+        }
+
+        Ok(Self { conn, state })
+    }
+
+    /// Returns the underlying connection, for example to query the stored records
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Returns the ID of the current run in the `runs` table
+    pub fn run_id(&self) -> Option<i64> {
+        self.state.run_id
+    }
+
+    /// Writes a single record in its own transaction.
+    pub fn apply(&mut self, record: &Record) -> rusqlite::Result<()> {
+        self.apply_all(core::iter::once(record))
+    }
+
+    /// Writes every record from `records` in a single transaction, which is considerably faster
+    /// than writing each record individually. If any write fails, none of the records are
+    /// stored.
+    pub fn apply_all<'a, I>(&mut self, records: I) -> rusqlite::Result<()>
+    where
+        I: IntoIterator<Item = &'a Record>,
+    {
+        let mut state = self.state.clone();
+        let tx = self.conn.transaction()?;
+        for record in records {
+            write(&tx, &mut state, record)?;
+        }
+        tx.commit()?;
+        self.state = state;
+        Ok(())
+    }
+}
+
+/// Returns the current run, creating it if this is the first record since a reset
+fn run_id(tx: &Transaction, state: &mut State) -> rusqlite::Result<i64> {
+    if let Some(id) = state.run_id {
+        return Ok(id);
+    }
+    let (date, time) = state.init.take().unzip();
+    tx.execute(
+        "INSERT INTO runs (init_date, init_time) VALUES (?1, ?2)",
+        params![date, time],
+    )?;
+    let id = tx.last_insert_rowid();
+    state.run_id = Some(id);
+    Ok(id)
+}
+
+/// Returns the ID of a competitor in the current run, creating them if they are unknown
+fn competitor_id(
+    tx: &Transaction,
+    state: &mut State,
+    registration_number: &str,
+) -> rusqlite::Result<i64> {
+    let run_id = run_id(tx, state)?;
+    tx.prepare_cached(
+        "INSERT INTO competitors (run_id, registration_number) VALUES (?1, ?2)
+         ON CONFLICT (run_id, registration_number) DO NOTHING",
+    )?
+    .execute(params![run_id, registration_number])?;
+    tx.prepare_cached("SELECT id FROM competitors WHERE run_id = ?1 AND registration_number = ?2")?
+        .query_row(params![run_id, registration_number], |row| row.get(0))
+}
+
+fn close_flag_period(tx: &Transaction, state: &mut State) -> rusqlite::Result<()> {
+    if let Some((id, _)) = state.flag_period.take() {
+        tx.execute(
+            "UPDATE flag_periods SET end_time_of_day = ?2, end_race_time = ?3 WHERE id = ?1",
+            params![id, state.time_of_day, state.race_time],
+        )?;
+    }
+    Ok(())
+}
+
+fn write(tx: &Transaction, state: &mut State, record: &Record) -> rusqlite::Result<()> {
+    match record {
+        Record::Heartbeat(r) => {
+            state.time_of_day = Some(r.time_of_day.clone());
+            state.race_time = Some(r.race_time.clone());
+            if state.flag_period.as_ref().map(|(_, flag)| flag) != Some(&r.flag_status) {
+                close_flag_period(tx, state)?;
+                let run_id = run_id(tx, state)?;
+                tx.execute(
+                    "INSERT INTO flag_periods (run_id, flag, start_time_of_day, start_race_time)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![run_id, r.flag_status.name(), r.time_of_day, r.race_time],
+                )?;
+                state.flag_period = Some((tx.last_insert_rowid(), r.flag_status.clone()));
+            }
+        }
+        Record::Competitor(r) => {
+            let id = competitor_id(tx, state, &r.registration_number)?;
+            tx.prepare_cached(
+                "UPDATE competitors SET number = ?2, transponder_number = ?3, first_name = ?4,
+                 last_name = ?5, nationality = ?6, class_number = ?7 WHERE id = ?1",
+            )?
+            .execute(params![
+                id,
+                r.number,
+                r.transponder_number,
+                r.first_name,
+                r.last_name,
+                r.nationality,
+                r.class_number
+            ])?;
+        }
+        Record::CompetitorExt(r) => {
+            let id = competitor_id(tx, state, &r.registration_number)?;
+            tx.prepare_cached(
+                "UPDATE competitors SET number = ?2, class_number = ?3, first_name = ?4,
+                 last_name = ?5, nationality = ?6, additional_data = ?7 WHERE id = ?1",
+            )?
+            .execute(params![
+                id,
+                r.number,
+                r.class_number,
+                r.first_name,
+                r.last_name,
+                r.nationality,
+                r.additional_data
+            ])?;
+        }
+        Record::Run(r) => {
+            let current: Option<(Option<u8>, Option<String>)> = match state.run_id {
+                Some(id) => tx
+                    .query_row(
+                        "SELECT number, description FROM runs WHERE id = ?1",
+                        [id],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?,
+                None => None,
+            };
+            match current {
+                // Resuming the same run, for example after a restart
+                Some((Some(number), Some(description)))
+                    if number == r.number && description == r.description => {}
+                // The current run was started by earlier records, so is this one
+                Some((None, _)) => {
+                    tx.execute(
+                        "UPDATE runs SET number = ?2, description = ?3 WHERE id = ?1",
+                        params![state.run_id, r.number, r.description],
+                    )?;
+                }
+                _ => {
+                    close_flag_period(tx, state)?;
+                    state.run_id = None;
+                    let id = run_id(tx, state)?;
+                    tx.execute(
+                        "UPDATE runs SET number = ?2, description = ?3 WHERE id = ?1",
+                        params![id, r.number, r.description],
+                    )?;
+                }
+            }
+        }
+        Record::Class(r) => {
+            let run_id = run_id(tx, state)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO classes (run_id, number, description) VALUES (?1, ?2, ?3)",
+                params![run_id, r.number, r.description],
+            )?;
+        }
+        Record::Setting(r) => {
+            let run_id = run_id(tx, state)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO settings (run_id, description, value) VALUES (?1, ?2, ?3)",
+                params![run_id, r.description, r.value],
+            )?;
+        }
+        Record::Race(r) => {
+            let id = competitor_id(tx, state, &r.registration_number)?;
+            tx.prepare_cached(
+                "INSERT OR IGNORE INTO positions
+                 (competitor_id, kind, position, laps, total_time_ms, time_of_day)
+                 VALUES (?1, 'race', ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                id,
+                r.position,
+                r.laps.map_or(-1, i64::from),
+                parse_millis(&r.total_time).unwrap_or(-1),
+                state.time_of_day
+            ])?;
+        }
+        Record::PracticeQual(r) => {
+            let id = competitor_id(tx, state, &r.registration_number)?;
+            tx.prepare_cached(
+                "INSERT OR IGNORE INTO positions
+                 (competitor_id, kind, position, best_lap, best_laptime_ms, time_of_day)
+                 VALUES (?1, 'practice', ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                id,
+                r.position,
+                r.best_lap,
+                parse_millis(&r.best_laptime).unwrap_or(-1),
+                state.time_of_day
+            ])?;
+        }
+        Record::Init(r) => {
+            // The scoreboard is reset, so the next record begins a new run
+            close_flag_period(tx, state)?;
+            state.run_id = None;
+            state.init = Some((r.date.clone(), r.time.clone()));
+        }
+        Record::Passing(r) => {
+            let id = competitor_id(tx, state, &r.registration_number)?;
+            tx.prepare_cached(
+                "INSERT OR IGNORE INTO passings
+                 (competitor_id, laptime_ms, total_time_ms, time_of_day)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(params![
+                id,
+                parse_millis(&r.laptime),
+                parse_millis(&r.total_time).unwrap_or(-1),
+                state.time_of_day
+            ])?;
+        }
+        Record::Correction(r) => {
+            let id = competitor_id(tx, state, &r.registration_number)?;
+            tx.prepare_cached(
+                "INSERT OR IGNORE INTO corrections
+                 (competitor_id, laps, total_time_ms, correction_ms, time_of_day)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                id,
+                r.laps,
+                parse_millis(&r.total_time).unwrap_or(-1),
+                parse_signed_millis(&r.correction),
+                state.time_of_day
+            ])?;
+        }
+        Record::LineCrossing(r) => {
+            let run_id = run_id(tx, state)?;
+            tx.prepare_cached(
+                "INSERT OR IGNORE INTO line_crossings (run_id, number, timeline_number,
+                 timeline_name, date, time, driver_id, class_name)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?
+            .execute(params![
+                run_id,
+                r.number,
+                r.timeline_number,
+                r.timeline_name,
+                r.date,
+                r.time,
+                r.driver_id,
+                r.class_name
+            ])?;
+        }
+        Record::TrackDescription(_) => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::fixtures::ENTRIES;
+
+    /// The shared entries, from a reset to a line crossing
+    fn lines() -> Vec<&'static str> {
+        let reset = [r#"$I,"16:36:08.000","12 jan 01""#];
+        [&reset, ENTRIES, LINES].concat()
+    }
+
+    const LINES: &[&str] = &[
+        r#"$E,"TRACKNAME","Indianapolis Motor Speedway""#,
+        r#"$COMP,"2","2",1,"Joe","Bloggs","GBR","Team""#,
+        r#"$F,9999,"00:00:00","14:09:52","00:00:00","Green ""#,
+        r#"$J,"1","00:01:10.000","00:01:10.000""#,
+        r#"$G,1,"1",1,"00:01:10.000""#,
+        r#"$H,1,"1",1,"00:01:10.000""#,
+        r#"$F,9999,"00:00:00","14:10:52","00:01:00","Yellow""#,
+        r#"$COR,"1","1",1,"00:01:10.100","+00:00:00.100""#,
+        r#"$L,"1","P1","SF","2010-01-01","14:10:53.000""#,
+    ];
+
+    fn records(lines: &[&str]) -> Vec<Record> {
+        lines.iter().map(|l| Record::decode(l).unwrap()).collect()
+    }
+
+    fn count(store: &SqliteStore, table: &str) -> i64 {
+        let sql = format!("SELECT count(*) FROM {}", table);
+        store
+            .connection()
+            .query_row(&sql, [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stores_records() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store.apply_all(&records(&lines())).unwrap();
+
+        let conn = store.connection();
+        let (number, description, date): (u8, String, String) = conn
+            .query_row("SELECT number, description, init_date FROM runs", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(
+            (number, description.as_str(), date.as_str()),
+            (5, "Friday free practice", "12 jan 01")
+        );
+
+        let additional: String = conn
+            .query_row(
+                "SELECT additional_data FROM competitors WHERE registration_number = '2'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(additional, "Team");
+
+        let (laptime, time_of_day): (i64, String) = conn
+            .query_row("SELECT laptime_ms, time_of_day FROM passings", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((laptime, time_of_day.as_str()), (70_000, "14:09:52"));
+
+        let periods: Vec<(String, Option<String>)> = conn
+            .prepare("SELECT flag, end_time_of_day FROM flag_periods ORDER BY id")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            periods,
+            vec![
+                ("Green".to_owned(), Some("14:10:52".to_owned())),
+                ("Yellow".to_owned(), None)
+            ]
+        );
+
+        assert_eq!(count(&store, "classes"), 2);
+        assert_eq!(count(&store, "settings"), 1);
+        assert_eq!(count(&store, "positions"), 2);
+        assert_eq!(count(&store, "corrections"), 1);
+        assert_eq!(count(&store, "line_crossings"), 1);
+    }
+
+    #[test]
+    fn test_resumes_after_restart() {
+        let path = std::env::temp_dir().join(format!("rmonitor-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = SqliteStore::open(&path).unwrap();
+        store.apply_all(&records(&lines()[1..11])).unwrap();
+        let run_id = store.run_id();
+        drop(store);
+
+        // The session is replayed on reconnection, then continues
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.run_id(), run_id);
+        store.apply_all(&records(&lines()[1..])).unwrap();
+        assert_eq!(store.run_id(), run_id);
+
+        assert_eq!(count(&store, "runs"), 1);
+        assert_eq!(count(&store, "competitors"), 2);
+        assert_eq!(count(&store, "passings"), 1);
+        assert_eq!(count(&store, "positions"), 2);
+        assert_eq!(count(&store, "flag_periods"), 2);
+
+        // A reset begins a new run
+        store.apply_all(&records(&lines()[..2])).unwrap();
+        assert_eq!(count(&store, "runs"), 2);
+        assert_ne!(store.run_id(), run_id);
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ignores_replayed_passings_without_time() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let lines = [
+            r#"$J,"1","00:00:00.000","00:59:59.999""#,
+            r#"$COR,"1","1",0,"00:59:59.999","+00:00:00.000""#,
+        ];
+        store.apply_all(&records(&lines)).unwrap();
+        store.apply_all(&records(&lines)).unwrap();
+
+        assert_eq!(count(&store, "passings"), 1);
+        assert_eq!(count(&store, "corrections"), 1);
+    }
+
+    #[test]
+    fn test_stores_negative_corrections() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store
+            .apply_all(&records(&[
+                r#"$COR,"1","1",2,"00:02:19.500","-00:00:00.500""#,
+            ]))
+            .unwrap();
+
+        let correction: Option<i64> = store
+            .connection()
+            .query_row("SELECT correction_ms FROM corrections", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(correction, Some(-500));
+    }
+
+    #[test]
+    fn test_rolls_back_failed_batch() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store
+            .connection()
+            .execute_batch(
+                "CREATE TRIGGER fail BEFORE INSERT ON passings
+                 BEGIN SELECT RAISE(ABORT, 'fail'); END;",
+            )
+            .unwrap();
+
+        assert!(store.apply_all(&records(&lines())).is_err());
+        assert_eq!(count(&store, "competitors"), 0);
+        assert_eq!(store.run_id(), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! A Server-Sent Events endpoint streaming every decoded record.
+//!
+//! Each record is sent as an event at `/events`, with the event type set to the record kind
+//! (see [`Record::kind`]) and the data set to the record as JSON:
+//!
+//! ```text
+//! id: 42
+//! event: passing
+//! data: {"type":"passing","registration_number":"12","laptime":"00:01:12.345",...}
+//! ```
+//!
+//! Event IDs increase by one for each record. The most recent records are kept in a ring buffer,
+//! so a client reconnecting with a `Last-Event-ID` header is first sent every record it missed,
+//! provided it wasn't disconnected for longer than the buffer covers.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::sse::RecordEvents;
+//! use rmonitor::RMonitorDecoder;
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::FramedRead;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let events = RecordEvents::new();
+//!
+//!     let stream = TcpStream::connect("127.0.0.1:50000").await?;
+//!     let records = FramedRead::new(stream, RMonitorDecoder::new_with_max_length(2048));
+//!     tokio::spawn(events.clone().track(records));
+//!
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//!     axum::serve(listener, events.router()).await?;
+//!     # Ok(())
+//! }
+//! ```
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use futures::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::protocol::Record;
+use crate::sync::lock;
+
+/// Number of records kept for clients resuming with `Last-Event-ID`, by default
+const DEFAULT_CAPACITY: usize = 1024;
+
+type Entry = (u64, Arc<Record>);
+
+#[derive(Debug)]
+struct Buffer {
+    next_id: u64,
+    records: VecDeque<Entry>,
+    capacity: usize,
+}
+
+/// Streams records to Server-Sent Events clients, keeping recent records to allow clients to
+/// resume after a disconnection.
+#[derive(Clone, Debug)]
+pub struct RecordEvents {
+    buffer: Arc<Mutex<Buffer>>,
+    sender: broadcast::Sender<Entry>,
+}
+
+impl Default for RecordEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordEvents {
+    /// Returns a `RecordEvents` keeping the last 1024 records for resuming clients
+    pub fn new() -> Self {
+        Self::new_with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Returns a `RecordEvents` keeping the last `capacity` records for resuming clients
+    pub fn new_with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self {
+            buffer: Arc::new(Mutex::new(Buffer {
+                next_id: 1,
+                records: VecDeque::with_capacity(capacity),
+                capacity,
+            })),
+            sender,
+        }
+    }
+
+    /// Sends a record to every connected client, returning its event ID
+    pub fn push(&self, record: Record) -> u64 {
+        let mut buffer = lock(&self.buffer);
+        let id = buffer.next_id;
+        buffer.next_id += 1;
+
+        let entry = (id, Arc::new(record));
+        if buffer.capacity > 0 {
+            if buffer.records.len() == buffer.capacity {
+                buffer.records.pop_front();
+            }
+            buffer.records.push_back(entry.clone());
+        }
+
+        // Sent while holding the lock, so a subscribing client sees each record exactly once.
+        // An error only means there are no clients connected.
+        let _ = self.sender.send(entry);
+        id
+    }
+
+    /// Sends every record from `records`, until the stream ends or returns an error.
+    pub async fn track<S, E>(self, records: S)
+    where
+        S: Stream<Item = Result<Record, E>>,
+        E: Display,
+    {
+        futures::pin_mut!(records);
+        while let Some(record) = records.next().await {
+            match record {
+                Ok(record) => {
+                    self.push(record);
+                }
+                Err(e) => {
+                    log::warn!("Stopped streaming records: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the buffered records after `last_event_id`, and a receiver for every later record.
+    ///
+    /// Without a `last_event_id` only later records are received. If `last_event_id` is unknown,
+    /// for example after a server restart, every buffered record is returned.
+    fn subscribe(&self, last_event_id: Option<u64>) -> (Vec<Entry>, broadcast::Receiver<Entry>) {
+        let buffer = lock(&self.buffer);
+        let receiver = self.sender.subscribe();
+
+        let backlog = match last_event_id {
+            None => Vec::new(),
+            Some(last) => {
+                let last = if last >= buffer.next_id { 0 } else { last };
+                if buffer
+                    .records
+                    .front()
+                    .is_some_and(|(first, _)| *first > last + 1)
+                {
+                    log::warn!("Client resuming from event {} missed some records", last);
+                }
+                buffer
+                    .records
+                    .iter()
+                    .filter(|(id, _)| *id > last)
+                    .cloned()
+                    .collect()
+            }
+        };
+        (backlog, receiver)
+    }
+
+    /// Returns a [`Router`] serving the event stream at `/events`.
+    ///
+    /// [`Router`]: axum::Router
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/events", get(events))
+            .with_state(self.clone())
+    }
+}
+
+fn event((id, record): Entry) -> Result<Event, axum::Error> {
+    Event::default()
+        .id(id.to_string())
+        .event(record.kind())
+        .json_data(&*record)
+}
+
+async fn events(State(events): State<RecordEvents>, headers: HeaderMap) -> impl IntoResponse {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+
+    let (backlog, receiver) = events.subscribe(last_event_id);
+    let live = futures::stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(entry) => Some((entry, receiver)),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                // Ending the stream makes the client reconnect, resuming from the buffer
+                log::warn!("SSE client lagged by {} records, disconnecting", skipped);
+                None
+            }
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    });
+
+    let stream = futures::stream::iter(backlog).chain(live).map(event);
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn passing(registration_number: &str) -> Record {
+        let line = format!(
+            r#"$J,"{}","00:01:10.000","00:01:10.000""#,
+            registration_number
+        );
+        Record::decode(&line).unwrap()
+    }
+
+    #[test]
+    fn test_resumes_from_buffer() {
+        let events = RecordEvents::new_with_capacity(2);
+        assert_eq!(events.push(passing("1")), 1);
+        assert_eq!(events.push(passing("2")), 2);
+        assert_eq!(events.push(passing("3")), 3);
+
+        let ids = |backlog: Vec<Entry>| backlog.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids(events.subscribe(None).0), Vec::<u64>::new());
+        assert_eq!(ids(events.subscribe(Some(2)).0), vec![3]);
+        assert_eq!(ids(events.subscribe(Some(3)).0), Vec::<u64>::new());
+        // Record 2 has been dropped from the buffer, record 1 can't be resumed exactly
+        assert_eq!(ids(events.subscribe(Some(0)).0), vec![2, 3]);
+        // An ID from before a restart
+        assert_eq!(ids(events.subscribe(Some(99)).0), vec![2, 3]);
+
+        let (_, mut receiver) = events.subscribe(Some(3));
+        events.push(passing("4"));
+        assert_eq!(receiver.try_recv().unwrap().0, 4);
+    }
+
+    #[tokio::test]
+    async fn test_streams_events() {
+        let events = RecordEvents::new();
+        events.push(passing("1"));
+        events.push(passing("2"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = events.router();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = "GET /events HTTP/1.0\r\nHost: localhost\r\nLast-Event-ID: 1\r\n\r\n";
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        let mut buf = [0; 1024];
+        while !response.contains("id: 2\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0);
+            response.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+        assert!(response.contains("text/event-stream"));
+        assert!(!response.contains("id: 1\n"));
+        assert!(response.contains("event: passing\n"));
+        assert!(response.contains(r#""registration_number":"2""#));
+
+        events.push(Record::decode(r#"$B,5,"Race""#).unwrap());
+        while !response.contains("id: 3\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0);
+            response.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+        assert!(response.contains("event: run\n"));
+    }
+}
@@ -0,0 +1,23 @@
+//! Lock helpers shared by the modules which keep state behind a `Mutex` or `RwLock`.
+//!
+//! No lock in the crate guards state which a panic could leave worse than a missed update, so
+//! poisoning is ignored rather than passed on to every other user of the lock.
+#[cfg(any(feature = "merge", feature = "metrics", feature = "sse"))]
+use std::sync::{Mutex, MutexGuard};
+#[cfg(feature = "http")]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(any(feature = "merge", feature = "metrics", feature = "sse"))]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(feature = "http")]
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(feature = "http")]
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,144 @@
+//! Parsing and formatting of the time fields used in RMonitor records.
+//!
+//! Times are sent as strings such as `"01:42:17.672"`, `"02:03.826"` or `"35.272"`, with the
+//! sentinel value `"00:59:59.999"` used by some timing software for "no time recorded".
+use alloc::format;
+use alloc::string::String;
+use core::time::Duration;
+
+/// Sentinel time sent for competitors who have not yet recorded a passing
+pub const NO_TIME: &str = "00:59:59.999";
+
+/// Parses an RMonitor time field as a [`Duration`].
+///
+/// Accepts `HH:MM:SS.mmm`, `MM:SS.mmm` and `SS.mmm`, with or without the fractional part, and
+/// ignores a leading `+` as used in `Correction` records. Returns `None` for the [`NO_TIME`]
+/// sentinel, or if the field can't be parsed.
+///
+/// # Example
+///
+/// ```
+/// use rmonitor::time::parse_duration;
+/// use std::time::Duration;
+///
+/// assert_eq!(parse_duration("00:02:03.826"), Some(Duration::from_millis(123_826)));
+/// assert_eq!(parse_duration("00:59:59.999"), None);
+/// ```
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    if s == NO_TIME {
+        return None;
+    }
+    let s = s.strip_prefix('+').unwrap_or(s);
+
+    let (whole, fraction) = match s.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (s, ""),
+    };
+
+    let mut seconds: u64 = 0;
+    let mut parts = 0;
+    for part in whole.split(':') {
+        parts += 1;
+        if parts > 3 || part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+
+    // Normalise the fractional part to milliseconds, ignoring any extra precision
+    let mut millis: u64 = 0;
+    for (i, b) in fraction.bytes().enumerate() {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        if i < 3 {
+            millis = millis * 10 + u64::from(b - b'0');
+        }
+    }
+    for _ in fraction.len()..3 {
+        millis *= 10;
+    }
+
+    Some(Duration::from_secs(seconds) + Duration::from_millis(millis))
+}
+
+/// Returns a duration as a whole number of milliseconds, as used by the JSON and tabular exports.
+pub fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+/// Parses an RMonitor time field as a whole number of milliseconds, see [`parse_duration`].
+pub fn parse_millis(s: &str) -> Option<i64> {
+    parse_duration(s).map(|d| millis(d) as i64)
+}
+
+/// Parses a signed time field as a whole number of milliseconds, as sent in `Correction`
+/// records, e.g. `+00:00:01.234` or `-00:00:00.500`.
+///
+/// # Example
+///
+/// ```
+/// use rmonitor::time::parse_signed_millis;
+///
+/// assert_eq!(parse_signed_millis("+00:00:01.234"), Some(1_234));
+/// assert_eq!(parse_signed_millis("-00:00:00.500"), Some(-500));
+/// ```
+pub fn parse_signed_millis(s: &str) -> Option<i64> {
+    match s.trim().strip_prefix('-') {
+        Some(s) => parse_millis(s).map(|ms| -ms),
+        None => parse_millis(s),
+    }
+}
+
+/// Formats a duration for display as a lap or race time, e.g. `2:03.826` or `1:42:17.672`.
+pub fn format_duration(d: Duration) -> String {
+    let millis = d.subsec_millis();
+    let total = d.as_secs();
+    let (hours, minutes, seconds) = (total / 3600, (total / 60) % 60, total % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}.{:03}", hours, minutes, seconds, millis)
+    } else {
+        format!("{}:{:02}.{:03}", minutes, seconds, millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_durations() {
+        assert_eq!(
+            parse_duration("01:42:17.672"),
+            Some(Duration::from_millis(6_137_672))
+        );
+        assert_eq!(
+            parse_duration("02:03.826"),
+            Some(Duration::from_millis(123_826))
+        );
+        assert_eq!(parse_duration("35.2"), Some(Duration::from_millis(35_200)));
+        assert_eq!(
+            parse_duration("+00:00:00.012"),
+            Some(Duration::from_millis(12))
+        );
+        assert_eq!(
+            parse_duration("14:09:52"),
+            Some(Duration::from_secs(50_992))
+        );
+        assert_eq!(parse_duration(NO_TIME), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("1:2:3:4"), None);
+        assert_eq!(parse_duration("ab:cd"), None);
+    }
+
+    #[test]
+    fn test_formats_durations() {
+        assert_eq!(format_duration(Duration::from_millis(123_826)), "2:03.826");
+        assert_eq!(
+            format_duration(Duration::from_millis(6_137_672)),
+            "1:42:17.672"
+        );
+    }
+}
@@ -0,0 +1,508 @@
+//! A WebSocket bridge pushing the live state of a session to browsers.
+//!
+//! Each client connecting to `/ws` is sent a snapshot of the session, with the standings ordered
+//! for the inferred type of session, followed by a JSON delta for every change since as records
+//! are applied:
+//!
+//! - `{"type": "snapshot", "session": …, "standings": […], "flags": {…}}`
+//! - `{"type": "competitor_added", "competitor": {…}}` and `competitor_updated`
+//! - `{"type": "position_change", "registration_number", "number", "order", "previous", "position"}`
+//! - `{"type": "new_lap", "registration_number", "number", "lap": {…}}`
+//! - `{"type": "flag_change", "previous", "flag"}`
+//! - `{"type": "run_change", "run": {…}}`
+//! - `{"type": "warning", "kind", "registration_number", "number", "message"}`, when a
+//!   record disagrees with earlier records, see [`Inconsistency`](crate::session::Inconsistency)
+//! - `{"type": "new_best", "registration_number", "number", "scope", "sector", "lap", "time_ms",
+//!   "previous_ms", "previous_holder", "previous_holder_number", "improvement_ms", "message"}`,
+//!   for a personal, class or session best lap or sector, see
+//!   [`NewBest`](crate::session::NewBest)
+//! - `{"type": "reset"}`, after which clients should discard their state
+//!
+//! Competitors are represented as in the [HTTP API](crate::http), with durations in milliseconds.
+//!
+//! Clients may subscribe to a subset of competitors, either when connecting with
+//! `/ws?classes=1,2&numbers=12,7`, or at any time by sending
+//! `{"classes": [1, 2], "numbers": ["12", "7"]}`, which is answered with a fresh snapshot. A
+//! competitor matches the subscription if either its class or its car number is listed, and an
+//! empty subscription matches everything. Flag, run and reset deltas are always sent.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rmonitor::http::{self, SharedSession};
+//! use rmonitor::websocket::Bridge;
+//! use rmonitor::RMonitorDecoder;
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::FramedRead;
+//! # use std::error::Error;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let bridge = Bridge::new(SharedSession::default());
+//!
+//!     let stream = TcpStream::connect("127.0.0.1:50000").await?;
+//!     let records = FramedRead::new(stream, RMonitorDecoder::new_with_max_length(2048));
+//!     tokio::spawn(bridge.clone().track(records));
+//!
+//!     let app = http::router(bridge.session().clone()).merge(bridge.router());
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//!     axum::serve(listener, app).await?;
+//!     # Ok(())
+//! }
+//! ```
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::fmt::Display;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::http::SharedSession;
+use crate::json::{
+    best_json, competitor_json, flags_json, lap_json, session_json, standings_json, warning_json,
+};
+use crate::protocol::Record;
+use crate::session::{CompetitorState, Session, SessionChange, StandingsOrder};
+use crate::sync::{read, write};
+
+/// Number of deltas buffered for each client, a client falling further behind than this is sent
+/// a new snapshot instead.
+const CLIENT_BUFFER: usize = 1024;
+
+/// A single change, serialized once and shared between every client
+#[derive(Debug)]
+struct Delta {
+    /// The class and car number of the competitor concerned, for filtering
+    competitor: Option<(Option<u8>, String)>,
+    json: String,
+}
+
+/// A client's subscription, limiting deltas to competitors of the given classes or car numbers.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Subscription {
+    #[serde(default)]
+    pub classes: Vec<u8>,
+    #[serde(default)]
+    pub numbers: Vec<String>,
+}
+
+impl Subscription {
+    /// Returns `true` if the competitor is included in this subscription
+    pub fn matches(&self, class_number: Option<u8>, number: &str) -> bool {
+        (self.classes.is_empty() && self.numbers.is_empty())
+            || class_number.is_some_and(|c| self.classes.contains(&c))
+            || self.numbers.iter().any(|n| n == number)
+    }
+
+    fn matches_delta(&self, delta: &Delta) -> bool {
+        match &delta.competitor {
+            Some((class_number, number)) => self.matches(*class_number, number),
+            None => true,
+        }
+    }
+
+    fn matches_competitor(&self, c: &CompetitorState) -> bool {
+        self.matches(c.class_number, &c.number)
+    }
+}
+
+/// Comma separated query parameters, as `serde_urlencoded` doesn't support repeated keys
+#[derive(Deserialize)]
+struct SubscriptionQuery {
+    classes: Option<String>,
+    numbers: Option<String>,
+}
+
+impl From<SubscriptionQuery> for Subscription {
+    fn from(query: SubscriptionQuery) -> Self {
+        let split = |s: Option<String>| -> Vec<String> {
+            s.iter()
+                .flat_map(|s| s.split(','))
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect()
+        };
+        Self {
+            classes: split(query.classes)
+                .iter()
+                .filter_map(|c| c.parse().ok())
+                .collect(),
+            numbers: split(query.numbers),
+        }
+    }
+}
+
+/// Applies records to a shared session and pushes the resulting changes to WebSocket clients.
+#[derive(Clone, Debug)]
+pub struct Bridge {
+    session: SharedSession,
+    deltas: broadcast::Sender<Arc<Delta>>,
+}
+
+impl Bridge {
+    /// Returns a `Bridge` for the given session, which may also be served by [`http::router`].
+    ///
+    /// [`http::router`]: crate::http::router
+    pub fn new(session: SharedSession) -> Self {
+        let (deltas, _) = broadcast::channel(CLIENT_BUFFER);
+        Self { session, deltas }
+    }
+
+    /// Returns the shared session updated by this bridge
+    pub fn session(&self) -> &SharedSession {
+        &self.session
+    }
+
+    /// Applies a single record to the session, sending any resulting deltas to clients
+    pub fn apply(&self, record: &Record) {
+        // Deltas are sent under the lock, see `subscribe`
+        let mut session = write(&self.session);
+        for change in session.update(record) {
+            // An error only means there are no clients connected
+            let _ = self.deltas.send(Arc::new(delta(&session, &change)));
+        }
+    }
+
+    /// Returns a snapshot of the session and a receiver for the deltas which follow it.
+    ///
+    /// Deltas are sent while holding the session's write lock, so taking both under the read
+    /// lock means each change is either in the snapshot or received as a delta, not both.
+    fn subscribe(&self, subscription: &Subscription) -> (String, broadcast::Receiver<Arc<Delta>>) {
+        let session = read(&self.session);
+        (snapshot(&session, subscription), self.deltas.subscribe())
+    }
+
+    /// Applies every record from `records`, until the stream ends or returns an error.
+    pub async fn track<S, E>(self, records: S)
+    where
+        S: Stream<Item = Result<Record, E>>,
+        E: Display,
+    {
+        futures::pin_mut!(records);
+        while let Some(record) = records.next().await {
+            match record {
+                Ok(record) => self.apply(&record),
+                Err(e) => {
+                    log::warn!("Stopped tracking session: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns a [`Router`] accepting WebSocket clients at `/ws`.
+    ///
+    /// [`Router`]: axum::Router
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/ws", get(upgrade))
+            .with_state(self.clone())
+    }
+}
+
+fn delta(session: &Session, change: &SessionChange) -> Delta {
+    let competitor = |registration_number: &str| {
+        session
+            .competitors
+            .get(registration_number)
+            .map(|c| (c.class_number, c.number.clone()))
+    };
+
+    let (competitor, json) = match change {
+        SessionChange::CompetitorAdded {
+            registration_number,
+        }
+        | SessionChange::CompetitorUpdated {
+            registration_number,
+        } => {
+            let kind = match change {
+                SessionChange::CompetitorAdded { .. } => "competitor_added",
+                _ => "competitor_updated",
+            };
+            let json = session
+                .competitors
+                .get(registration_number)
+                .map(|c| competitor_json(session, c));
+            (
+                competitor(registration_number),
+                serde_json::json!({ "type": kind, "competitor": json }),
+            )
+        }
+        SessionChange::PositionChanged {
+            registration_number,
+            order,
+            previous,
+            position,
+        } => {
+            let competitor = competitor(registration_number);
+            let json = serde_json::json!({
+                "type": "position_change",
+                "registration_number": registration_number,
+                "number": competitor.as_ref().map(|(_, number)| number),
+                "order": match order {
+                    StandingsOrder::Race => "race",
+                    StandingsOrder::PracticeQual => "practice",
+                },
+                "previous": previous,
+                "position": position,
+            });
+            (competitor, json)
+        }
+        SessionChange::LapCompleted {
+            registration_number,
+            lap,
+        } => {
+            let competitor = competitor(registration_number);
+            let json = serde_json::json!({
+                "type": "new_lap",
+                "registration_number": registration_number,
+                "number": competitor.as_ref().map(|(_, number)| number),
+                "lap": lap_json(lap),
+            });
+            (competitor, json)
+        }
+        SessionChange::FlagChanged { previous, flag } => (
+            None,
+            serde_json::json!({ "type": "flag_change", "previous": previous, "flag": flag }),
+        ),
+        SessionChange::RunChanged => (
+            None,
+            serde_json::json!({ "type": "run_change", "run": session.run }),
+        ),
+        SessionChange::Reset => (None, serde_json::json!({ "type": "reset" })),
+        SessionChange::Inconsistency(i) => {
+            let mut json = warning_json(session, i);
+            json["type"] = "warning".into();
+            (competitor(i.registration_number()), json)
+        }
+        SessionChange::NewBest(best) => {
+            let mut json = best_json(session, best);
+            json["type"] = "new_best".into();
+            (competitor(&best.registration_number), json)
+        }
+    };
+
+    Delta {
+        competitor,
+        json: json.to_string(),
+    }
+}
+
+/// Returns the snapshot sent to a client on connection or when its subscription changes, with
+/// the standings ordered for the inferred type of session
+fn snapshot(session: &Session, subscription: &Subscription) -> String {
+    let order = session.session_type().session_type.standings_order();
+    let standings = standings_json(session, order, None, |c| subscription.matches_competitor(c));
+    serde_json::json!({
+        "type": "snapshot",
+        "session": session_json(session),
+        "standings": standings,
+        "flags": flags_json(session),
+    })
+    .to_string()
+}
+
+async fn upgrade(
+    ws: WebSocketUpgrade,
+    State(bridge): State<Bridge>,
+    Query(query): Query<SubscriptionQuery>,
+) -> Response {
+    ws.on_upgrade(move |socket| serve(socket, bridge, query.into()))
+}
+
+async fn serve(mut socket: WebSocket, bridge: Bridge, mut subscription: Subscription) {
+    loop {
+        let (snapshot, mut deltas) = bridge.subscribe(&subscription);
+        if socket.send(Message::Text(snapshot.into())).await.is_err() {
+            return;
+        }
+
+        // Until a new snapshot is needed
+        loop {
+            tokio::select! {
+                delta = deltas.recv() => match delta {
+                    Ok(delta) => {
+                        if subscription.matches_delta(&delta) {
+                            let message = Message::Text(delta.json.as_str().into());
+                            if socket.send(message).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!(
+                            "WebSocket client lagged by {} deltas, resending snapshot",
+                            skipped
+                        );
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(s) => {
+                            subscription = s;
+                            break;
+                        }
+                        Err(e) => log::debug!("Ignoring invalid subscription: {}", e),
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::SinkExt;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite;
+
+    use crate::session::fixtures::ENTRIES;
+
+    type Client = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    fn bridge() -> Bridge {
+        let bridge = Bridge::new(SharedSession::default());
+        let green = r#"$F,9999,"00:00:00","14:09:52","00:59:59","Green ""#;
+        for line in ENTRIES.iter().chain(&[green]) {
+            bridge.apply(&Record::decode(line).unwrap());
+        }
+        bridge
+    }
+
+    async fn connect(bridge: &Bridge, query: &str) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = bridge.router();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let url = format!("ws://{}/ws{}", addr, query);
+        tokio_tungstenite::connect_async(url).await.unwrap().0
+    }
+
+    async fn next(client: &mut Client) -> serde_json::Value {
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                tungstenite::Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue,
+            }
+        }
+    }
+
+    #[test]
+    fn test_subscription_matches() {
+        let all = Subscription::default();
+        assert!(all.matches(None, "12"));
+
+        let subscription = Subscription::from(SubscriptionQuery {
+            classes: Some("1, 3".to_owned()),
+            numbers: Some("12".to_owned()),
+        });
+        assert_eq!(subscription.classes, vec![1, 3]);
+        assert!(subscription.matches(Some(1), "7"));
+        assert!(subscription.matches(Some(2), "12"));
+        assert!(!subscription.matches(Some(2), "7"));
+        assert!(!subscription.matches(None, "7"));
+    }
+
+    #[tokio::test]
+    async fn test_sends_snapshot_and_deltas() {
+        let bridge = bridge();
+        let mut client = connect(&bridge, "").await;
+
+        let snapshot = next(&mut client).await;
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(
+            snapshot["session"]["run"]["description"],
+            "Friday free practice"
+        );
+        assert_eq!(snapshot["standings"].as_array().unwrap().len(), 2);
+        assert_eq!(snapshot["flags"]["current"], "Green");
+
+        bridge.apply(&Record::decode(r#"$G,1,"2",1,"00:01:10.000""#).unwrap());
+        let delta = next(&mut client).await;
+        assert_eq!(delta["type"], "position_change");
+        assert_eq!(delta["number"], "2");
+        assert_eq!(delta["position"], 1);
+        assert_eq!(delta["previous"], serde_json::Value::Null);
+
+        bridge.apply(&Record::decode(r#"$J,"2","00:01:10.000","00:01:10.000""#).unwrap());
+        let delta = next(&mut client).await;
+        assert_eq!(delta["type"], "new_lap");
+        assert_eq!(delta["lap"]["laptime_ms"], 70_000);
+        for scope in ["personal", "class", "session"] {
+            let delta = next(&mut client).await;
+            assert_eq!(delta["type"], "new_best");
+            assert_eq!(delta["scope"], scope);
+        }
+
+        bridge.apply(&Record::decode(r#"$A,"3","3",3,"Jane","Doe","",1"#).unwrap());
+        let delta = next(&mut client).await;
+        assert_eq!(delta["type"], "competitor_added");
+        assert_eq!(delta["competitor"]["class_name"], "GT");
+
+        bridge.apply(&Record::decode(r#"$F,0,"00:00:00","14:29:52","00:20:00","Finish""#).unwrap());
+        let delta = next(&mut client).await;
+        assert_eq!(delta["type"], "flag_change");
+        assert_eq!(delta["previous"], "Green");
+        assert_eq!(delta["flag"], "Finish");
+    }
+
+    #[tokio::test]
+    async fn test_orders_snapshot_for_session_type() {
+        let bridge = bridge();
+        for line in &[
+            r#"$G,1,"1",2,"00:02:30.000""#,
+            r#"$G,2,"2",2,"00:02:31.000""#,
+            r#"$H,1,"2",2,"00:01:10.000""#,
+            r#"$H,2,"1",1,"00:01:12.000""#,
+        ] {
+            bridge.apply(&Record::decode(line).unwrap());
+        }
+        let mut client = connect(&bridge, "").await;
+
+        // A practice session is ordered by fastest lap
+        let snapshot = next(&mut client).await;
+        assert_eq!(snapshot["standings"][0]["number"], "2");
+        assert_eq!(snapshot["standings"][1]["number"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_filters_deltas() {
+        let bridge = bridge();
+        let mut client = connect(&bridge, "?classes=1").await;
+
+        let snapshot = next(&mut client).await;
+        assert_eq!(snapshot["standings"].as_array().unwrap().len(), 1);
+        assert_eq!(snapshot["standings"][0]["number"], "1");
+
+        // Car 2 is in class 2, so only the change for car 1 should be sent
+        bridge.apply(&Record::decode(r#"$G,1,"2",1,"00:01:10.000""#).unwrap());
+        bridge.apply(&Record::decode(r#"$G,2,"1",0,"00:01:11.500""#).unwrap());
+        let delta = next(&mut client).await;
+        assert_eq!(delta["type"], "position_change");
+        assert_eq!(delta["number"], "1");
+
+        // Changing the subscription sends a new snapshot
+        let subscription = r#"{"numbers": ["2"]}"#;
+        client
+            .send(tungstenite::Message::Text(subscription.into()))
+            .await
+            .unwrap();
+        let snapshot = next(&mut client).await;
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["standings"].as_array().unwrap().len(), 1);
+        assert_eq!(snapshot["standings"][0]["number"], "2");
+    }
+}